@@ -0,0 +1,5 @@
+cargo build --release
+# part I: broadcasts reach every node within 300ms
+~/maelstrom/maelstrom/maelstrom test -w broadcast --bin ~/distributed-systems-challenges/efficient-broadcast/target/release/efficient-broadcast --node-count 25 --time-limit 20 --rate 100 --latency 100
+# part II: fewer messages per op, broadcasts may take up to 1.4s
+DSC_GOSSIP_LATENCY_TARGET_MS=1400 ~/maelstrom/maelstrom/maelstrom test -w broadcast --bin ~/distributed-systems-challenges/efficient-broadcast/target/release/efficient-broadcast --node-count 25 --time-limit 20 --rate 100 --latency 100
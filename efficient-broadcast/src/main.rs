@@ -0,0 +1,233 @@
+use crate::Body::{BroadcastOk, InternalMessage, ReadOk, TopologyOk};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use shared::config::Config;
+use shared::crdt::{Crdt, GSet};
+use shared::gossip::Scheduler;
+use shared::log;
+use shared::sim::simulate;
+use shared::{Message, Node, Runtime};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::warn;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Body {
+    Broadcast {
+        message: u64,
+        msg_id: u64,
+    },
+    BroadcastOk {
+        msg_id: u64,
+        in_reply_to: u64,
+    },
+    Read {
+        msg_id: u64,
+    },
+    ReadOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        messages: GSet<u64>,
+    },
+    Topology {
+        // topology: InnerTopology,
+        topology: HashMap<String, Vec<String>>,
+        msg_id: u64,
+    },
+    TopologyOk {
+        msg_id: u64,
+        in_reply_to: u64,
+    },
+    Error {
+        in_reply_to: u64,
+        code: u64,
+        text: String,
+    },
+    InternalMessage {
+        all_messages: GSet<u64>,
+    },
+}
+
+// naive solution:
+// sending all messages to other nodes in the cluster
+// in some frequent interval
+#[derive(Clone)]
+struct Handler {
+    msgs: Arc<RwLock<GSet<u64>>>,
+    // neighbours in our own topology, empty until the topology message arrives
+    adjacent: Arc<RwLock<Vec<String>>>,
+    // decides when to flush, based on the incoming load and the latency target
+    // every message has to travel 2 hops in the star topology below
+    scheduler: Arc<Mutex<Scheduler>>,
+    tick: Duration,
+}
+
+impl Handler {
+    fn new(config: Config) -> Self {
+        Handler {
+            msgs: Arc::new(RwLock::new(GSet::new())),
+            adjacent: Arc::new(RwLock::new(Vec::new())),
+            scheduler: Arc::new(Mutex::new(Scheduler::new(config.gossip.settings(2)))),
+            tick: config.gossip.tick(),
+        }
+    }
+
+    // send current node's all messages to its neighbours
+    // whenever the scheduler decides that it is time to flush (or right away if `force`d)
+    fn flush(&self, runtime: &Runtime, force: bool) -> anyhow::Result<()> {
+        let adjacent = self.adjacent.read().unwrap().clone();
+        if adjacent.is_empty() {
+            return Ok(());
+        }
+        let mut scheduler = self.scheduler.lock().unwrap();
+        let now = Instant::now();
+        if !force && !scheduler.should_flush(now) {
+            return Ok(());
+        }
+        let pending = scheduler.pending();
+        scheduler.flushed(now, pending);
+        drop(scheduler);
+
+        let all_messages = self.msgs.read().unwrap().clone();
+        for cluster_node in &adjacent {
+            if runtime.node_id() != cluster_node {
+                runtime.send(
+                    cluster_node,
+                    InternalMessage {
+                        all_messages: all_messages.clone(),
+                    },
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// the center of the star, the same on every node whatever the order of the node ids
+fn hub(runtime: &Runtime) -> &str {
+    runtime.node_ids().iter().min().map_or("", String::as_str)
+}
+
+#[async_trait]
+impl Node for Handler {
+    type Body = Body;
+
+    async fn init(&self, runtime: Runtime) -> anyhow::Result<()> {
+        // STAR TOPOLOGY
+        // the first node of the cluster is connected to all, everyone else only to it
+        let hub = hub(&runtime).to_string();
+        let adjacent_nodes: Vec<String> = if runtime.node_id() == hub {
+            runtime
+                .node_ids()
+                .iter()
+                .filter(|node| **node != hub)
+                .cloned()
+                .collect()
+        } else {
+            vec![hub]
+        };
+        *self.adjacent.write().unwrap() = adjacent_nodes;
+
+        let handler = self.clone();
+        let flush_runtime = runtime.clone();
+        runtime.every(self.tick, move || {
+            let result = handler.flush(&flush_runtime, false);
+            async move { result }
+        });
+        Ok(())
+    }
+
+    // don't take the messages received since the last flush down with us
+    async fn shutdown(&self, runtime: Runtime) -> anyhow::Result<()> {
+        if self.scheduler.lock().unwrap().pending() > 0 {
+            self.flush(&runtime, true)?;
+        }
+        Ok(())
+    }
+
+    async fn process(&self, runtime: Runtime, input: Message<Body>) -> anyhow::Result<()> {
+        match input.body {
+            Body::Read { msg_id } => {
+                let messages = self.msgs.read().unwrap().clone();
+                runtime.send(
+                    &input.src,
+                    ReadOk {
+                        msg_id,
+                        in_reply_to: msg_id,
+                        messages,
+                    },
+                )?;
+            }
+            Body::Broadcast { msg_id, message } => {
+                if self.msgs.write().unwrap().insert(message) {
+                    self.scheduler.lock().unwrap().record(1);
+                }
+                runtime.send(
+                    &input.src,
+                    BroadcastOk {
+                        msg_id,
+                        in_reply_to: msg_id,
+                    },
+                )?;
+            }
+            Body::Topology { msg_id, .. } => {
+                // the star below is built from the cluster membership instead
+                // TODO: try using the given topology and compare the results
+                runtime.send(
+                    &input.src,
+                    TopologyOk {
+                        msg_id,
+                        in_reply_to: msg_id,
+                    },
+                )?;
+            }
+            Body::InternalMessage { all_messages, .. } => {
+                let mut my_msgs = self.msgs.write().unwrap();
+                let before = my_msgs.len();
+                my_msgs.merge(&all_messages);
+                let added = my_msgs.len() - before;
+                drop(my_msgs);
+                // only the center of the star has to pass these messages on
+                if runtime.node_id() == hub(&runtime) {
+                    self.scheduler.lock().unwrap().record(added);
+                }
+            }
+            ReadOk { .. } | BroadcastOk { .. } | TopologyOk { .. } => {
+                warn!("impossible input");
+            }
+            Body::Error { text, .. } => {
+                warn!("{}", text);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let mut defaults = Config::default();
+    // part I asks for 300ms. part II allows 1.4s for fewer messages per op, run it with
+    // `DSC_GOSSIP_LATENCY_TARGET_MS=1400` (see run-command.sh)
+    defaults.gossip.latency_target_ms = 300;
+    let config = Config::load(defaults)?;
+    log::init(&config.log);
+    if config.sim.enabled() {
+        return simulate(config.sim.clone(), move |_| Handler::new(config.clone())).await;
+    }
+
+    let runtime = Runtime::new()
+        .with_trace(config.trace.dir.clone())
+        .with_metrics(config.metrics.clone());
+    runtime.run(Handler::new(config)).await
+}
+// Solution description:
+// batch process to send current node's all messages to its neighbours in the star topology
+// as soon as new messages arrive (within the latency target), and every second otherwise
+// this ensures we are sending all the messages from given node to every other node in the cluster
+// even in the case of network partitions, eventual consistency will be observed
+// because even if some of the internal messages are not received on the other end,
+// they will be in consensus after the network partition is restored and all the messages are sent again
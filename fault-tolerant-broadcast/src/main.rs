@@ -1,32 +1,22 @@
-use crate::Body::{BroadcastOk, InitOk, InternalMessage, ReadOk, TopologyOk};
+use crate::Body::{BroadcastOk, InternalMessage, ReadOk, TopologyOk};
+use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
-use std::io::{BufRead, Write};
-use std::sync::{Arc, Mutex};
+use shared::config::Config;
+use shared::crdt::{Crdt, GSet};
+use shared::gossip::Scheduler;
+use shared::log;
+use shared::sim::simulate;
+use shared::{Message, Node, Runtime};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::Duration;
-use std::{io, thread};
-
-// generic type for json received for all problems
-// use this as a template
-#[derive(Serialize, Deserialize, Debug)]
-struct Message {
-    src: String,
-    dest: String,
-    body: Body,
-}
+use tokio::time::Instant;
+use tracing::warn;
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
 enum Body {
-    Init {
-        msg_id: u64,
-        node_id: String,
-        node_ids: Vec<String>,
-    },
-    InitOk {
-        in_reply_to: u64,
-    },
     Broadcast {
         message: u64,
         msg_id: u64,
@@ -41,7 +31,7 @@ enum Body {
     ReadOk {
         msg_id: u64,
         in_reply_to: u64,
-        messages: HashSet<u64>,
+        messages: GSet<u64>,
     },
     Topology {
         // topology: InnerTopology,
@@ -58,154 +48,147 @@ enum Body {
         text: String,
     },
     InternalMessage {
-        all_messages: HashSet<u64>,
+        all_messages: GSet<u64>,
     },
 }
 
-fn main() -> anyhow::Result<()> {
-    let stdin = io::stdin();
-    let mut stdout = io::stdout();
-
-    let mut print_and_flush = |output: Message| -> anyhow::Result<()> {
-        let serialized_output = serde_json::to_string(&output)?;
-        writeln!(stdout, "{}", serialized_output)?;
-        stdout.flush()?;
-        Ok(())
-    };
+// naive solution:
+// sending all messages to other nodes in the cluster
+// in some frequent interval
+#[derive(Clone)]
+struct Handler {
+    msgs: Arc<RwLock<GSet<u64>>>,
+    // decides when to flush, based on the incoming load and the latency target
+    // the idle flushes are what heals the cluster after a network partition
+    scheduler: Arc<Mutex<Scheduler>>,
+    tick: Duration,
+}
 
-    // naive solution:
-    // sending all messages to other nodes in the cluster
-    // in some frequent interval
+impl Handler {
+    fn new(config: Config) -> Self {
+        Handler {
+            msgs: Arc::new(RwLock::new(GSet::new())),
+            scheduler: Arc::new(Mutex::new(Scheduler::new(config.gossip.settings(1)))),
+            tick: config.gossip.tick(),
+        }
+    }
 
-    let msgs = Arc::new(Mutex::new(HashSet::new()));
-    let msgs_secondary = Arc::clone(&msgs);
-    let mut this_node_id = String::new();
-    let mut cluster_nodes = Vec::<String>::new();
+    // send current node's all messages to everyone in the cluster
+    // whenever the scheduler decides that it is time to flush (or right away if `force`d)
+    fn flush(&self, runtime: &Runtime, force: bool) -> anyhow::Result<()> {
+        let mut scheduler = self.scheduler.lock().unwrap();
+        let now = Instant::now();
+        if !force && !scheduler.should_flush(now) {
+            return Ok(());
+        }
+        let pending = scheduler.pending();
+        scheduler.flushed(now, pending);
+        drop(scheduler);
 
-    // process init message
-    let mut line = String::new();
-    stdin.read_line(&mut line)?;
-    let info: Message = serde_json::from_str(&line)?;
-    match info.body {
-        Body::Init {
-            node_id,
-            node_ids,
-            msg_id,
-        } => {
-            this_node_id = node_id;
-            cluster_nodes = node_ids;
-            let output: Message = Message {
-                src: info.dest,
-                dest: info.src,
-                body: InitOk {
-                    in_reply_to: msg_id,
+        let all_messages = self.msgs.read().unwrap().clone();
+        for cluster_node in runtime.other_node_ids() {
+            runtime.send(
+                cluster_node,
+                InternalMessage {
+                    all_messages: all_messages.clone(),
                 },
-            };
-            print_and_flush(output)?;
-        }
-        _ => {
-            panic!("expected init message at first");
+            )?;
         }
+        Ok(())
     }
-    // process init message
+}
 
-    #[allow(unreachable_code)]
-    let handler = std::thread::spawn(move || -> anyhow::Result<()> {
-        // batch thread to send current node's all messages to everyone in the cluster
-        // every 500 ms
-        loop {
-            for cluster_node in &cluster_nodes {
-                if this_node_id != *cluster_node {
-                    let my_msgs = msgs_secondary.lock().unwrap();
-                    let internal_msg: Message = Message {
-                        src: this_node_id.clone(),
-                        dest: (*cluster_node).clone(),
-                        body: InternalMessage {
-                            all_messages: my_msgs.clone(),
-                        },
-                    };
-                    drop(my_msgs);
+#[async_trait]
+impl Node for Handler {
+    type Body = Body;
 
-                    let serialized_output = serde_json::to_string(&internal_msg)?;
-                    println!("{}", serialized_output);
-                }
-            }
-            thread::sleep(Duration::from_millis(800));
+    async fn init(&self, runtime: Runtime) -> anyhow::Result<()> {
+        let handler = self.clone();
+        let flush_runtime = runtime.clone();
+        runtime.every(self.tick, move || {
+            let result = handler.flush(&flush_runtime, false);
+            async move { result }
+        });
+        Ok(())
+    }
+
+    // don't take the messages received since the last flush down with us
+    async fn shutdown(&self, runtime: Runtime) -> anyhow::Result<()> {
+        if self.scheduler.lock().unwrap().pending() > 0 {
+            self.flush(&runtime, true)?;
         }
         Ok(())
-    });
+    }
 
-    for line in stdin.lock().lines() {
-        let input: Message = serde_json::from_str(&line?)?;
+    async fn process(&self, runtime: Runtime, input: Message<Body>) -> anyhow::Result<()> {
         match input.body {
-            Body::Init { .. } => {
-                // already process this message
-                // will never receive this message again
-                // let output: Message = Message {
-                //     src: input.dest,
-                //     dest: input.src,
-                //     body: InitOk {
-                //         in_reply_to: msg_id,
-                //     },
-                // };
-                // this_node_id = node_id;
-                // cluster_nodes = node_ids;
-                // print_and_flush(output)?;
-                panic!("init message sent twice");
-            }
             Body::Read { msg_id } => {
-                let output: Message = Message {
-                    src: input.dest,
-                    dest: input.src,
-                    body: ReadOk {
+                let messages = self.msgs.read().unwrap().clone();
+                runtime.send(
+                    &input.src,
+                    ReadOk {
                         msg_id,
                         in_reply_to: msg_id,
-                        messages: msgs.lock().unwrap().clone(),
+                        messages,
                     },
-                };
-                print_and_flush(output)?;
+                )?;
             }
             Body::Broadcast { msg_id, message } => {
-                msgs.lock().unwrap().insert(message);
-                let output: Message = Message {
-                    src: input.dest,
-                    dest: input.src,
-                    body: BroadcastOk {
+                // messages received from other nodes are not recorded,
+                // every node gossips its full set to everyone else anyway
+                if self.msgs.write().unwrap().insert(message) {
+                    self.scheduler.lock().unwrap().record(1);
+                }
+                runtime.send(
+                    &input.src,
+                    BroadcastOk {
                         msg_id,
                         in_reply_to: msg_id,
                     },
-                };
-                print_and_flush(output)?;
+                )?;
             }
             Body::Topology { msg_id, .. } => {
-                let output: Message = Message {
-                    src: input.dest,
-                    dest: input.src,
-                    body: TopologyOk {
+                runtime.send(
+                    &input.src,
+                    TopologyOk {
                         msg_id,
                         in_reply_to: msg_id,
                     },
-                };
-                print_and_flush(output)?;
+                )?;
             }
             Body::InternalMessage { all_messages, .. } => {
-                let mut my_msgs = msgs.lock().unwrap();
-                my_msgs.extend(all_messages);
+                self.msgs.write().unwrap().merge(&all_messages);
             }
-            InitOk { .. } | ReadOk { .. } | BroadcastOk { .. } | TopologyOk { .. } => {
-                eprintln!("Impossible input");
+            ReadOk { .. } | BroadcastOk { .. } | TopologyOk { .. } => {
+                warn!("impossible input");
             }
             Body::Error { text, .. } => {
-                eprintln!("{}", text);
+                warn!("{}", text);
             }
         }
+        Ok(())
     }
-    let _ = handler.join().unwrap();
-    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let mut defaults = Config::default();
+    defaults.gossip.latency_target_ms = 800;
+    defaults.gossip.idle_interval_ms = 800;
+    let config = Config::load(defaults)?;
+    log::init(&config.log);
+    if config.sim.enabled() {
+        return simulate(config.sim.clone(), move |_| Handler::new(config.clone())).await;
+    }
+
+    let runtime = Runtime::new()
+        .with_trace(config.trace.dir.clone())
+        .with_metrics(config.metrics.clone());
+    runtime.run(Handler::new(config)).await
 }
 // Solution description:
 // batch process to send current node's all messages to every other node in the cluster
-// every 500 ms
+// as soon as new messages arrive (within the latency target), and every 800 ms otherwise
 // this ensures we are sending all the messages from given node to every other node in the cluster
 // even in the case of network partitions, eventual consistency will be observed
 // because even if some of the internal messages are not received on the other end,
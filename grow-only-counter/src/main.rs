@@ -1,31 +1,21 @@
-use crate::Body::{AddOk, InitOk, InternalMessage, ReadOk};
+use crate::Body::{AddOk, InternalMessage, ReadOk};
+use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
+use shared::config::{Config, CounterMode};
+use shared::crdt::{Crdt, GCounter};
+use shared::kv;
+use shared::log;
+use shared::sim::simulate;
+use shared::{Message, Node, Runtime};
 use std::collections::HashMap;
-use std::io::{BufRead, Write};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use std::{io, thread};
-
-#[derive(Serialize, Deserialize, Debug, Eq, Hash, PartialEq)]
-struct Message {
-    src: String,
-    dest: String,
-    body: Body,
-}
+use tracing::warn;
 
 #[derive(Serialize, Deserialize, Debug, Eq, Hash, PartialEq)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
 enum Body {
-    Init {
-        msg_id: u64,
-        node_id: String,
-        // this node's id
-        node_ids: Vec<String>, // all nodes in the current cluster
-    },
-    InitOk {
-        in_reply_to: u64,
-    },
     Read {
         msg_id: u64,
     },
@@ -48,137 +38,199 @@ enum Body {
         text: String,
     },
     InternalMessage {
-        msg_id: u64,
-        latest_value: u64,
+        // increases with every snapshot the sender gossips
+        version: u64,
+        counter: GCounter,
     },
 }
 
-fn main() -> anyhow::Result<()> {
-    let stdin = io::stdin();
-    let mut stdout = io::stdout();
+#[derive(Clone)]
+struct Handler {
+    // every node only increments its own entry, the value is the sum of all entries
+    counter: Arc<Mutex<GCounter>>,
+    // version of the next snapshot we gossip
+    version: Arc<Mutex<u64>>,
+    // latest version applied from every other node, older (reordered) gossip is dropped
+    peer_versions: Arc<Mutex<HashMap<String, u64>>>,
+    // last value returned to every client, so a client never reads a smaller value
+    // than before (e.g. after a stale seq-kv read or a reordered reply)
+    sessions: Arc<Mutex<HashMap<String, u64>>>,
+    mode: CounterMode,
+    tick: Duration,
+}
 
-    let mut print_and_flush = move |output: Message| -> anyhow::Result<()> {
-        let serialized_output = serde_json::to_string(&output)?;
-        writeln!(stdout, "{}", serialized_output)?;
-        stdout.flush()?;
-        Ok(())
-    };
+// key of the shared counter in seq-kv mode
+const COUNTER_KEY: &str = "counter";
 
-    let this_node;
-    let cluster_nodes;
-    let this_node_clone;
-    let cluster_nodes_clone;
+impl Handler {
+    fn new(config: Config) -> Self {
+        Handler {
+            counter: Arc::new(Mutex::new(GCounter::new())),
+            version: Arc::new(Mutex::new(0)),
+            peer_versions: Arc::new(Mutex::new(HashMap::new())),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+            mode: config.counter.mode,
+            tick: config.counter.tick(),
+        }
+    }
 
-    // process init message - start
-    let mut line = String::new();
-    stdin.read_line(&mut line)?;
-    let info: Message = serde_json::from_str(&line)?;
-    match info.body {
-        Body::Init {
-            node_id,
-            node_ids,
-            msg_id,
-        } => {
-            this_node = node_id;
-            this_node_clone = this_node.clone();
-            cluster_nodes = node_ids;
-            cluster_nodes_clone = cluster_nodes.clone();
-            let output: Message = Message {
-                src: info.dest,
-                dest: info.src,
-                body: InitOk {
-                    in_reply_to: msg_id,
+    // send this node's counter state to every other node in the cluster
+    fn gossip(&self, runtime: &Runtime) -> anyhow::Result<()> {
+        // snapshot and version are taken together, so a newer version never carries older state
+        let (version, counter) = {
+            let counter = self.counter.lock().unwrap();
+            let mut version = self.version.lock().unwrap();
+            *version += 1;
+            (*version, counter.clone())
+        };
+        for cluster_node in runtime.other_node_ids() {
+            runtime.send(
+                cluster_node,
+                InternalMessage {
+                    version,
+                    counter: counter.clone(),
                 },
-            };
-            print_and_flush(output)?;
+            )?;
         }
-        _ => {
-            panic!("expected init message at first");
+        Ok(())
+    }
+
+    // ignores the snapshot if we already applied the same or a newer one from `src`
+    fn apply(&self, src: String, version: u64, counter: &GCounter) {
+        let mut peer_versions = self.peer_versions.lock().unwrap();
+        let seen = peer_versions.entry(src).or_insert(0);
+        if version <= *seen {
+            return;
         }
+        *seen = version;
+        self.counter.lock().unwrap().merge(counter);
     }
-    // process init message - end
 
-    let my_value = Arc::new(Mutex::new(0u64));
-    let my_value_secondary = Arc::clone(&my_value);
-    let mut latest_values_for = HashMap::<String, u64>::new();
+    // never return less to `client` than what it has already seen
+    fn monotonic(&self, client: &str, value: u64) -> u64 {
+        let mut sessions = self.sessions.lock().unwrap();
+        let last = sessions.entry(client.to_string()).or_insert(0);
+        *last = (*last).max(value);
+        *last
+    }
 
-    #[allow(unreachable_code)]
-    let handler = std::thread::spawn(move || -> anyhow::Result<()> {
-        let mut my_msg_id = 0;
-        loop {
-            thread::sleep(Duration::from_millis(20));
-            for cluster_node in &cluster_nodes_clone {
-                if *cluster_node != this_node_clone {
-                    let msg: Message = Message {
-                        src: this_node_clone.clone(),
-                        dest: cluster_node.clone(),
-                        body: InternalMessage {
-                            msg_id: my_msg_id,
-                            latest_value: *my_value_secondary.lock().unwrap(),
-                        },
-                    };
-                    my_msg_id += 1;
-                    let serialized_msg = serde_json::to_string(&msg)?;
-                    println!("{}", serialized_msg);
-                }
+    async fn read(&self, runtime: &Runtime) -> anyhow::Result<u64> {
+        match self.mode {
+            CounterMode::Gossip => Ok(self.counter.lock().unwrap().value()),
+            CounterMode::SeqKv => {
+                let storage = kv::seq_kv(runtime.clone());
+                // seq-kv may serve us an old snapshot, writing a value nobody wrote before
+                // moves this node's view past it, so the read below sees every finished add
+                let sync_key = format!("sync_{}", runtime.node_id());
+                storage.write(&sync_key, runtime.next_msg_id()).await?;
+                Ok(storage.read(COUNTER_KEY).await?.unwrap_or(0))
             }
         }
-        Ok(())
-    });
+    }
 
-    for line in stdin.lock().lines() {
-        let input: Message = serde_json::from_str(&line?)?;
-        match input.body {
-            Body::Init { .. } => {
-                panic!("already done");
+    async fn add(&self, runtime: &Runtime, delta: u64) -> anyhow::Result<()> {
+        match self.mode {
+            CounterMode::Gossip => {
+                self.counter
+                    .lock()
+                    .unwrap()
+                    .increment(runtime.node_id(), delta);
             }
-            Body::Read { msg_id } => {
-                let mut sum_of_all = *my_value.lock().unwrap();
-                for cluster_node in &cluster_nodes {
-                    if *cluster_node != this_node {
-                        if let Some(value) = latest_values_for.get(cluster_node) {
-                            sum_of_all += value;
-                        }
+            CounterMode::SeqKv => {
+                if delta == 0 {
+                    return Ok(());
+                }
+                let storage = kv::seq_kv(runtime.clone());
+                // retry until nobody else updated the counter between our read and cas
+                loop {
+                    let current: u64 = storage.read(COUNTER_KEY).await?.unwrap_or(0);
+                    if storage
+                        .cas(COUNTER_KEY, current, current + delta, true)
+                        .await?
+                    {
+                        break;
                     }
                 }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Node for Handler {
+    type Body = Body;
+
+    async fn init(&self, runtime: Runtime) -> anyhow::Result<()> {
+        if self.mode == CounterMode::SeqKv {
+            return Ok(());
+        }
+        let handler = self.clone();
+        let gossip_runtime = runtime.clone();
+        runtime.every(self.tick, move || {
+            let result = handler.gossip(&gossip_runtime);
+            async move { result }
+        });
+        Ok(())
+    }
 
-                let output: Message = Message {
-                    src: input.dest,
-                    dest: input.src,
-                    body: ReadOk {
+    // one last round, so the peers see the adds since the previous tick
+    async fn shutdown(&self, runtime: Runtime) -> anyhow::Result<()> {
+        match self.mode {
+            CounterMode::Gossip => self.gossip(&runtime),
+            CounterMode::SeqKv => Ok(()),
+        }
+    }
+
+    async fn process(&self, runtime: Runtime, input: Message<Body>) -> anyhow::Result<()> {
+        match input.body {
+            Body::Read { msg_id } => {
+                let sum_of_all = self.read(&runtime).await?;
+                let sum_of_all = self.monotonic(&input.src, sum_of_all);
+                runtime.send(
+                    &input.src,
+                    ReadOk {
                         msg_id,
                         in_reply_to: msg_id,
                         value: sum_of_all,
                     },
-                };
-                print_and_flush(output)?;
+                )?;
             }
             Body::Add { msg_id, delta, .. } => {
-                *my_value.lock().unwrap() += delta;
-                let output: Message = Message {
-                    src: input.dest,
-                    dest: input.src,
-                    body: AddOk {
+                self.add(&runtime, delta).await?;
+                runtime.send(
+                    &input.src,
+                    AddOk {
                         msg_id,
                         in_reply_to: msg_id,
                     },
-                };
-                print_and_flush(output)?;
+                )?;
             }
-            InitOk { .. } | ReadOk { .. } | AddOk { .. } => {
-                eprintln!("{}", "Impossible input");
+            ReadOk { .. } | AddOk { .. } => {
+                warn!("impossible input");
             }
             Body::Error { text, .. } => {
-                eprintln!("{}", text);
+                warn!("{}", text);
             }
-            Body::InternalMessage {
-                msg_id: _,
-                latest_value,
-            } => {
-                *latest_values_for.entry(input.src).or_insert(0) = latest_value;
+            Body::InternalMessage { version, counter } => {
+                // merging alone would already keep the highest value seen per node,
+                // the version check just skips the work for duplicated or reordered gossip
+                self.apply(input.src, version, &counter);
             }
         }
+        Ok(())
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let config = Config::load(Config::default())?;
+    log::init(&config.log);
+    if config.sim.enabled() {
+        return simulate(config.sim.clone(), move |_| Handler::new(config.clone())).await;
     }
-    let _ = handler.join().expect("Failed to join thread");
-    Ok(())
+    let runtime = Runtime::new()
+        .with_trace(config.trace.dir.clone())
+        .with_metrics(config.metrics.clone());
+    runtime.run(Handler::new(config)).await
 }
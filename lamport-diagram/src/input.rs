@@ -0,0 +1,226 @@
+use anyhow::{anyhow, Context};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+// a message between two processes, times in milliseconds since the first event
+#[derive(Debug, Clone)]
+pub struct Arrow {
+    pub src: String,
+    pub dest: String,
+    pub sent: f64,
+    // None when the message never arrived
+    pub received: Option<f64>,
+    pub body: Value,
+}
+
+// the messages of a simulator history (`--sim-history`) or of the traces of some
+// nodes (`--trace-dir`), told apart line by line so both can be mixed
+pub fn parse(files: &[(String, String)]) -> anyhow::Result<Vec<Arrow>> {
+    let mut history = Vec::new();
+    let mut traces = Vec::new();
+    for (path, contents) in files {
+        for (number, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record: Value = serde_json::from_str(line)
+                .with_context(|| format!("{}:{} is not json", path, number + 1))?;
+            if record.get("dir").is_some() {
+                traces.push(record);
+            } else if record.get("sent").is_some() {
+                history.push(record);
+            } else {
+                return Err(anyhow!(
+                    "{}:{} is neither history nor trace",
+                    path,
+                    number + 1
+                ));
+            }
+        }
+    }
+    let mut arrows = from_history(history);
+    arrows.extend(from_traces(traces));
+    let start = arrows
+        .iter()
+        .map(|arrow| arrow.sent)
+        .fold(f64::INFINITY, f64::min);
+    for arrow in &mut arrows {
+        arrow.sent -= start;
+        arrow.received = arrow.received.map(|received| received - start);
+    }
+    arrows.sort_by(|a, b| {
+        a.sent
+            .total_cmp(&b.sent)
+            .then_with(|| (&a.src, &a.dest).cmp(&(&b.src, &b.dest)))
+    });
+    Ok(arrows)
+}
+
+fn text(value: &Value, field: &str) -> String {
+    value[field].as_str().unwrap_or_default().to_string()
+}
+
+fn from_history(records: Vec<Value>) -> Vec<Arrow> {
+    records
+        .into_iter()
+        .map(|record| Arrow {
+            src: text(&record, "src"),
+            dest: text(&record, "dest"),
+            sent: record["sent"].as_f64().unwrap_or_default(),
+            received: record["received"].as_f64(),
+            body: record["body"].clone(),
+        })
+        .collect()
+}
+
+// every node traces what it sent and what it received, so a message shows up twice
+// when both ends were traced. the ends are matched by their (identical) message.
+// messages from or to a process without a trace (clients, kv services) are drawn as
+// taking no time, a message a traced node sent but its traced receiver never read
+// was lost
+fn from_traces(records: Vec<Value>) -> Vec<Arrow> {
+    let traced: HashSet<String> = records
+        .iter()
+        .filter(|record| record["dir"] == "out")
+        .map(|record| text(&record["msg"], "src"))
+        .collect();
+    let mut received: HashMap<String, VecDeque<f64>> = HashMap::new();
+    for record in records.iter().filter(|record| record["dir"] == "in") {
+        let time = record["time"].as_f64().unwrap_or_default();
+        received
+            .entry(record["msg"].to_string())
+            .or_default()
+            .push_back(time);
+    }
+
+    let mut arrows = Vec::new();
+    for record in records.iter().filter(|record| record["dir"] == "out") {
+        let msg = &record["msg"];
+        let sent = record["time"].as_f64().unwrap_or_default();
+        let dest = text(msg, "dest");
+        let arrival = received
+            .get_mut(&msg.to_string())
+            .and_then(VecDeque::pop_front);
+        let received = match arrival {
+            Some(time) => Some(time),
+            None if traced.contains(&dest) => None,
+            None => Some(sent),
+        };
+        arrows.push(Arrow {
+            src: text(msg, "src"),
+            dest,
+            sent,
+            received,
+            body: msg["body"].clone(),
+        });
+    }
+    // what's left came from processes without a trace
+    for (msg, times) in received {
+        let Ok(msg) = serde_json::from_str::<Value>(&msg) else {
+            continue;
+        };
+        for time in times {
+            arrows.push(Arrow {
+                src: text(&msg, "src"),
+                dest: text(&msg, "dest"),
+                sent: time,
+                received: Some(time),
+                body: msg["body"].clone(),
+            });
+        }
+    }
+    arrows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn file(records: &[Value]) -> (String, String) {
+        let lines: Vec<String> = records.iter().map(Value::to_string).collect();
+        ("run.jsonl".to_string(), lines.join("\n"))
+    }
+
+    fn ends(arrows: &[Arrow]) -> Vec<(&str, &str, f64, Option<f64>)> {
+        arrows
+            .iter()
+            .map(|arrow| {
+                (
+                    arrow.src.as_str(),
+                    arrow.dest.as_str(),
+                    arrow.sent,
+                    arrow.received,
+                )
+            })
+            .collect()
+    }
+
+    fn msg(src: &str, dest: &str, msg_id: u64) -> Value {
+        json!({"src": src, "dest": dest, "body": {"type": "echo", "msg_id": msg_id}})
+    }
+
+    #[test]
+    fn history() {
+        let records = [
+            json!({"src": "n1", "dest": "n2", "sent": 15.0, "body": {}}),
+            json!({"src": "c1", "dest": "n1", "sent": 10.0, "received": 12.5, "body": {"type": "echo"}}),
+        ];
+        let arrows = parse(&[file(&records)]).unwrap();
+        // sorted by send time, from the first one
+        assert_eq!(
+            ends(&arrows),
+            [("c1", "n1", 0.0, Some(2.5)), ("n1", "n2", 5.0, None)]
+        );
+        assert_eq!(arrows[0].body["type"], "echo");
+    }
+
+    #[test]
+    fn traces() {
+        let n1 = [
+            json!({"dir": "in", "time": 1.0, "msg": msg("c1", "n1", 1)}),
+            json!({"dir": "out", "time": 2.0, "msg": msg("n1", "n2", 2)}),
+            json!({"dir": "out", "time": 3.0, "msg": msg("n1", "n2", 3)}),
+            json!({"dir": "out", "time": 4.0, "msg": msg("n1", "lin-kv", 4)}),
+        ];
+        let n2 = [
+            json!({"dir": "in", "time": 6.0, "msg": msg("n1", "n2", 2)}),
+            json!({"dir": "out", "time": 7.0, "msg": msg("n2", "c1", 5)}),
+        ];
+        let arrows = parse(&[file(&n1), file(&n2)]).unwrap();
+        assert_eq!(
+            ends(&arrows),
+            [
+                // from a client without a trace, drawn as taking no time
+                ("c1", "n1", 0.0, Some(0.0)),
+                // both ends traced
+                ("n1", "n2", 1.0, Some(5.0)),
+                // n2 never read it
+                ("n1", "n2", 2.0, None),
+                // to a service without a trace
+                ("n1", "lin-kv", 3.0, Some(3.0)),
+                ("n2", "c1", 6.0, Some(6.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn history_and_traces_mix() {
+        let records = [
+            json!({"src": "c1", "dest": "n1", "sent": 0.0, "received": 1.0, "body": {}}),
+            json!({"dir": "out", "time": 2.0, "msg": msg("n1", "c1", 1)}),
+        ];
+        assert_eq!(parse(&[file(&records)]).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn bad_lines() {
+        let bad = ("run.jsonl".to_string(), "\n{\"src\": \"n1\"".to_string());
+        let err = parse(&[bad]).unwrap_err();
+        assert_eq!(err.to_string(), "run.jsonl:2 is not json");
+
+        let neither = file(&[json!({"src": "n1", "dest": "n2"})]);
+        let err = parse(&[neither]).unwrap_err();
+        assert_eq!(err.to_string(), "run.jsonl:1 is neither history nor trace");
+    }
+}
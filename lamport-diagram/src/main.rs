@@ -0,0 +1,76 @@
+use anyhow::{anyhow, Context};
+
+mod input;
+mod svg;
+
+// draws the messages of a run as a space-time (lamport) diagram
+//
+// usage: lamport-diagram [--html] [--out <file>] [--from <ms>] [--to <ms>] <file>...
+//
+// the files are simulator histories (`--sim-history`) or node traces (`--trace-dir`,
+// pass all of a run's traces to connect the ends of each message). every process
+// gets a lane, clients first, and every message an arrow labelled with its type:
+// blue between clients and nodes, grey between nodes, yellow to the kv services and
+// red for errors. hovering an arrow shows the whole body. --from and --to keep the
+// messages sent in that window, in milliseconds since the first one. the svg goes to
+// stdout unless --out is given; --html wraps it in a page with toggles per colour
+
+const USAGE: &str =
+    "usage: lamport-diagram [--html] [--out <file>] [--from <ms>] [--to <ms>] <file>...";
+
+fn main() -> anyhow::Result<()> {
+    let mut html = false;
+    let mut out = None;
+    let mut from = f64::NEG_INFINITY;
+    let mut to = f64::INFINITY;
+    let mut paths = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| anyhow!(USAGE))
+                .and_then(|value| value.parse::<f64>().context(name.to_string()))
+        };
+        match arg.as_str() {
+            "--html" => html = true,
+            "--out" => out = Some(args.next().ok_or_else(|| anyhow!(USAGE))?),
+            "--from" => from = value("--from")?,
+            "--to" => to = value("--to")?,
+            _ if !arg.starts_with("--") => paths.push(arg),
+            _ => return Err(anyhow!(USAGE)),
+        }
+    }
+    if paths.is_empty() {
+        return Err(anyhow!(USAGE));
+    }
+
+    let mut files = Vec::new();
+    for path in paths {
+        let contents =
+            std::fs::read_to_string(&path).with_context(|| format!("failed to read {}", path))?;
+        files.push((path, contents));
+    }
+    let mut arrows = input::parse(&files)?;
+    arrows.retain(|arrow| arrow.sent >= from && arrow.sent <= to);
+    // the window starts at the top of the diagram
+    if let Some(start) = arrows.first().map(|arrow| arrow.sent) {
+        for arrow in &mut arrows {
+            arrow.sent -= start;
+            arrow.received = arrow.received.map(|received| received - start);
+        }
+    }
+
+    let rendered = if html {
+        svg::page(&arrows)
+    } else {
+        svg::render(&arrows)
+    };
+    match out {
+        Some(out) => {
+            std::fs::write(&out, rendered).with_context(|| format!("failed to write {}", out))?;
+            eprintln!("drew {} messages to {}", arrows.len(), out);
+        }
+        None => print!("{}", rendered),
+    }
+    Ok(())
+}
@@ -0,0 +1,253 @@
+use crate::input::Arrow;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+// space-time diagram: one vertical line per process, time flowing down, and an
+// arrow per message from its sender at the send time to its receiver at the
+// receive time. lost messages stop halfway with a cross
+
+const LANE_WIDTH: f64 = 140.0;
+const MARGIN: f64 = 60.0;
+// height the diagram aims for, unless that squeezes messages closer than this
+const TARGET_HEIGHT: f64 = 3000.0;
+const MAX_PX_PER_MS: f64 = 20.0;
+
+// what a message is, which decides its colour
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    // between a client and a node
+    Client,
+    // between two nodes: gossip, consensus, forwarding
+    Internal,
+    // to or from a kv service
+    Service,
+    // an error reply, whoever sent it
+    Error,
+}
+
+impl Kind {
+    fn of(arrow: &Arrow) -> Kind {
+        let is_node = |id: &str| id.starts_with('n');
+        let is_client = |id: &str| id.starts_with('c');
+        if arrow.body["type"] == "error" {
+            Kind::Error
+        } else if is_node(&arrow.src) && is_node(&arrow.dest) {
+            Kind::Internal
+        } else if is_client(&arrow.src) || is_client(&arrow.dest) {
+            Kind::Client
+        } else {
+            Kind::Service
+        }
+    }
+
+    fn class(self) -> &'static str {
+        match self {
+            Kind::Client => "client",
+            Kind::Internal => "internal",
+            Kind::Service => "service",
+            Kind::Error => "error",
+        }
+    }
+}
+
+const STYLE: &str = "
+  text { font: 11px monospace; }
+  .lane { stroke: #bbb; }
+  .client { stroke: #1f6feb; fill: #1f6feb; }
+  .internal { stroke: #8b949e; fill: #8b949e; }
+  .service { stroke: #d29922; fill: #d29922; }
+  .error { stroke: #da3633; fill: #da3633; }
+  .lost { stroke-dasharray: 4 3; }
+  line.arrow { stroke-width: 1.2; marker-end: url(#head); }
+  text.label { stroke: none; font-size: 9px; }
+";
+
+// clients first, then the nodes, then everything else (the kv services)
+fn lanes(arrows: &[Arrow]) -> Vec<String> {
+    let ids: BTreeSet<&str> = arrows
+        .iter()
+        .flat_map(|arrow| [arrow.src.as_str(), arrow.dest.as_str()])
+        .collect();
+    let mut lanes: Vec<String> = ids.into_iter().map(str::to_string).collect();
+    let rank = |id: &str| match id.chars().next() {
+        Some('c') => 0,
+        Some('n') => 1,
+        _ => 2,
+    };
+    // c2 before c10
+    let number = |id: &str| {
+        id.get(1..)
+            .and_then(|number| number.parse::<u64>().ok())
+            .unwrap_or(u64::MAX)
+    };
+    lanes.sort_by(|a, b| (rank(a), number(a), a.as_str()).cmp(&(rank(b), number(b), b.as_str())));
+    lanes
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+pub fn render(arrows: &[Arrow]) -> String {
+    let lanes = lanes(arrows);
+    let x = |id: &str| {
+        let lane = lanes.iter().position(|lane| lane == id).unwrap_or(0);
+        MARGIN + lane as f64 * LANE_WIDTH
+    };
+    let end = arrows
+        .iter()
+        .map(|arrow| arrow.received.unwrap_or(arrow.sent))
+        .fold(0.0, f64::max);
+    let scale = (TARGET_HEIGHT / end.max(1.0)).min(MAX_PX_PER_MS);
+    let y = |time: f64| MARGIN + time * scale;
+    let width = MARGIN * 2.0 + (lanes.len().max(1) - 1) as f64 * LANE_WIDTH;
+    let height = y(end) + MARGIN;
+
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{:.0}" height="{:.0}">"#,
+        width, height
+    );
+    let _ = writeln!(svg, "<style>{}</style>", STYLE);
+    let _ = writeln!(
+        svg,
+        r#"<defs><marker id="head" viewBox="0 0 10 10" refX="10" refY="5" markerWidth="6" markerHeight="6" orient="auto-start-reverse"><path d="M 0 0 L 10 5 L 0 10 z" fill="context-stroke"/></marker></defs>"#
+    );
+    for lane in &lanes {
+        let _ = writeln!(
+            svg,
+            r#"<text x="{:.1}" y="{:.1}" text-anchor="middle">{}</text><line class="lane" x1="{:.1}" y1="{:.1}" x2="{:.1}" y2="{:.1}"/>"#,
+            x(lane),
+            MARGIN - 20.0,
+            escape(lane),
+            x(lane),
+            MARGIN - 10.0,
+            x(lane),
+            height - MARGIN / 2.0
+        );
+    }
+    // a time mark every 10% of the run
+    for step in 0..=10 {
+        let time = end * step as f64 / 10.0;
+        let _ = writeln!(
+            svg,
+            r#"<text x="4" y="{:.1}">{:.0}ms</text>"#,
+            y(time),
+            time
+        );
+    }
+
+    for arrow in arrows {
+        let kind = Kind::of(arrow);
+        let (x1, y1) = (x(&arrow.src), y(arrow.sent));
+        let lost = arrow.received.is_none();
+        let (x2, y2) = match arrow.received {
+            Some(received) => (x(&arrow.dest), y(received)),
+            // halfway to the receiver, 5ms after it was sent
+            None => ((x1 + x(&arrow.dest)) / 2.0, y(arrow.sent + 5.0)),
+        };
+        let kind_type = arrow.body["type"].as_str().unwrap_or("?");
+        let class = if lost {
+            format!("{} lost", kind.class())
+        } else {
+            kind.class().to_string()
+        };
+        let _ = write!(
+            svg,
+            r#"<g class="{}"><title>{} → {} at {:.3}ms: {}</title><line class="arrow" x1="{:.1}" y1="{:.1}" x2="{:.1}" y2="{:.1}"/>"#,
+            class,
+            escape(&arrow.src),
+            escape(&arrow.dest),
+            arrow.sent,
+            escape(&arrow.body.to_string()),
+            x1,
+            y1,
+            x2,
+            y2
+        );
+        if lost {
+            let _ = write!(
+                svg,
+                r#"<path d="M {:.1} {:.1} l 6 6 m -6 0 l 6 -6" transform="translate(-3 -3)"/>"#,
+                x2, y2
+            );
+        }
+        let _ = writeln!(
+            svg,
+            r#"<text class="label" x="{:.1}" y="{:.1}" text-anchor="middle">{}</text></g>"#,
+            (x1 + x2) / 2.0,
+            (y1 + y2) / 2.0 - 2.0,
+            escape(kind_type)
+        );
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+// the svg with a legend and checkboxes hiding each kind of message
+pub fn page(arrows: &[Arrow]) -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>lamport diagram</title>\n\
+         <style>body { font: 13px sans-serif; } label { margin-right: 1em; }\n",
+    );
+    let kinds = [Kind::Client, Kind::Internal, Kind::Service, Kind::Error];
+    for kind in kinds {
+        let _ = writeln!(
+            html,
+            "body.hide-{0} g.{0} {{ display: none; }}",
+            kind.class()
+        );
+    }
+    html.push_str("</style></head><body>\n<p>");
+    for kind in kinds {
+        let count = arrows
+            .iter()
+            .filter(|arrow| Kind::of(arrow) == kind)
+            .count();
+        let _ = writeln!(
+            html,
+            r#"<label class="{0}"><input type="checkbox" checked onchange="document.body.classList.toggle('hide-{0}', !this.checked)"> {0} ({1})</label>"#,
+            kind.class(),
+            count
+        );
+    }
+    html.push_str("</p>\n");
+    html.push_str(&render(arrows));
+    html.push_str("</body></html>\n");
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn arrow(src: &str, dest: &str) -> Arrow {
+        Arrow {
+            src: src.to_string(),
+            dest: dest.to_string(),
+            sent: 0.0,
+            received: Some(1.0),
+            body: json!({"type": "echo"}),
+        }
+    }
+
+    #[test]
+    fn lane_order() {
+        let arrows = [
+            arrow("c10", "n2"),
+            arrow("n10", "lin-kv"),
+            arrow("c2", "n1"),
+            arrow("éa", ""),
+            arrow("n1", "cx"),
+        ];
+        assert_eq!(
+            lanes(&arrows),
+            ["c2", "c10", "cx", "n1", "n2", "n10", "", "lin-kv", "éa"]
+        );
+    }
+}
@@ -0,0 +1,3 @@
+cargo build --release
+
+~/maelstrom/maelstrom/maelstrom test -w lin-kv --bin ~/distributed-systems-challenges/lin-kv/target/release/lin-kv --node-count 3 --concurrency 2n --rate 100 --time-limit 20 --nemesis partition
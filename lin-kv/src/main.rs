@@ -0,0 +1,230 @@
+use async_trait::async_trait;
+use linearizability_checker::model::Model;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use shared::config::Config;
+use shared::consensus::{Replica, Role, Rpc, StateMachine};
+use shared::error::{code, RpcError};
+use shared::log;
+use shared::sim::simulate_checked;
+use shared::{Message, Node, Runtime};
+use std::collections::BTreeMap;
+
+// maelstrom's lin-kv workload on top of our own raft (or multi-paxos, see `[consensus]`)
+//
+// every request (reads included) goes through the replicated log, so it takes effect
+// at the moment its entry is applied, somewhere between the request and the reply.
+// followers forward requests to the leader they know of and relay its reply
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+enum Body {
+    Consensus(Rpc<Command, Snapshot>),
+    Client(Request),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Request {
+    Read {
+        msg_id: u64,
+        key: Value,
+    },
+    Write {
+        msg_id: u64,
+        key: Value,
+        value: Value,
+    },
+    Cas {
+        msg_id: u64,
+        key: Value,
+        from: Value,
+        to: Value,
+        #[serde(default)]
+        create_if_not_exists: bool,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+#[allow(clippy::enum_variant_names)]
+enum Response {
+    ReadOk { value: Value, in_reply_to: u64 },
+    WriteOk { in_reply_to: u64 },
+    CasOk { in_reply_to: u64 },
+}
+
+// a request as it is stored in the log
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "op")]
+#[serde(rename_all = "snake_case")]
+enum Command {
+    Read {
+        key: Value,
+    },
+    Write {
+        key: Value,
+        value: Value,
+    },
+    Cas {
+        key: Value,
+        from: Value,
+        to: Value,
+        create_if_not_exists: bool,
+    },
+}
+
+// keys are any json value, so they are stored by their json text
+type Snapshot = BTreeMap<String, Value>;
+
+#[derive(Default)]
+struct Store {
+    data: Snapshot,
+}
+
+impl StateMachine for Store {
+    type Command = Command;
+    // the value for a read
+    type Output = Result<Option<Value>, RpcError>;
+    type Snapshot = Snapshot;
+
+    fn apply(&mut self, command: &Command) -> Self::Output {
+        match command {
+            Command::Read { key } => match self.data.get(&key.to_string()) {
+                Some(value) => Ok(Some(value.clone())),
+                None => Err(not_found(key)),
+            },
+            Command::Write { key, value } => {
+                self.data.insert(key.to_string(), value.clone());
+                Ok(None)
+            }
+            Command::Cas {
+                key,
+                from,
+                to,
+                create_if_not_exists,
+            } => match self.data.get_mut(&key.to_string()) {
+                Some(current) if current == from => {
+                    *current = to.clone();
+                    Ok(None)
+                }
+                Some(current) => {
+                    let text = format!("{} is {}, not {}", key, current, from);
+                    Err(RpcError::new(code::PRECONDITION_FAILED, text))
+                }
+                None if *create_if_not_exists => {
+                    self.data.insert(key.to_string(), to.clone());
+                    Ok(None)
+                }
+                None => Err(not_found(key)),
+            },
+        }
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        self.data.clone()
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        self.data = snapshot;
+    }
+}
+
+fn not_found(key: &Value) -> RpcError {
+    RpcError::new(code::KEY_DOES_NOT_EXIST, format!("{} does not exist", key))
+}
+
+impl Request {
+    fn msg_id(&self) -> u64 {
+        match self {
+            Request::Read { msg_id, .. }
+            | Request::Write { msg_id, .. }
+            | Request::Cas { msg_id, .. } => *msg_id,
+        }
+    }
+
+    fn command(self) -> Command {
+        match self {
+            Request::Read { key, .. } => Command::Read { key },
+            Request::Write { key, value, .. } => Command::Write { key, value },
+            Request::Cas {
+                key,
+                from,
+                to,
+                create_if_not_exists,
+                ..
+            } => Command::Cas {
+                key,
+                from,
+                to,
+                create_if_not_exists,
+            },
+        }
+    }
+}
+
+struct Handler {
+    replica: Replica<Store>,
+}
+
+#[async_trait]
+impl Node for Handler {
+    type Body = Body;
+
+    async fn init(&self, _runtime: Runtime) -> anyhow::Result<()> {
+        self.replica.start();
+        Ok(())
+    }
+
+    async fn process(&self, runtime: Runtime, req: Message<Body>) -> anyhow::Result<()> {
+        let request = match req.body {
+            Body::Consensus(rpc) => return self.replica.handle(&req.src, rpc),
+            Body::Client(request) => request,
+        };
+        let in_reply_to = request.msg_id();
+        if self.replica.role() != Role::Leader {
+            return self
+                .replica
+                .forward(&runtime, &req.src, in_reply_to, &request)
+                .await;
+        }
+
+        let output = self.replica.propose(request.clone().command()).await??;
+        let response = match request {
+            Request::Read { .. } => Response::ReadOk {
+                value: output.unwrap_or_default(),
+                in_reply_to,
+            },
+            Request::Write { .. } => Response::WriteOk { in_reply_to },
+            Request::Cas { .. } => Response::CasOk { in_reply_to },
+        };
+        runtime.send(&req.src, response)
+    }
+}
+
+fn handler(runtime: Runtime, config: &Config) -> Handler {
+    let replica = Replica::new(
+        config.consensus.algorithm,
+        runtime,
+        config.raft.clone(),
+        Store::default(),
+    );
+    Handler { replica }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let config = Config::load(Config::default())?;
+    log::init(&config.log);
+    if config.sim.enabled() {
+        let sim = config.sim.clone();
+        let check = |ops: &[Value]| linearizability_checker::check_ops(Model::CasRegister, ops);
+        return simulate_checked(sim, move |runtime| handler(runtime, &config), check).await;
+    }
+    let runtime = Runtime::new()
+        .with_trace(config.trace.dir.clone())
+        .with_metrics(config.metrics.clone());
+    runtime.clone().run(handler(runtime, &config)).await
+}
@@ -0,0 +1,389 @@
+use anyhow::{anyhow, Context};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+// values of a history, the subset of edn / json maelstrom workloads use
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Val {
+    Nil,
+    Int(i64),
+    Str(String),
+    List(Vec<Val>),
+}
+
+impl fmt::Display for Val {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Val::Nil => write!(f, "nil"),
+            Val::Int(int) => write!(f, "{}", int),
+            Val::Str(string) => write!(f, "{:?}", string),
+            Val::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Input {
+    Read,
+    Write(Val),
+    Cas(Val, Val),
+    // a kafka send, the output is the offset it got
+    Append,
+}
+
+// a single operation of a client, from its invocation to its completion
+#[derive(Debug, Clone)]
+pub struct Op {
+    pub process: String,
+    pub input: Input,
+    // what a read returned / the offset of an append
+    pub output: Val,
+    // line numbers of the invocation and the completion,
+    // no completion if the outcome is unknown (timeout, crash)
+    pub call: usize,
+    pub ret: Option<usize>,
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "process {} ", self.process)?;
+        match &self.input {
+            Input::Read => write!(f, "read {}", self.output)?,
+            Input::Write(value) => write!(f, "write {}", value)?,
+            Input::Cas(from, to) => write!(f, "cas {} -> {}", from, to)?,
+            Input::Append => write!(f, "append at {}", self.output)?,
+        }
+        match self.ret {
+            Some(ret) => write!(f, " (lines {}-{})", self.call, ret),
+            None => write!(f, " (line {}, never completed)", self.call),
+        }
+    }
+}
+
+// the operations of every key, keys are checked on their own
+pub type History = BTreeMap<Val, Vec<Op>>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Invoke,
+    Ok,
+    Fail,
+    Info,
+}
+
+// one line of a history file
+struct Line {
+    process: String,
+    kind: Kind,
+    f: String,
+    key: Val,
+    value: Val,
+}
+
+// maelstrom's history.txt, one operation per line, in the order they happened:
+//   <process> <:invoke|:ok|:fail|:info> <:read|:write|:cas> [<key> <value>] [<error>]
+// a cas value is [<from> <to>]. failed and indeterminate operations carry the error
+// after the value, only the first value of the line is parsed
+pub fn parse_maelstrom(contents: &str) -> anyhow::Result<History> {
+    let mut lines = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let (process, rest) = field(line);
+        let (kind, rest) = field(rest);
+        let (f, value) = field(rest);
+        if process.parse::<u64>().is_err() || value.trim().is_empty() {
+            // nemesis operations, empty lines, ...
+            continue;
+        }
+        let line_no = index + 1;
+        let kind = parse_kind(kind.trim_start_matches(':'))
+            .with_context(|| format!("on line {}", line_no))?;
+        let (key, value) =
+            match parse_edn(value).with_context(|| format!("bad value on line {}", line_no))? {
+                Val::List(pair) if pair.len() == 2 => (pair[0].clone(), pair[1].clone()),
+                value => (Val::Nil, value),
+            };
+        lines.push((
+            line_no,
+            Line {
+                process: process.to_string(),
+                kind,
+                f: f.trim_start_matches(':').to_string(),
+                key,
+                value,
+            },
+        ));
+    }
+    collect(lines)
+}
+
+// the first whitespace separated field of `line` and the rest of it
+fn field(line: &str) -> (&str, &str) {
+    let line = line.trim_start();
+    line.split_once(char::is_whitespace).unwrap_or((line, ""))
+}
+
+// one json object per line:
+//   {"process": 0, "type": "invoke", "f": "cas", "key": 1, "value": [2, 3]}
+// `key` is optional, appends have `"f": "append"` and the offset as the value
+pub fn parse_json(contents: &str) -> anyhow::Result<History> {
+    let mut events = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let line_no = index + 1;
+        let json: Value =
+            serde_json::from_str(line).with_context(|| format!("bad json on line {}", line_no))?;
+        events.push((line_no, json));
+    }
+    parse_events(events)
+}
+
+// the same objects as `parse_json`, already parsed, with their line numbers
+pub fn parse_events(events: impl IntoIterator<Item = (usize, Value)>) -> anyhow::Result<History> {
+    let mut lines = Vec::new();
+    for (line_no, json) in events {
+        let field = |name: &str| {
+            json.get(name)
+                .ok_or_else(|| anyhow!("no {} on line {}", name, line_no))
+        };
+        let process = match field("process")? {
+            Value::String(process) => process.clone(),
+            process => process.to_string(),
+        };
+        let kind = field("type")?.as_str().unwrap_or_default();
+        let f = field("f")?.as_str().unwrap_or_default();
+        lines.push((
+            line_no,
+            Line {
+                process,
+                kind: parse_kind(kind).with_context(|| format!("on line {}", line_no))?,
+                f: f.to_string(),
+                key: json.get("key").map_or(Ok(Val::Nil), from_json)?,
+                value: json.get("value").map_or(Ok(Val::Nil), from_json)?,
+            },
+        ));
+    }
+    collect(lines)
+}
+
+fn parse_kind(kind: &str) -> anyhow::Result<Kind> {
+    match kind {
+        "invoke" => Ok(Kind::Invoke),
+        "ok" => Ok(Kind::Ok),
+        "fail" => Ok(Kind::Fail),
+        "info" => Ok(Kind::Info),
+        _ => Err(anyhow!("unknown op type {}", kind)),
+    }
+}
+
+// pair up invocations and completions by process
+//
+// failed operations definitely didn't happen and are dropped, so are reads and
+// appends we never got a result for, they can't tell us anything
+fn collect(lines: Vec<(usize, Line)>) -> anyhow::Result<History> {
+    let mut history = History::new();
+    let mut open: HashMap<String, (usize, Line)> = HashMap::new();
+    let mut pending = Vec::new();
+
+    for (line_no, line) in lines {
+        if line.kind == Kind::Invoke {
+            if let Some((call, invoke)) = open.insert(line.process.clone(), (line_no, line)) {
+                // the process moved on, the previous operation never completed
+                pending.push((call, invoke));
+            }
+            continue;
+        }
+        let (call, invoke) = open
+            .remove(&line.process)
+            .ok_or_else(|| anyhow!("completion without invoke on line {}", line_no))?;
+        if invoke.f != line.f {
+            return Err(anyhow!(
+                "{} completes a {} on line {}",
+                line.f,
+                invoke.f,
+                line_no
+            ));
+        }
+        match line.kind {
+            Kind::Ok => {
+                let input = input(&invoke)?;
+                let op = Op {
+                    process: invoke.process,
+                    input,
+                    output: line.value,
+                    call,
+                    ret: Some(line_no),
+                };
+                history.entry(invoke.key).or_default().push(op);
+            }
+            Kind::Info => pending.push((call, invoke)),
+            Kind::Fail | Kind::Invoke => {}
+        }
+    }
+
+    pending.extend(open.into_values());
+    for (call, invoke) in pending {
+        let input = input(&invoke)?;
+        if matches!(input, Input::Read | Input::Append) {
+            continue;
+        }
+        let op = Op {
+            process: invoke.process,
+            input,
+            output: Val::Nil,
+            call,
+            ret: None,
+        };
+        history.entry(invoke.key).or_default().push(op);
+    }
+    Ok(history)
+}
+
+fn input(invoke: &Line) -> anyhow::Result<Input> {
+    match (invoke.f.as_str(), &invoke.value) {
+        ("read", _) => Ok(Input::Read),
+        ("write", value) => Ok(Input::Write(value.clone())),
+        ("cas", Val::List(pair)) if pair.len() == 2 => {
+            Ok(Input::Cas(pair[0].clone(), pair[1].clone()))
+        }
+        ("append", _) => Ok(Input::Append),
+        (f, value) => Err(anyhow!("unsupported operation {} {}", f, value)),
+    }
+}
+
+fn from_json(json: &Value) -> anyhow::Result<Val> {
+    match json {
+        Value::Null => Ok(Val::Nil),
+        Value::Number(number) => number
+            .as_i64()
+            .map(Val::Int)
+            .ok_or_else(|| anyhow!("only integers are supported, got {}", number)),
+        Value::String(string) => Ok(Val::Str(string.clone())),
+        Value::Array(items) => Ok(Val::List(
+            items.iter().map(from_json).collect::<anyhow::Result<_>>()?,
+        )),
+        Value::Bool(_) | Value::Object(_) => Err(anyhow!("unsupported value {}", json)),
+    }
+}
+
+// the first edn value of `text`, whatever follows it is ignored
+fn parse_edn(text: &str) -> anyhow::Result<Val> {
+    edn_value(&mut text.chars().peekable())
+}
+
+fn edn_value(chars: &mut Peekable<Chars>) -> anyhow::Result<Val> {
+    while chars.next_if(|c| c.is_whitespace() || *c == ',').is_some() {}
+    match chars.next().ok_or_else(|| anyhow!("value ends early"))? {
+        '[' => {
+            let mut items = Vec::new();
+            loop {
+                while chars.next_if(|c| c.is_whitespace() || *c == ',').is_some() {}
+                if chars.next_if_eq(&']').is_some() {
+                    return Ok(Val::List(items));
+                }
+                items.push(edn_value(chars)?);
+            }
+        }
+        '"' => {
+            let mut string = String::new();
+            loop {
+                match chars.next().ok_or_else(|| anyhow!("unterminated string"))? {
+                    '"' => return Ok(Val::Str(string)),
+                    '\\' => match chars.next().ok_or_else(|| anyhow!("unterminated string"))? {
+                        'n' => string.push('\n'),
+                        't' => string.push('\t'),
+                        c => string.push(c),
+                    },
+                    c => string.push(c),
+                }
+            }
+        }
+        ']' => Err(anyhow!("unexpected ]")),
+        c => {
+            let mut token = c.to_string();
+            while let Some(c) =
+                chars.next_if(|c| !c.is_whitespace() && !matches!(c, ',' | '[' | ']' | '"'))
+            {
+                token.push(c);
+            }
+            match token.as_str() {
+                "nil" => Ok(Val::Nil),
+                _ => Ok(token.parse().map_or(Val::Str(token), Val::Int)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edn_values() {
+        let value = parse_edn(r#"[1 [nil "a b"], -2 "say \"hi\"" :ok]"#).unwrap();
+        let expected = Val::List(vec![
+            Val::Int(1),
+            Val::List(vec![Val::Nil, Val::Str("a b".into())]),
+            Val::Int(-2),
+            Val::Str("say \"hi\"".into()),
+            Val::Str(":ok".into()),
+        ]);
+        assert_eq!(value, expected);
+        // only the first value counts
+        assert_eq!(parse_edn("3 {:code 22}").unwrap(), Val::Int(3));
+        assert!(parse_edn("[1 2").is_err());
+        assert!(parse_edn("\"a b").is_err());
+        assert!(parse_edn("]").is_err());
+    }
+
+    #[test]
+    fn maelstrom_history_with_errors() {
+        let contents = [
+            "0\t:invoke\t:write\t[0 \"a b\"]",
+            "1\t:invoke\t:cas\t[0 [\"a b\" 3]]",
+            "2\t:invoke\t:read\t[0 nil]",
+            ":nemesis\t:info\t:start-partition\t:majority",
+            "0\t:ok\t:write\t[0 \"a b\"]",
+            "1\t:fail\t:cas\t[0 [\"a b\" 3]]\t[:precondition-failed \"expected a b, had c\"]",
+            "2\t:info\t:read\t[0 nil]\t:net-timeout",
+            "3\t:invoke\t:write\t[1 4]",
+            "3\t:info\t:write\t[1 4]\t[:crash \"node n1 crashed\"]",
+            "",
+        ]
+        .join("\n");
+        let history = parse_maelstrom(&contents).unwrap();
+
+        // the failed cas and the read without result are dropped
+        let ops = &history[&Val::Int(0)];
+        assert_eq!(ops.len(), 1);
+        assert_eq!(ops[0].input, Input::Write(Val::Str("a b".into())));
+        assert_eq!((ops[0].call, ops[0].ret), (1, Some(5)));
+
+        // the crashed write may or may not have happened
+        let ops = &history[&Val::Int(1)];
+        assert_eq!(ops.len(), 1);
+        assert_eq!(ops[0].input, Input::Write(Val::Int(4)));
+        assert_eq!((ops[0].call, ops[0].ret), (8, None));
+    }
+
+    #[test]
+    fn maelstrom_history_errors() {
+        let err = parse_maelstrom("0 :ok :write [0 1]").unwrap_err();
+        assert!(err.to_string().contains("line 1"), "{}", err);
+        assert!(parse_maelstrom("0 :invoke :write [0 1").is_err());
+        assert!(parse_maelstrom("0 :done :write [0 1]").is_err());
+    }
+}
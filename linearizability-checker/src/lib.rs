@@ -0,0 +1,54 @@
+use anyhow::anyhow;
+use history::{History, Val};
+use model::Model;
+use serde_json::Value;
+use std::fmt::Write;
+
+pub mod history;
+pub mod model;
+pub mod wgl;
+
+// every key of `history` which can't be linearized, each with the shortest prefix of
+// its history which already can't be and how far that prefix gets
+pub fn check(model: Model, history: &History) -> Vec<String> {
+    history
+        .iter()
+        .filter(|(_, ops)| wgl::check(model, ops).is_err())
+        .map(|(key, ops)| report(model, key, ops))
+        .collect()
+}
+
+// check the operations of a simulated run (see `shared::sim`), the ones `model` doesn't
+// know (like kafka polls) are left out
+pub fn check_ops(model: Model, ops: &[Value]) -> anyhow::Result<()> {
+    let events = ops
+        .iter()
+        .enumerate()
+        .filter(|(_, op)| op["f"].as_str().is_some_and(|f| model.checks(f)))
+        .map(|(index, op)| (index + 1, op.clone()));
+    let history = history::parse_events(events)?;
+    let reports = check(model, &history);
+    if reports.is_empty() {
+        return Ok(());
+    }
+    Err(anyhow!("not linearizable\n{}", reports.join("\n")))
+}
+
+fn report(model: Model, key: &Val, ops: &[history::Op]) -> String {
+    let ops = wgl::minimize(model, ops);
+    let failure = wgl::check(model, &ops).expect_err("the prefix can't be linearized");
+    let mut report = format!("key {} is not linearizable, shortest failing history:", key);
+    for op in &ops {
+        let _ = write!(report, "\n  {}", op);
+    }
+    report.push_str("\nlongest linearization:");
+    for op in &failure.linearized {
+        let _ = write!(report, "\n  {}", ops[*op]);
+    }
+    let _ = write!(
+        report,
+        "\nwhich leaves {}, but then {} can't take effect",
+        failure.state, ops[failure.blocked]
+    );
+    report
+}
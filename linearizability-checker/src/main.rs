@@ -0,0 +1,70 @@
+use anyhow::{anyhow, Context};
+use linearizability_checker::history::{self, History};
+use linearizability_checker::model::Model;
+use std::process::ExitCode;
+
+// checks that a history of a lin-kv like service is linearizable
+//
+// usage: linearizability-checker [--model register|cas-register|append] [--json] <history>
+//
+// the history is maelstrom's history.txt by default, or one json object per
+// operation event with --json (see history.rs). every key is an independent object
+// and checked on its own against the model, cas-register by default. `append` checks
+// offsets handed out by a log, like the sends of the kafka workloads
+//
+// a key that can't be linearized is reported with the shortest prefix of its
+// history which already can't be
+
+const USAGE: &str =
+    "usage: linearizability-checker [--model register|cas-register|append] [--json] <history>";
+
+fn main() -> anyhow::Result<ExitCode> {
+    let mut model = Model::CasRegister;
+    let mut json = false;
+    let mut path = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--model" => {
+                let name = args.next().ok_or_else(|| anyhow!(USAGE))?;
+                model = Model::parse(&name).ok_or_else(|| anyhow!("unknown model {}", name))?;
+            }
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg),
+            _ => return Err(anyhow!(USAGE)),
+        }
+    }
+    let path = path.ok_or_else(|| anyhow!(USAGE))?;
+    let contents =
+        std::fs::read_to_string(&path).with_context(|| format!("failed to read {}", path))?;
+    let history: History = if json {
+        history::parse_json(&contents)?
+    } else {
+        history::parse_maelstrom(&contents)?
+    };
+    if let Some(op) = history
+        .values()
+        .flatten()
+        .find(|op| !model.supports(&op.input))
+    {
+        return Err(anyhow!("{:?} model can't check {}", model, op));
+    }
+
+    let reports = linearizability_checker::check(model, &history);
+    for report in &reports {
+        println!("{}", report);
+    }
+    let errors = reports.len();
+
+    println!(
+        "checked {} operations on {} keys: {}",
+        history.values().map(Vec::len).sum::<usize>(),
+        history.len(),
+        if errors == 0 { "valid" } else { "INVALID" }
+    );
+    Ok(if errors == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
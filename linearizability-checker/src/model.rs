@@ -0,0 +1,69 @@
+use crate::history::{Input, Op, Val};
+
+// the sequential specification a history is checked against
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Model {
+    // reads and writes
+    Register,
+    // reads, writes and compare-and-set, a cas that failed its precondition is a
+    // failed operation and never part of the history
+    CasRegister,
+    // a log handing out offsets: every append gets a larger offset than the appends
+    // linearized before it. offsets may have gaps, they only have to be unique and
+    // increasing in real-time order
+    Append,
+}
+
+impl Model {
+    pub fn parse(name: &str) -> Option<Model> {
+        match name {
+            "register" => Some(Model::Register),
+            "cas-register" => Some(Model::CasRegister),
+            "append" => Some(Model::Append),
+            _ => None,
+        }
+    }
+
+    // the value of a key before any operation, the register value / the lowest offset
+    pub fn init(&self) -> Val {
+        match self {
+            Model::Register | Model::CasRegister => Val::Nil,
+            Model::Append => Val::Int(0),
+        }
+    }
+
+    // whether the operations called `f` are checked against this model
+    pub fn checks(&self, f: &str) -> bool {
+        matches!(
+            (self, f),
+            (Model::Register, "read" | "write")
+                | (Model::CasRegister, "read" | "write" | "cas")
+                | (Model::Append, "append")
+        )
+    }
+
+    pub fn supports(&self, input: &Input) -> bool {
+        matches!(
+            (self, input),
+            (Model::Register, Input::Read | Input::Write(_))
+                | (
+                    Model::CasRegister,
+                    Input::Read | Input::Write(_) | Input::Cas(..)
+                )
+                | (Model::Append, Input::Append)
+        )
+    }
+
+    // the state after `op` took effect in `state`, `None` if `op` can't happen there
+    pub fn step(&self, state: &Val, op: &Op) -> Option<Val> {
+        match &op.input {
+            Input::Read => (op.output == *state).then(|| state.clone()),
+            Input::Write(value) => Some(value.clone()),
+            Input::Cas(from, to) => (from == state).then(|| to.clone()),
+            Input::Append => match (state, &op.output) {
+                (Val::Int(next), Val::Int(offset)) if offset >= next => Some(Val::Int(offset + 1)),
+                _ => None,
+            },
+        }
+    }
+}
@@ -0,0 +1,273 @@
+use crate::history::{Input, Op, Val};
+use crate::model::Model;
+use std::collections::HashSet;
+
+// the wing & gong / lowe search for a linearization, as done by porcupine
+//
+// the history is a list of call and return events ordered by time. the search walks
+// it from the front: a call can be linearized if the model accepts the operation in
+// the current state, its call and return are then lifted out of the list and the walk
+// restarts. reaching a return means that operation had to take effect already, so the
+// last choice is undone and the walk continues after it. (linearized set, state) pairs
+// which were explored once are cached, which keeps the search from revisiting the same
+// dead ends through different orders of commuting operations
+//
+// operations which never completed have their return at the very end, so they may
+// take effect anywhere after their call or not at all
+
+struct Event {
+    op: usize,
+    call: bool,
+}
+
+// why a history can't be linearized
+#[derive(Debug)]
+pub struct Failure {
+    // the longest order of operations the search found, as indices into the history
+    pub linearized: Vec<usize>,
+    // the state after them
+    pub state: Val,
+    // the operation which completed without any way to take effect before
+    pub blocked: usize,
+}
+
+pub fn check(model: Model, ops: &[Op]) -> Result<(), Failure> {
+    let mut order: Vec<(usize, usize, bool)> = Vec::with_capacity(ops.len() * 2);
+    for (i, op) in ops.iter().enumerate() {
+        order.push((op.call, i, true));
+        order.push((op.ret.unwrap_or(usize::MAX), i, false));
+    }
+    order.sort();
+
+    // a doubly linked list of the events which are not lifted yet, 0 is the head
+    let mut events = vec![Event { op: 0, call: false }];
+    let mut returns = vec![0; ops.len()];
+    for (_, op, call) in order {
+        if !call {
+            returns[op] = events.len();
+        }
+        events.push(Event { op, call });
+    }
+    let mut next: Vec<Option<usize>> = (1..=events.len())
+        .map(|i| (i < events.len()).then_some(i))
+        .collect();
+    let mut prev: Vec<usize> = (0..events.len()).map(|i| i.saturating_sub(1)).collect();
+
+    let unlink = |next: &mut Vec<Option<usize>>, prev: &mut Vec<usize>, e: usize| {
+        next[prev[e]] = next[e];
+        if let Some(n) = next[e] {
+            prev[n] = prev[e];
+        }
+    };
+    // only valid in the reverse order of the unlinks
+    let relink = |next: &mut Vec<Option<usize>>, prev: &mut Vec<usize>, e: usize| {
+        next[prev[e]] = Some(e);
+        if let Some(n) = next[e] {
+            prev[n] = e;
+        }
+    };
+
+    let mut linearized = vec![0u64; ops.len().div_ceil(64)];
+    let mut cache: HashSet<(Vec<u64>, Val)> = HashSet::new();
+    // the calls linearized so far and the state before each of them
+    let mut stack: Vec<(usize, Val)> = Vec::new();
+    let mut state = model.init();
+    let mut deepest: Option<Failure> = None;
+    let mut entry = next[0];
+
+    while let Some(e) = entry {
+        let op = events[e].op;
+        if events[e].call {
+            if let Some(after) = model.step(&state, &ops[op]) {
+                linearized[op / 64] |= 1 << (op % 64);
+                if cache.insert((linearized.clone(), after.clone())) {
+                    stack.push((e, std::mem::replace(&mut state, after)));
+                    unlink(&mut next, &mut prev, e);
+                    unlink(&mut next, &mut prev, returns[op]);
+                    entry = next[0];
+                    continue;
+                }
+                linearized[op / 64] &= !(1 << (op % 64));
+            }
+            entry = next[e];
+            continue;
+        }
+
+        if ops[op].ret.is_none() {
+            // every completed operation is linearized, the rest may not have happened
+            return Ok(());
+        }
+        if deepest
+            .as_ref()
+            .is_none_or(|failure| stack.len() > failure.linearized.len())
+        {
+            deepest = Some(Failure {
+                linearized: stack.iter().map(|(call, _)| events[*call].op).collect(),
+                state: state.clone(),
+                blocked: op,
+            });
+        }
+        let Some((call, before)) = stack.pop() else {
+            return Err(deepest.expect("recorded before backtracking"));
+        };
+        let op = events[call].op;
+        linearized[op / 64] &= !(1 << (op % 64));
+        state = before;
+        relink(&mut next, &mut prev, returns[op]);
+        relink(&mut next, &mut prev, call);
+        entry = next[call];
+    }
+
+    Ok(())
+}
+
+// the shortest prefix of a history which can't be linearized already
+//
+// the history is cut right after a completion, operations still running at that
+// point become operations that never completed. a prefix which can't be linearized
+// means no longer one can be either, so the cut is found by bisection
+pub fn minimize(model: Model, ops: &[Op]) -> Vec<Op> {
+    let mut cuts: Vec<usize> = ops.iter().filter_map(|op| op.ret).collect();
+    cuts.sort();
+
+    let (mut lo, mut hi) = (0, cuts.len());
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        if check(model, &prefix(ops, cuts[mid])).is_err() {
+            hi = mid;
+        } else {
+            lo = mid + 1;
+        }
+    }
+    match cuts.get(lo) {
+        Some(cut) => prefix(ops, *cut),
+        None => ops.to_vec(),
+    }
+}
+
+fn prefix(ops: &[Op], cut: usize) -> Vec<Op> {
+    ops.iter()
+        .filter(|op| op.call <= cut)
+        .filter_map(|op| match (op.ret, &op.input) {
+            (Some(ret), _) if ret <= cut => Some(op.clone()),
+            // a running read or append doesn't tell us anything yet
+            (_, Input::Read | Input::Append) => None,
+            _ => Some(Op {
+                output: Val::Nil,
+                ret: None,
+                ..op.clone()
+            }),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn op(input: Input, output: Val, call: usize, ret: Option<usize>) -> Op {
+        Op {
+            process: format!("c{}", call),
+            input,
+            output,
+            call,
+            ret,
+        }
+    }
+
+    fn write(value: i64, call: usize, ret: Option<usize>) -> Op {
+        op(Input::Write(Val::Int(value)), Val::Nil, call, ret)
+    }
+
+    fn read(value: Option<i64>, call: usize, ret: usize) -> Op {
+        let output = value.map_or(Val::Nil, Val::Int);
+        op(Input::Read, output, call, Some(ret))
+    }
+
+    fn append(offset: i64, call: usize, ret: usize) -> Op {
+        op(Input::Append, Val::Int(offset), call, Some(ret))
+    }
+
+    #[test]
+    fn concurrent_writes_take_effect_in_any_order() {
+        let ops = [
+            write(1, 1, Some(4)),
+            write(2, 2, Some(3)),
+            read(Some(1), 5, 6),
+            read(Some(1), 7, 8),
+        ];
+        assert!(check(Model::Register, &ops).is_ok());
+
+        // once both completed the order is fixed
+        let ops = [
+            write(1, 1, Some(4)),
+            write(2, 2, Some(3)),
+            read(Some(1), 5, 6),
+            read(Some(2), 7, 8),
+        ];
+        assert!(check(Model::Register, &ops).is_err());
+    }
+
+    #[test]
+    fn read_cannot_see_a_later_write() {
+        let ops = [read(Some(1), 1, 2), write(1, 3, Some(4))];
+        let failure = check(Model::Register, &ops).unwrap_err();
+        assert_eq!(failure.blocked, 0);
+        assert!(failure.linearized.is_empty());
+        assert_eq!(failure.state, Val::Nil);
+    }
+
+    #[test]
+    fn write_which_never_completed_may_or_may_not_happen() {
+        let seen = [write(1, 1, None), read(Some(1), 2, 3)];
+        assert!(check(Model::Register, &seen).is_ok());
+        let lost = [write(1, 1, None), read(None, 2, 3)];
+        assert!(check(Model::Register, &lost).is_ok());
+        // but only after it was invoked
+        let early = [read(Some(1), 1, 2), write(1, 3, None)];
+        assert!(check(Model::Register, &early).is_err());
+    }
+
+    #[test]
+    fn cas_only_takes_effect_on_its_precondition() {
+        let cas = |from: i64, to: i64, call, ret| {
+            let input = Input::Cas(Val::Int(from), Val::Int(to));
+            op(input, Val::Nil, call, Some(ret))
+        };
+        let ops = [write(1, 1, Some(2)), cas(1, 2, 3, 6), read(Some(2), 4, 5)];
+        assert!(check(Model::CasRegister, &ops).is_ok());
+        let ops = [write(1, 1, Some(2)), cas(2, 3, 3, 4)];
+        assert!(check(Model::CasRegister, &ops).is_err());
+    }
+
+    #[test]
+    fn appends_get_increasing_offsets_in_real_time_order() {
+        let concurrent = [append(5, 1, 3), append(3, 2, 4)];
+        assert!(check(Model::Append, &concurrent).is_ok());
+        let sequential = [append(5, 1, 2), append(3, 3, 4)];
+        assert!(check(Model::Append, &sequential).is_err());
+        let duplicate = [append(3, 1, 3), append(3, 2, 4)];
+        assert!(check(Model::Append, &duplicate).is_err());
+    }
+
+    #[test]
+    fn minimize_cuts_after_the_first_completion_that_fails() {
+        let ops = [
+            write(1, 1, Some(2)),
+            write(2, 3, Some(10)),
+            read(Some(2), 4, 5),
+            // 2 was read already and nothing writes 1 again
+            read(Some(1), 6, 7),
+            read(Some(2), 11, 12),
+        ];
+        let failure = check(Model::Register, &ops).unwrap_err();
+        assert_eq!(failure.blocked, 3);
+        assert_eq!(failure.linearized, [0, 1, 2]);
+
+        let minimal = minimize(Model::Register, &ops);
+        let cut: Vec<_> = minimal.iter().map(|op| (op.call, op.ret)).collect();
+        // the write of 2 was still running at the cut
+        assert_eq!(cut, [(1, Some(2)), (3, None), (4, Some(5)), (6, Some(7))]);
+        assert!(check(Model::Register, &minimal).is_err());
+    }
+}
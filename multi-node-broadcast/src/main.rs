@@ -1,32 +1,22 @@
-use crate::Body::{BroadcastOk, InitOk, InternalMessage, ReadOk, TopologyOk};
+use crate::Body::{BroadcastOk, InternalMessage, ReadOk, TopologyOk};
+use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
-use std::io;
-use std::io::{BufRead, Write};
-use std::sync::mpsc::{channel, Receiver, Sender};
+use shared::config::Config;
+use shared::crdt::GSet;
+use shared::gossip::Scheduler;
+use shared::log;
+use shared::sim::simulate;
+use shared::{Message, Node, Runtime};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
-
-// generic type for json received for all problems
-// use this as a template
-#[derive(Serialize, Deserialize, Debug)]
-struct Message {
-    src: String,
-    dest: String,
-    body: Body,
-}
+use tokio::time::Instant;
+use tracing::warn;
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
 enum Body {
-    Init {
-        msg_id: u64,
-        node_id: String,
-        node_ids: Vec<String>,
-    },
-    InitOk {
-        in_reply_to: u64,
-    },
     Broadcast {
         message: u64,
         msg_id: u64,
@@ -41,7 +31,7 @@ enum Body {
     ReadOk {
         msg_id: u64,
         in_reply_to: u64,
-        messages: HashSet<u64>,
+        messages: GSet<u64>,
     },
     Topology {
         // topology: InnerTopology,
@@ -62,122 +52,147 @@ enum Body {
     },
 }
 
-fn main() -> anyhow::Result<()> {
-    let stdin = io::stdin();
-    let mut stdout = io::stdout();
+#[derive(Clone)]
+struct Handler {
+    msgs: Arc<Mutex<GSet<u64>>>,
+    // internal messages (destination, message) waiting for the next flush
+    outbox: Arc<Mutex<VecDeque<(String, u64)>>>,
+    // decides when to flush and how many of the queued messages to send,
+    // based on the incoming load and the latency target
+    scheduler: Arc<Mutex<Scheduler>>,
+    tick: Duration,
+}
 
-    let mut print_and_flush = |output: Message| -> anyhow::Result<()> {
-        let serialized_output = serde_json::to_string(&output)?;
-        writeln!(stdout, "{}", serialized_output)?;
-        stdout.flush()?;
-        Ok(())
-    };
+impl Handler {
+    fn new(config: Config) -> Self {
+        Handler {
+            msgs: Arc::new(Mutex::new(GSet::new())),
+            outbox: Arc::new(Mutex::new(VecDeque::new())),
+            scheduler: Arc::new(Mutex::new(Scheduler::new(config.gossip.settings(1)))),
+            tick: config.gossip.tick(),
+        }
+    }
 
-    let (msg_sender, msg_receiver): (Sender<Message>, Receiver<Message>) = channel();
+    // send the next batch once the scheduler decides it is time to,
+    // `force` sends the whole outbox right away
+    fn flush(&self, runtime: &Runtime, force: bool) -> anyhow::Result<()> {
+        let mut scheduler = self.scheduler.lock().unwrap();
+        let now = Instant::now();
+        if !force && !scheduler.should_flush(now) {
+            return Ok(());
+        }
+        let batch: Vec<(String, u64)> = {
+            let mut outbox = self.outbox.lock().unwrap();
+            let size = if force {
+                outbox.len()
+            } else {
+                scheduler.batch_size().min(outbox.len())
+            };
+            outbox.drain(..size).collect()
+        };
+        scheduler.flushed(now, batch.len());
+        drop(scheduler);
 
-    let handler = std::thread::spawn(move || -> anyhow::Result<()> {
-        loop {
-            for msg in msg_receiver.iter().take(50) {
-                let serialized_output = serde_json::to_string(&msg)?;
-                println!("{}", serialized_output);
-            }
-            std::thread::sleep(Duration::from_millis(400));
+        for (dest, new_message) in batch {
+            runtime.send(&dest, InternalMessage { new_message })?;
         }
         Ok(())
-    });
+    }
+}
+
+#[async_trait]
+impl Node for Handler {
+    type Body = Body;
+
+    async fn init(&self, runtime: Runtime) -> anyhow::Result<()> {
+        // frequently send the queued messages to the other nodes in the cluster
+        let handler = self.clone();
+        let flush_runtime = runtime.clone();
+        runtime.every(self.tick, move || {
+            let result = handler.flush(&flush_runtime, false);
+            async move { result }
+        });
+        Ok(())
+    }
 
-    let mut msgs = HashSet::new();
-    let mut this_node_id = String::new();
-    let mut cluster_nodes = Vec::<String>::new();
+    // send whatever is still queued, the other nodes have no other way to learn about it
+    async fn shutdown(&self, runtime: Runtime) -> anyhow::Result<()> {
+        self.flush(&runtime, true)
+    }
 
-    for line in stdin.lock().lines() {
-        let input: Message = serde_json::from_str(&line?)?;
+    async fn process(&self, runtime: Runtime, input: Message<Body>) -> anyhow::Result<()> {
         match input.body {
-            // this message is important
-            // has info { node_id of current node, node_ids of all nodes in the current cluster }
-            Body::Init {
-                msg_id,
-                node_id,
-                node_ids,
-            } => {
-                let output: Message = Message {
-                    src: input.dest,
-                    dest: input.src,
-                    body: InitOk {
-                        in_reply_to: msg_id,
-                    },
-                };
-                this_node_id = node_id;
-                cluster_nodes = node_ids;
-                print_and_flush(output)?;
-            }
             Body::Read { msg_id } => {
-                let output: Message = Message {
-                    src: input.dest,
-                    dest: input.src,
-                    body: ReadOk {
+                let messages = self.msgs.lock().unwrap().clone();
+                runtime.send(
+                    &input.src,
+                    ReadOk {
                         msg_id,
                         in_reply_to: msg_id,
-                        messages: msgs.clone(),
+                        messages,
                     },
-                };
-                print_and_flush(output)?;
+                )?;
             }
             Body::Broadcast { msg_id, message } => {
-                msgs.insert(message);
-                let output: Message = Message {
-                    src: input.dest,
-                    dest: input.src,
-                    body: BroadcastOk {
+                self.msgs.lock().unwrap().insert(message);
+                runtime.send(
+                    &input.src,
+                    BroadcastOk {
                         msg_id,
                         in_reply_to: msg_id,
                     },
-                };
-                print_and_flush(output)?;
+                )?;
 
                 // better solution
                 // send internal message to all other nodes in the cluster
                 // to add a new message in their state
                 // frequently send messages to all the other nodes in the cluster
-                // using mpsc::channels for inter-thread communication
-                for cluster_node in &cluster_nodes {
-                    if *cluster_node != this_node_id {
-                        let internal_msg: Message = Message {
-                            src: this_node_id.clone(),
-                            dest: (*cluster_node).clone(),
-                            body: InternalMessage {
-                                new_message: message,
-                            },
-                        };
-                        msg_sender.send(internal_msg)?;
-                        // let serialized_output = serde_json::to_string(&internalMessage)?;
-                        // println!("{}", serialized_output);
-                        // print_and_flush(internalMessage)?;
-                    }
+                // the outbox is flushed by the periodic task started in `init`
+                let mut queued = 0;
+                let mut outbox = self.outbox.lock().unwrap();
+                for cluster_node in runtime.other_node_ids() {
+                    outbox.push_back((cluster_node.clone(), message));
+                    queued += 1;
                 }
+                drop(outbox);
+                self.scheduler.lock().unwrap().record(queued);
             }
             Body::Topology { msg_id, .. } => {
-                let output: Message = Message {
-                    src: input.dest,
-                    dest: input.src,
-                    body: TopologyOk {
+                runtime.send(
+                    &input.src,
+                    TopologyOk {
                         msg_id,
                         in_reply_to: msg_id,
                     },
-                };
-                print_and_flush(output)?;
+                )?;
             }
             Body::InternalMessage { new_message, .. } => {
-                msgs.insert(new_message);
+                self.msgs.lock().unwrap().insert(new_message);
             }
-            InitOk { .. } | ReadOk { .. } | BroadcastOk { .. } | TopologyOk { .. } => {
-                eprintln!("{}", "Impossible input");
+            ReadOk { .. } | BroadcastOk { .. } | TopologyOk { .. } => {
+                warn!("impossible input");
             }
             Body::Error { text, .. } => {
-                eprintln!("{}", text);
+                warn!("{}", text);
             }
         }
+        Ok(())
     }
-    let _ = handler.join().unwrap();
-    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let mut defaults = Config::default();
+    defaults.gossip.latency_target_ms = 400;
+    defaults.gossip.max_batch = 50;
+    let config = Config::load(defaults)?;
+    log::init(&config.log);
+    if config.sim.enabled() {
+        return simulate(config.sim.clone(), move |_| Handler::new(config.clone())).await;
+    }
+
+    let runtime = Runtime::new()
+        .with_trace(config.trace.dir.clone())
+        .with_metrics(config.metrics.clone());
+    runtime.run(Handler::new(config)).await
 }
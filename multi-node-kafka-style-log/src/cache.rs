@@ -0,0 +1,90 @@
+use std::collections::{HashMap, HashSet};
+
+// what this node knows about the kv store, so steady-state requests skip kv reads
+//
+// the cache is written through: it is only updated after the kv write / cas succeeded.
+// entries can still be stale because of other nodes, that's fine for
+// - chunks: the cas against a stale chunk fails, the chunk is dropped and read again
+// - start offsets: they only move forward and nothing below them is deleted,
+//   a stale start only means a poll may return messages retention just dropped
+// - committed offsets: only cached with forwarding on, every commit, reset and list of
+//   a key then goes to its owner, so the owner's entries are never stale
+#[derive(Debug, Default)]
+pub struct Cache {
+    chunks: HashMap<String, Chunk>,
+    starts: HashMap<String, u64>,
+    // by `commit_key(group, key)`
+    committed: HashMap<String, u64>,
+    // groups which are registered in the kv store already
+    groups: HashSet<String>,
+}
+
+// the latest chunk this node appended to
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    pub index: usize,
+    // its contents after our last append, `None` once they are known to be stale
+    pub msgs: Option<Vec<u64>>,
+}
+
+impl Cache {
+    pub fn chunk(&self, key: &str) -> Option<Chunk> {
+        self.chunks.get(key).cloned()
+    }
+
+    pub fn set_chunk(&mut self, key: &str, index: usize, msgs: Vec<u64>) {
+        let chunk = Chunk {
+            index,
+            msgs: Some(msgs),
+        };
+        self.chunks.insert(key.to_string(), chunk);
+    }
+
+    // `index` is the latest chunk as far as we know, without knowing its contents
+    pub fn saw_chunk(&mut self, key: &str, index: usize) {
+        let chunk = self
+            .chunks
+            .entry(key.to_string())
+            .or_insert(Chunk { index, msgs: None });
+        if index > chunk.index {
+            *chunk = Chunk { index, msgs: None };
+        }
+    }
+
+    // a cas failed, keep the index as a hint but forget the contents
+    pub fn invalidate_chunk(&mut self, key: &str) {
+        if let Some(chunk) = self.chunks.get_mut(key) {
+            chunk.msgs = None;
+        }
+    }
+
+    pub fn start(&self, key: &str) -> Option<u64> {
+        self.starts.get(key).copied()
+    }
+
+    pub fn set_start(&mut self, key: &str, start: u64) {
+        let entry = self.starts.entry(key.to_string()).or_insert(start);
+        *entry = (*entry).max(start);
+    }
+
+    pub fn committed(&self, commit_key: &str) -> Option<u64> {
+        self.committed.get(commit_key).copied()
+    }
+
+    pub fn set_committed(&mut self, commit_key: &str, offset: u64) {
+        self.committed.insert(commit_key.to_string(), offset);
+    }
+
+    // a cas failed, the next read goes to the kv store
+    pub fn invalidate_committed(&mut self, commit_key: &str) {
+        self.committed.remove(commit_key);
+    }
+
+    pub fn has_group(&self, group: &str) -> bool {
+        self.groups.contains(group)
+    }
+
+    pub fn add_group(&mut self, group: &str) {
+        self.groups.insert(group.to_string());
+    }
+}
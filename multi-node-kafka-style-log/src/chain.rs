@@ -0,0 +1,763 @@
+use crate::store::Store;
+use crate::{Request, Response};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use shared::config::{Config, KafkaConfig};
+use shared::error::{code, error_code, RpcError};
+use shared::kv::{lin_kv, Kv};
+use shared::poll::PollBudget;
+use shared::{Message, Node, Runtime};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{info, warn};
+
+// `storage = "chain"`: the nodes replicate the logs along a chain
+// see https://www.cs.cornell.edu/home/rvr/papers/OSDI04.pdf
+//
+// - the chain starts out as `node_ids` in order. sends and commits enter at the head,
+//   which assigns the offset, and travel down the chain: every node stores the update
+//   and passes it on, it is acknowledged once the tail stored it
+// - polls and committed offset lists are served by the tail, everything it has was
+//   acknowledged (or is about to be)
+// - the other nodes forward requests to the head / tail
+// - a node which doesn't get an answer from a chain member probes it a few times and
+//   removes it from the chain if it still doesn't answer, or itself if no other member
+//   answers a probe either. the chain lives in lin-kv under `chain`, with an epoch
+//   bumped by every change, and chain messages carry the epoch so nodes with an
+//   outdated chain notice
+// - a removed node keeps forwarding requests and asks the tail to take it back every
+//   `REJOIN_INTERVAL`. the tail stops storing updates, copies its store to the node and
+//   appends it to the chain, so the node becomes the new tail without missing anything
+//
+// updates of a key travel down the chain one at a time and in offset order, so every
+// node has a prefix of the log of its predecessor. when a node is removed its
+// predecessor sends its update to the next node again, which asks for everything it
+// is missing
+
+const CHAIN_KEY: &str = "chain";
+// how often a member which didn't answer is probed before it is removed
+const PROBES: usize = 3;
+const REJOIN_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+struct Chain {
+    epoch: u64,
+    nodes: Vec<String>,
+}
+
+impl Chain {
+    fn head(&self) -> Option<&String> {
+        self.nodes.first()
+    }
+
+    fn tail(&self) -> Option<&String> {
+        self.nodes.last()
+    }
+
+    fn contains(&self, node: &str) -> bool {
+        self.nodes.iter().any(|member| member == node)
+    }
+
+    fn successor(&self, node: &str) -> Option<&String> {
+        let position = self.nodes.iter().position(|member| member == node)?;
+        self.nodes.get(position + 1)
+    }
+}
+
+// the updates a node sends its successor
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Update {
+    // the messages of `key` from offset `from` on
+    ChainAppend {
+        msg_id: u64,
+        epoch: u64,
+        key: String,
+        from: usize,
+        msgs: Vec<u64>,
+    },
+    ChainCommit {
+        msg_id: u64,
+        epoch: u64,
+        offsets: HashMap<String, u64>,
+        group: Option<String>,
+        // a reset may move the offsets backwards, see `Store::commit`
+        reset: bool,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+#[allow(clippy::enum_variant_names)]
+enum Stored {
+    // `len` is the length of the key's log on the successor, shorter than what we
+    // sent if it is missing messages before `from`
+    ChainAppendOk { in_reply_to: u64, len: usize },
+    ChainCommitOk { in_reply_to: u64 },
+    ChainProbeOk { in_reply_to: u64 },
+    ChainJoinOk { in_reply_to: u64 },
+    ChainSnapshotOk { in_reply_to: u64 },
+}
+
+// asks a member whether it can be reached at all
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Probe {
+    ChainProbe { msg_id: u64 },
+}
+
+// how a removed node gets back into the chain
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Join {
+    // sent to the tail by a node which isn't in the chain
+    ChainJoin {
+        msg_id: u64,
+    },
+    // the tail's store, sent to the joining node before it is added in `epoch + 1`
+    ChainSnapshot {
+        msg_id: u64,
+        epoch: u64,
+        store: Store,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum Body {
+    Chain(Update),
+    Probe(Probe),
+    Join(Join),
+    Client(Request),
+}
+
+#[derive(Clone)]
+pub struct Handler {
+    runtime: Runtime,
+    config: KafkaConfig,
+    kv: Kv,
+    chain: Arc<Mutex<Chain>>,
+    store: Arc<Mutex<Store>>,
+    // updates of a key go down the chain one at a time
+    appends: Arc<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>>,
+    commits: Arc<tokio::sync::Mutex<()>>,
+    // held for reading while an update is stored and passed on,
+    // for writing while the store is copied to a joining node
+    updates: Arc<tokio::sync::RwLock<()>>,
+}
+
+impl Handler {
+    fn chain(&self) -> Chain {
+        self.chain.lock().unwrap().clone()
+    }
+
+    fn me(&self) -> &str {
+        self.runtime.node_id()
+    }
+
+    // the chain as far as we know, it is loaded with the first request
+    // (`Node::init` can't wait for lin-kv)
+    async fn current(&self) -> anyhow::Result<Chain> {
+        let chain = self.chain();
+        if chain.nodes.is_empty() {
+            return self.refresh().await;
+        }
+        Ok(chain)
+    }
+
+    // read the chain from lin-kv, creating it on the first start
+    async fn refresh(&self) -> anyhow::Result<Chain> {
+        let chain = match self.kv.read::<Chain>(CHAIN_KEY).await? {
+            Some(chain) => chain,
+            None => {
+                let initial = Chain {
+                    epoch: 0,
+                    nodes: self.runtime.node_ids().to_vec(),
+                };
+                self.kv
+                    .cas(CHAIN_KEY, initial.clone(), initial, true)
+                    .await?;
+                self.kv.read(CHAIN_KEY).await?.unwrap_or_default()
+            }
+        };
+        Ok(self.learn(chain))
+    }
+
+    // take `chain` over unless we know a newer one
+    fn learn(&self, chain: Chain) -> Chain {
+        let mut current = self.chain.lock().unwrap();
+        if chain.epoch >= current.epoch {
+            *current = chain;
+        }
+        current.clone()
+    }
+
+    // `node` didn't answer, take it out of the chain unless it answers a probe now.
+    // only members change the chain and the last member always stays
+    async fn remove(&self, node: &str) -> anyhow::Result<()> {
+        let chain = self.chain();
+        if !chain.contains(self.me()) || !chain.contains(node) || chain.nodes.len() <= 1 {
+            self.refresh().await?;
+            return Ok(());
+        }
+        for _ in 0..PROBES {
+            if reachable(self.runtime.clone(), node.to_string()).await {
+                return Ok(());
+            }
+        }
+        // when no other member answers either we are the one who is cut off
+        let leaving = if self.isolated(&chain, node).await {
+            self.me().to_string()
+        } else {
+            node.to_string()
+        };
+        let updated = Chain {
+            epoch: chain.epoch + 1,
+            nodes: chain
+                .nodes
+                .iter()
+                .filter(|member| **member != leaving)
+                .cloned()
+                .collect(),
+        };
+        if self.kv.cas(CHAIN_KEY, &chain, &updated, false).await? {
+            info!(
+                epoch = updated.epoch,
+                "removed {} from the chain: {:?}", leaving, updated.nodes
+            );
+            self.learn(updated);
+        } else {
+            self.refresh().await?;
+        }
+        Ok(())
+    }
+
+    // whether none of the members besides `node` answer a probe, false if there are
+    // no others to ask
+    async fn isolated(&self, chain: &Chain, node: &str) -> bool {
+        let mut probes = tokio::task::JoinSet::new();
+        for member in &chain.nodes {
+            if member == node || member == self.me() {
+                continue;
+            }
+            probes.spawn(reachable(self.runtime.clone(), member.clone()));
+        }
+        if probes.is_empty() {
+            return false;
+        }
+        while let Some(reached) = probes.join_next().await {
+            if reached.unwrap_or(false) {
+                return false;
+            }
+        }
+        true
+    }
+
+    // ask the tail to take us back if we were removed
+    async fn rejoin(&self) -> anyhow::Result<()> {
+        let chain = self.refresh().await?;
+        let Some(tail) = chain.tail() else {
+            return Ok(());
+        };
+        if chain.contains(self.me()) {
+            return Ok(());
+        }
+        let join = Join::ChainJoin { msg_id: 0 };
+        let timeout = self.config.forward_timeout();
+        self.runtime
+            .call_timeout::<_, Stored>(tail, join, timeout)
+            .await?;
+        info!("rejoined the chain");
+        Ok(())
+    }
+
+    // as the tail, add `src` to the chain after it got a copy of our store. no updates
+    // are stored in between, so `src` has everything we acknowledged
+    async fn add(&self, src: &str, msg_id: u64) -> anyhow::Result<()> {
+        let _updates = self.updates.write().await;
+        let chain = self.refresh().await?;
+        if chain.tail().map(String::as_str) != Some(self.me()) || chain.contains(src) {
+            let text = "only the tail adds nodes which aren't in the chain";
+            return Err(RpcError::new(code::TEMPORARILY_UNAVAILABLE, text).into());
+        }
+        let snapshot = Join::ChainSnapshot {
+            msg_id: 0,
+            epoch: chain.epoch,
+            store: self.store.lock().unwrap().clone(),
+        };
+        let timeout = self.config.forward_timeout();
+        self.runtime
+            .call_timeout::<_, Stored>(src, snapshot, timeout)
+            .await?;
+        let mut updated = chain.clone();
+        updated.epoch += 1;
+        updated.nodes.push(src.to_string());
+        if !self.kv.cas(CHAIN_KEY, &chain, &updated, false).await? {
+            self.refresh().await?;
+            let text = "the chain changed while adding the node";
+            return Err(RpcError::new(code::TEMPORARILY_UNAVAILABLE, text).into());
+        }
+        info!(
+            epoch = updated.epoch,
+            "added {} to the chain: {:?}", src, updated.nodes
+        );
+        self.learn(updated);
+        self.runtime.send(
+            src,
+            Stored::ChainJoinOk {
+                in_reply_to: msg_id,
+            },
+        )
+    }
+
+    // the tail is about to add us to the chain, start out with its store
+    async fn restore(
+        &self,
+        src: &str,
+        msg_id: u64,
+        epoch: u64,
+        store: Store,
+    ) -> anyhow::Result<()> {
+        // the updates we are still working on fail once they see we aren't a member
+        let _updates = self.updates.write().await;
+        let chain = self.refresh().await?;
+        if chain.epoch != epoch || chain.contains(self.me()) {
+            let text = format!("snapshot of epoch {} is outdated", epoch);
+            return Err(RpcError::new(code::TEMPORARILY_UNAVAILABLE, text).into());
+        }
+        *self.store.lock().unwrap() = store;
+        self.runtime.send(
+            src,
+            Stored::ChainSnapshotOk {
+                in_reply_to: msg_id,
+            },
+        )
+    }
+
+    // updates from an outdated chain are rejected, their sender has to refresh
+    async fn check_epoch(&self, epoch: u64) -> anyhow::Result<()> {
+        let mut chain = self.current().await?;
+        if epoch > chain.epoch {
+            chain = self.refresh().await?;
+        }
+        if epoch != chain.epoch || !chain.contains(self.me()) {
+            let text = format!("chain epoch {} is not the current {}", epoch, chain.epoch);
+            return Err(RpcError::new(code::TEMPORARILY_UNAVAILABLE, text).into());
+        }
+        Ok(())
+    }
+
+    // the lock an update is stored and passed on with. the head takes updates from
+    // clients (`epoch` is `None`), the other members from their predecessor in `epoch`
+    async fn hold(
+        &self,
+        epoch: Option<u64>,
+    ) -> anyhow::Result<tokio::sync::RwLockReadGuard<'_, ()>> {
+        let updates = self.updates.read().await;
+        match epoch {
+            Some(epoch) => self.check_epoch(epoch).await?,
+            None => {
+                let chain = self.current().await?;
+                if chain.head().map(String::as_str) != Some(self.me()) {
+                    let text = "not the head of the chain";
+                    return Err(RpcError::new(code::TEMPORARILY_UNAVAILABLE, text).into());
+                }
+            }
+        }
+        Ok(updates)
+    }
+
+    fn turn(&self, key: &str) -> Arc<tokio::sync::Mutex<()>> {
+        Arc::clone(
+            self.appends
+                .lock()
+                .unwrap()
+                .entry(key.to_string())
+                .or_default(),
+        )
+    }
+
+    // pass `update` on until the tail has it, with the turn of the update held.
+    // we already stored it, so failing here leaves the update in an unknown state
+    // and the client gets a timeout. `head` is whether the update came from a client
+    async fn propagate(&self, mut update: Update, head: bool) -> anyhow::Result<()> {
+        // every node could drop out once, plus a few stale epochs
+        let attempts = self.runtime.node_ids().len() * 2 + 2;
+        for _ in 0..attempts {
+            let chain = self.chain();
+            if !chain.contains(self.me()) {
+                let text = "removed from the chain";
+                return Err(RpcError::new(code::TIMEOUT, text).into());
+            }
+            // we may have been removed and added back as the tail meanwhile
+            if head && chain.head().map(String::as_str) != Some(self.me()) {
+                let text = "not the head of the chain anymore";
+                return Err(RpcError::new(code::TIMEOUT, text).into());
+            }
+            let Some(next) = chain.successor(self.me()).cloned() else {
+                return Ok(());
+            };
+            match &mut update {
+                Update::ChainAppend { epoch, .. } | Update::ChainCommit { epoch, .. } => {
+                    *epoch = chain.epoch
+                }
+            }
+            let timeout = self.config.forward_timeout();
+            match self.runtime.call_timeout(&next, &update, timeout).await {
+                Ok(Stored::ChainAppendOk { len, .. }) => {
+                    let Update::ChainAppend {
+                        key, from, msgs, ..
+                    } = &mut update
+                    else {
+                        return Ok(());
+                    };
+                    if len >= *from + msgs.len() {
+                        return Ok(());
+                    }
+                    // the successor is missing messages, send them along
+                    let store = self.store.lock().unwrap();
+                    let log = store.logs.get(key).map_or(&[][..], Vec::as_slice);
+                    *msgs = log[len.min(log.len())..].to_vec();
+                    *from = len;
+                }
+                Ok(_) => return Ok(()),
+                Err(err) if error_code(&err) == Some(code::TIMEOUT) => self.remove(&next).await?,
+                Err(_) => {
+                    self.refresh().await?;
+                }
+            }
+        }
+        let text = "could not pass the update down the chain";
+        Err(RpcError::new(code::TIMEOUT, text).into())
+    }
+
+    async fn append(&self, key: String, msg: u64) -> anyhow::Result<usize> {
+        let _updates = self.hold(None).await?;
+        let turn = self.turn(&key);
+        let _turn = turn.lock().await;
+        let offset = {
+            let mut store = self.store.lock().unwrap();
+            let log = store.logs.entry(key.clone()).or_default();
+            log.push(msg);
+            log.len() - 1
+        };
+        let update = Update::ChainAppend {
+            msg_id: 0,
+            epoch: 0,
+            key,
+            from: offset,
+            msgs: vec![msg],
+        };
+        self.propagate(update, true).await?;
+        Ok(offset)
+    }
+
+    // `epoch` is the one of our predecessor, `None` as the head
+    async fn commit(
+        &self,
+        group: Option<String>,
+        offsets: HashMap<String, u64>,
+        reset: bool,
+        epoch: Option<u64>,
+    ) -> anyhow::Result<()> {
+        let _updates = self.hold(epoch).await?;
+        let _turn = self.commits.lock().await;
+        self.store
+            .lock()
+            .unwrap()
+            .commit(group.clone(), offsets.clone(), reset);
+        let update = Update::ChainCommit {
+            msg_id: 0,
+            epoch: 0,
+            offsets,
+            group,
+            reset,
+        };
+        self.propagate(update, epoch.is_none()).await
+    }
+
+    // store an update from our predecessor and pass it on
+    async fn handle_update(&self, src: &str, update: Update) -> anyhow::Result<()> {
+        let reply = match update {
+            Update::ChainAppend {
+                msg_id,
+                epoch,
+                key,
+                from,
+                msgs,
+            } => {
+                let _updates = self.hold(Some(epoch)).await?;
+                let turn = self.turn(&key);
+                let _turn = turn.lock().await;
+                let (stored, len) = {
+                    let mut store = self.store.lock().unwrap();
+                    let log = store.logs.entry(key.clone()).or_default();
+                    // a resent update we already have still goes down the chain, the
+                    // first attempt might not have made it to the tail
+                    let stored = from <= log.len();
+                    if stored {
+                        // where we differ from our predecessor we stored messages as a
+                        // head which was removed meanwhile, they were never acknowledged
+                        let same = log[from..].iter().zip(&msgs).take_while(|(a, b)| a == b);
+                        let same = from + same.count();
+                        if same < log.len() && same < from + msgs.len() {
+                            log.truncate(same);
+                        }
+                        if from + msgs.len() > log.len() {
+                            log.extend_from_slice(&msgs[log.len() - from..]);
+                        }
+                    }
+                    (stored, log.len())
+                };
+                if stored {
+                    let update = Update::ChainAppend {
+                        msg_id: 0,
+                        epoch,
+                        key,
+                        from,
+                        msgs,
+                    };
+                    self.propagate(update, false).await?;
+                }
+                Stored::ChainAppendOk {
+                    in_reply_to: msg_id,
+                    len,
+                }
+            }
+            Update::ChainCommit {
+                msg_id,
+                epoch,
+                offsets,
+                group,
+                reset,
+            } => {
+                self.commit(group, offsets, reset, Some(epoch)).await?;
+                Stored::ChainCommitOk {
+                    in_reply_to: msg_id,
+                }
+            }
+        };
+        self.runtime.send(src, reply)
+    }
+
+    // the chain member handling `request`: the head for updates, the tail for reads
+    fn handled_by(request: &Request, chain: &Chain) -> Option<String> {
+        match request {
+            Request::Send { .. } | Request::CommitOffsets { .. } | Request::ResetOffsets { .. } => {
+                chain.head().cloned()
+            }
+            _ => chain.tail().cloned(),
+        }
+    }
+
+    // proxy `request` to the head / tail, requests from other nodes are never passed on
+    async fn forward(&self, src: &str, request: &Request) -> anyhow::Result<()> {
+        let msg_id = request.msg_id().unwrap_or_default();
+        for _ in 0..self.runtime.node_ids().len() + 1 {
+            let chain = self.current().await?;
+            let target = match Self::handled_by(request, &chain) {
+                Some(target) if target != self.me() => target,
+                _ => return self.serve(src, request.clone()).await,
+            };
+            if self.runtime.node_ids().iter().any(|node| node == src) {
+                let text = format!("not the chain member for this request, {} is", target);
+                return Err(RpcError::new(code::TEMPORARILY_UNAVAILABLE, text).into());
+            }
+            let timeout = self.config.forward_timeout();
+            match self
+                .runtime
+                .call_timeout::<_, serde_json::Value>(&target, request, timeout)
+                .await
+            {
+                Ok(mut reply) => {
+                    reply["in_reply_to"] = msg_id.into();
+                    return self.runtime.send(src, reply);
+                }
+                Err(err) if error_code(&err) == Some(code::TIMEOUT) => self.remove(&target).await?,
+                Err(err) if error_code(&err) == Some(code::TEMPORARILY_UNAVAILABLE) => {
+                    self.refresh().await?;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        let text = "no chain member answered";
+        Err(RpcError::new(code::TEMPORARILY_UNAVAILABLE, text).into())
+    }
+
+    // handle `request` as the head / tail of the chain
+    async fn serve(&self, src: &str, request: Request) -> anyhow::Result<()> {
+        let response = match request {
+            Request::Send {
+                msg_id, key, msg, ..
+            } => Response::SendOk {
+                offset: self.append(key, msg).await?,
+                in_reply_to: msg_id,
+            },
+            Request::CommitOffsets {
+                msg_id,
+                offsets,
+                group,
+            } => {
+                self.commit(group, offsets, false, None).await?;
+                Response::CommitOffsetsOk {
+                    in_reply_to: msg_id,
+                }
+            }
+            Request::ResetOffsets {
+                msg_id,
+                offsets,
+                group,
+            } => {
+                self.commit(group, offsets, true, None).await?;
+                Response::ResetOffsetsOk {
+                    in_reply_to: msg_id,
+                }
+            }
+            request => {
+                // make sure we still are the tail, a removed tail could miss new messages
+                let chain = self.refresh().await?;
+                if chain.tail().map(String::as_str) != Some(self.me()) {
+                    let text = "not the tail of the chain anymore";
+                    return Err(RpcError::new(code::TEMPORARILY_UNAVAILABLE, text).into());
+                }
+                self.read(request)?
+            }
+        };
+        self.runtime.send(src, response)
+    }
+
+    fn read(&self, request: Request) -> Result<Response, RpcError> {
+        let store = self.store.lock().unwrap();
+        let response = match request {
+            Request::Poll {
+                msg_id,
+                offsets,
+                max_messages,
+                max_bytes,
+            } => {
+                let budget = PollBudget::new(&self.config, max_messages, max_bytes);
+                Response::PollOk {
+                    msgs: store.poll(offsets, budget),
+                    in_reply_to: msg_id,
+                }
+            }
+            Request::ListCommittedOffsets {
+                msg_id,
+                keys,
+                group,
+            } => Response::ListCommittedOffsetsOk {
+                offsets: store.committed(group.as_deref(), keys),
+                in_reply_to: msg_id,
+            },
+            Request::ListGroups { msg_id } => Response::ListGroupsOk {
+                groups: store.groups(),
+                in_reply_to: msg_id,
+            },
+            request => {
+                let text = format!(
+                    "{:?} is not a read, updates are served by the head",
+                    request
+                );
+                return Err(RpcError::new(code::NOT_SUPPORTED, text));
+            }
+        };
+        Ok(response)
+    }
+}
+
+#[async_trait]
+impl Node for Handler {
+    type Body = Body;
+
+    async fn init(&self, runtime: Runtime) -> anyhow::Result<()> {
+        let handler = self.clone();
+        runtime.every(REJOIN_INTERVAL, move || {
+            let handler = handler.clone();
+            async move { handler.rejoin().await }
+        });
+        Ok(())
+    }
+
+    async fn process(&self, _runtime: Runtime, req: Message<Body>) -> anyhow::Result<()> {
+        match req.body {
+            Body::Chain(update) => self.handle_update(&req.src, update).await,
+            Body::Probe(Probe::ChainProbe { msg_id }) => {
+                let reply = Stored::ChainProbeOk {
+                    in_reply_to: msg_id,
+                };
+                self.runtime.send(&req.src, reply)
+            }
+            Body::Join(Join::ChainJoin { msg_id }) => self.add(&req.src, msg_id).await,
+            Body::Join(Join::ChainSnapshot {
+                msg_id,
+                epoch,
+                store,
+            }) => self.restore(&req.src, msg_id, epoch, store).await,
+            Body::Client(Request::Error { text, .. }) => {
+                warn!("{}", text);
+                Ok(())
+            }
+            Body::Client(request) => self.forward(&req.src, &request).await,
+        }
+    }
+}
+
+pub fn handler(runtime: Runtime, config: Config) -> Handler {
+    Handler {
+        kv: lin_kv(runtime.clone()),
+        runtime,
+        config: config.kafka,
+        chain: Arc::new(Mutex::new(Chain::default())),
+        store: Arc::new(Mutex::new(Store::default())),
+        appends: Arc::new(Mutex::new(HashMap::new())),
+        commits: Arc::new(tokio::sync::Mutex::new(())),
+        updates: Arc::new(tokio::sync::RwLock::new(())),
+    }
+}
+
+// whether `member` answers a probe
+async fn reachable(runtime: Runtime, member: String) -> bool {
+    let probe = Probe::ChainProbe { msg_id: 0 };
+    runtime.call::<_, Stored>(&member, probe).await.is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use linearizability_checker::model::Model;
+    use serde_json::json;
+    use shared::config::KafkaStorage;
+    use shared::sim::simulate_checked;
+
+    #[tokio::test]
+    async fn offsets_stay_linearizable_while_members_are_partitioned() {
+        let requests: Vec<String> = (0..90)
+            .map(|i| match i % 3 {
+                2 => json!({"type": "poll", "offsets": {"k0": 0, "k1": 0}}),
+                _ => json!({"type": "send", "key": format!("k{}", i % 2), "msg": i}),
+            })
+            .map(|request| request.to_string())
+            .collect();
+        let path = std::env::temp_dir().join(format!("chain-{}.jsonl", std::process::id()));
+        std::fs::write(&path, requests.join("\n")).unwrap();
+
+        let mut config = Config::default();
+        config.kafka.storage = KafkaStorage::Chain;
+        let mut sim = config.sim.clone();
+        sim.requests = Some(path.clone());
+        sim.seed = Some(1);
+        sim.runs = 3;
+        sim.partition_ms = 500;
+        let check =
+            |ops: &[serde_json::Value]| linearizability_checker::check_ops(Model::Append, ops);
+        let result =
+            simulate_checked(sim, move |runtime| handler(runtime, config.clone()), check).await;
+        std::fs::remove_file(&path).unwrap();
+        result.unwrap();
+    }
+}
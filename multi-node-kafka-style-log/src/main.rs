@@ -1,49 +1,166 @@
 use async_trait::async_trait;
-use maelstrom::kv::{seq_kv, Storage, KV};
-use maelstrom::protocol::Message;
-use maelstrom::Error::KeyDoesNotExist;
-use maelstrom::{done, Node, Result, Runtime};
+use linearizability_checker::model::Model;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use shared::config::{Config, KafkaConfig, KafkaStorage, TruncatedPoll};
+use shared::error::{code, RpcError};
+use shared::forward::{Forwarder, Route};
+use shared::kv::{seq_kv, Kv};
+use shared::log;
+use shared::poll::PollBudget;
+use shared::sim::simulate_checked;
+use shared::{Message, Node, Runtime};
 use std::collections::HashMap;
-use std::error::Error;
+use std::ops::Range;
 use std::sync::{Arc, Mutex};
-use tokio_context::context::{Context, Handle};
+use tokio::task::JoinSet;
+
+mod cache;
+mod chain;
+mod replicated;
+mod store;
+
+use cache::Cache;
+use tracing::warn;
+
+const GROUPS_KEY: &str = "consumer_groups";
+// the default group (requests without `group`) is registered under this name,
+// so retention knows whether it consumes at all. `list_groups` leaves it out
+const DEFAULT_GROUP: &str = "";
 
 #[derive(Clone)]
 struct Handler {
-    storage: Storage,
-}
-
-struct InnerData {
-    data: HashMap<String, Vec<u64>>,
-    committed_offsets: HashMap<String, u64>,
+    storage: Kv,
+    config: KafkaConfig,
+    cache: Arc<Mutex<Cache>>,
+    forwarder: Forwarder,
+    // appends to a key on this node take turns, so they don't fail each other's cas
+    appends: Arc<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>>,
+    // so do commits and resets of a key, by `commit_key(group, key)`
+    commits: Arc<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
 enum Request {
-    Send { key: String, msg: u64 },
-    Poll { offsets: HashMap<String, usize> },
-    CommitOffsets { offsets: HashMap<String, u64> },
-    ListCommittedOffsets { keys: Vec<String> },
-    Error { code: u64, text: String },
+    Send {
+        msg_id: u64,
+        key: String,
+        msg: u64,
+        // `<client>/<msg_id>` of a send another node forwarded, see `append_claimed`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request: Option<String>,
+    },
+    Poll {
+        msg_id: u64,
+        offsets: HashMap<String, usize>,
+        #[serde(default)]
+        max_messages: Option<usize>,
+        #[serde(default)]
+        max_bytes: Option<usize>,
+    },
+    // without a group the offsets belong to the default group
+    CommitOffsets {
+        msg_id: u64,
+        offsets: HashMap<String, u64>,
+        #[serde(default)]
+        group: Option<String>,
+    },
+    ListCommittedOffsets {
+        msg_id: u64,
+        keys: Vec<String>,
+        #[serde(default)]
+        group: Option<String>,
+    },
+    ListGroups {
+        msg_id: u64,
+    },
+    ResetOffsets {
+        msg_id: u64,
+        offsets: HashMap<String, u64>,
+        #[serde(default)]
+        group: Option<String>,
+    },
+    Error {
+        code: u64,
+        text: String,
+    },
+}
+
+impl Request {
+    fn msg_id(&self) -> Option<u64> {
+        match self {
+            Request::Send { msg_id, .. }
+            | Request::Poll { msg_id, .. }
+            | Request::CommitOffsets { msg_id, .. }
+            | Request::ListCommittedOffsets { msg_id, .. }
+            | Request::ListGroups { msg_id }
+            | Request::ResetOffsets { msg_id, .. } => Some(*msg_id),
+            Request::Error { .. } => None,
+        }
+    }
+}
+
+// the `send_ok` of the owner of a key
+#[derive(Deserialize, Debug)]
+struct Sent {
+    offset: usize,
+}
+
+// the `list_committed_offsets_ok` of the owner of some keys
+#[derive(Deserialize, Debug)]
+struct Listed {
+    offsets: HashMap<String, u64>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
+#[allow(clippy::enum_variant_names)]
 enum Response {
     SendOk {
         offset: usize,
+        in_reply_to: u64,
     },
     PollOk {
         msgs: HashMap<String, Vec<Vec<u64>>>,
+        in_reply_to: u64,
+    },
+    CommitOffsetsOk {
+        in_reply_to: u64,
     },
-    CommitOffsetsOk {},
     ListCommittedOffsetsOk {
         offsets: HashMap<String, u64>,
+        in_reply_to: u64,
+    },
+    ListGroupsOk {
+        groups: Vec<String>,
+        in_reply_to: u64,
     },
+    ResetOffsetsOk {
+        in_reply_to: u64,
+    },
+}
+
+fn chunk_key(key: &str, chunk: usize) -> String {
+    format!("log/{key}/chunk/{chunk}")
+}
+
+fn latest_chunk_key(key: &str) -> String {
+    format!("log/{key}/latest_chunk")
+}
+
+fn claim_key(request: &str) -> String {
+    format!("send_claim/{request}")
+}
+
+fn commit_key(group: Option<&str>, key: &str) -> String {
+    match group {
+        Some(group) => format!("committed_offset_for_group_{group}_key_{key}"),
+        None => format!("committed_offset_for_key_{key}"),
+    }
 }
 
 impl Handler {
@@ -60,154 +177,572 @@ impl Handler {
 
     How do we store this data inside a key-value store?
 
-    let's store the info as follows:
+    every message under its own key costs one kv rpc per message on send and on poll,
+    so the messages are packed into chunks of `chunk_size` messages instead:
 
-    - latest_offset_for_{key} -> some offset
-    - value_for_{key}_at_offset_{offset} -> some value (message data)
+    - log/{key}/chunk/{n} -> [msg, msg, ...], the messages at offsets n * chunk_size ..
+    - log/{key}/latest_chunk -> some chunk index, a hint where appends should start
     - committed_offset_for_key_{key} -> some offset
+    - committed_offset_for_group_{group}_key_{key} -> some offset, of a consumer group
+    - consumer_groups -> [group1, group2, ...], "" for the default group
+    - start_offset_for_{key} -> first offset kept by retention
+    - send_claim/{client}/{msg_id} -> the node appending a forwarded send
 
+    a chunk is only ever appended to with a cas, the next chunk is started once it is full.
+    the kv services can't delete keys, so retention only moves the start offset,
+    the chunks below it are never read again
     */
-    async fn add_message_to_key(&self, key: String, msg: u64, mut handler: Handle) -> usize {
-        // TODO: store this start offset for each node in-memory
-        // 1. Get the latest offset for given key from KV store
-        // this helps use to minimize the search for the monotonous next offset to assign the given message
-        let search_key = format!("latest_offset_for_{}", key);
-        let mut start: usize = match self
-            .storage
-            .get(handler.spawn_ctx(), search_key.clone())
-            .await
-        {
-            Ok(result) => result,
-            Err(e) => 0,
-        };
+    async fn add_message_to_key(&self, key: String, msg: u64) -> anyhow::Result<usize> {
+        let size = self.config.chunk_size;
+        let first = self.latest_chunk(&key).await?;
+        let mut known = self
+            .cache
+            .lock()
+            .unwrap()
+            .chunk(&key)
+            .and_then(|chunk| chunk.msgs);
+        let mut chunk = first;
 
-        // 2. Find the increasing offset to store the given message at
-        //  - Loop over the latest offset retrieved from step 1
-        //  - Success result on a CAS operation means (offset) can be used for storing the new message
-        loop {
-            let curr = start.clone();
-            let (prev, now) = (curr.clone() - 1, curr);
-            let res = self
+        // append to the latest chunk which isn't full yet, the cas fails if another
+        // node appended in the meantime (or our copy was stale), then just read again
+        let offset = loop {
+            let chunk_key = chunk_key(&key, chunk);
+            let msgs: Vec<u64> = match known.take() {
+                Some(msgs) => msgs,
+                None => self.storage.read(&chunk_key).await?.unwrap_or_default(),
+            };
+            if msgs.len() >= size {
+                chunk += 1;
+                // most likely nobody started the next chunk yet
+                known = Some(Vec::new());
+                continue;
+            }
+            let offset = chunk * size + msgs.len();
+            let mut appended = msgs.clone();
+            appended.push(msg);
+            if self
                 .storage
-                .cas(handler.spawn_ctx(), search_key.clone(), prev, now, true)
-                .await;
-            match res {
-                Ok(_) => break,
-                Err(_) => {
-                    start += 1;
-                }
+                .cas(&chunk_key, msgs, appended.clone(), true)
+                .await?
+            {
+                self.cache.lock().unwrap().set_chunk(&key, chunk, appended);
+                break offset;
             }
+            self.cache.lock().unwrap().invalidate_chunk(&key);
+        };
+
+        if chunk != first {
+            // only a hint, it doesn't matter if another node overwrites it with an older chunk
+            self.storage.write(&latest_chunk_key(&key), chunk).await?;
         }
 
-        // 3. Write the message at that corresponding offset location
-        let messages_key = format!("value_for_{key}_at_offset_{start}");
-        self.storage
-            .put(handler.spawn_ctx(), messages_key, msg)
-            .await
-            .expect("Error occurred while writing to the KV store");
+        // only keep the latest `retention_messages` messages
+        if let Some(keep) = self.config.retention_messages {
+            let next = offset as u64 + 1;
+            if next > keep {
+                self.advance_start(&key, next - keep).await?;
+            }
+        }
 
-        // 4. Also update the latest offset for this key
-        let search_key = format!("latest_offset_for_{}", key);
+        Ok(offset)
+    }
+
+    async fn append(&self, key: String, msg: u64) -> anyhow::Result<usize> {
+        let turn = Arc::clone(self.appends.lock().unwrap().entry(key.clone()).or_default());
+        let _turn = turn.lock().await;
+        self.add_message_to_key(key, msg).await
+    }
 
-        let _ = self
+    // the parts of a request by the owners of their keys, with forwarding on the
+    // committed offsets of a key are only read and written by its owner (see `Cache`).
+    // a part another node forwarded is ours
+    fn by_owner<V>(
+        &self,
+        runtime: &Runtime,
+        src: &str,
+        entries: HashMap<String, V>,
+    ) -> HashMap<String, HashMap<String, V>> {
+        let forwarded = runtime.node_ids().iter().any(|node| node == src);
+        let mut parts: HashMap<String, HashMap<String, V>> = HashMap::new();
+        for (key, value) in entries {
+            let owner = match self.config.forward && !forwarded {
+                true => Forwarder::preference_list(runtime, &key)[0],
+                false => runtime.node_id(),
+            };
+            parts
+                .entry(owner.to_string())
+                .or_default()
+                .insert(key, value);
+        }
+        parts
+    }
+
+    // send every owner its part at once, `request` builds the request from a part
+    async fn ask_owners<V, R>(
+        &self,
+        runtime: &Runtime,
+        parts: HashMap<String, HashMap<String, V>>,
+        request: impl Fn(HashMap<String, V>) -> Request,
+    ) -> anyhow::Result<Vec<R>>
+    where
+        R: DeserializeOwned + Send + 'static,
+    {
+        let mut calls = JoinSet::new();
+        for (owner, part) in parts {
+            let runtime = runtime.clone();
+            let request = request(part);
+            let timeout = self.config.forward_timeout();
+            calls.spawn(async move { runtime.call_timeout(&owner, request, timeout).await });
+        }
+        let mut replies = Vec::new();
+        while let Some(reply) = calls.join_next().await {
+            replies.push(reply??);
+        }
+        Ok(replies)
+    }
+
+    // a forwarded send reaches a second node when the owner timed out, but the owner may
+    // still append it. whichever of them claims the request first appends it, the other
+    // one can't tell whether that happened yet
+    async fn append_claimed(
+        &self,
+        node: &str,
+        request: &str,
+        key: String,
+        msg: u64,
+    ) -> anyhow::Result<usize> {
+        if !self
             .storage
-            .put(handler.spawn_ctx(), search_key, start)
-            .await;
+            .cas(&claim_key(request), node, node, true)
+            .await?
+        {
+            let text = format!("send {} is handled by another node", request);
+            return Err(RpcError::new(code::TIMEOUT, text).into());
+        }
+        self.append(key, msg).await
+    }
+
+    async fn start_offset(&self, key: &str) -> anyhow::Result<u64> {
+        if let Some(start) = self.cache.lock().unwrap().start(key) {
+            return Ok(start);
+        }
+        self.read_start_offset(key).await
+    }
 
-        start
+    async fn read_start_offset(&self, key: &str) -> anyhow::Result<u64> {
+        let start_key = format!("start_offset_for_{key}");
+        let start = self.storage.read(&start_key).await?.unwrap_or(0);
+        self.cache.lock().unwrap().set_start(key, start);
+        Ok(start)
+    }
+
+    // the start offset only ever moves forward, even with several nodes truncating at once
+    async fn advance_start(&self, key: &str, offset: u64) -> anyhow::Result<()> {
+        if self.cache.lock().unwrap().start(key) >= Some(offset) {
+            return Ok(());
+        }
+        let start_key = format!("start_offset_for_{key}");
+        loop {
+            let current = self.read_start_offset(key).await?;
+            if offset <= current {
+                return Ok(());
+            }
+            if self.storage.cas(&start_key, current, offset, true).await? {
+                self.cache.lock().unwrap().set_start(key, offset);
+                return Ok(());
+            }
+        }
     }
 
     async fn get_messages_for_offsets(
         &self,
         offsets: HashMap<String, usize>,
-        mut handler: Handle,
-    ) -> HashMap<String, Vec<Vec<u64>>> {
-        // we limit the messages to send back to the caller
-        const LIMIT: usize = 100;
+        mut budget: PollBudget,
+    ) -> anyhow::Result<HashMap<String, Vec<Vec<u64>>>> {
         let mut msgs = HashMap::new();
-        for (key, offset) in offsets {
-            let mut response = Vec::new();
-            for id in offset..(offset + LIMIT) {
-                let search_key = format!("value_for_{key}_at_offset_{id}");
-                let value_result: Result<u64> =
-                    self.storage.get(handler.spawn_ctx(), search_key).await;
-                match value_result {
-                    Ok(value) => {
-                        response.push(vec![id as u64, value as u64]);
-                    }
-                    Err(e) => {
-                        continue;
+        for (key, mut offset) in offsets {
+            if self.config.retention_enabled() {
+                let start = self.start_offset(&key).await? as usize;
+                if offset < start {
+                    match self.config.truncated_poll {
+                        TruncatedPoll::Advance => offset = start,
+                        TruncatedPoll::Error => {
+                            let text = format!(
+                                "offset {} of {} was truncated, the log starts at {}",
+                                offset, key, start
+                            );
+                            return Err(RpcError::new(code::OFFSET_TRUNCATED, text).into());
+                        }
                     }
                 }
             }
-            msgs.insert(key, response);
+            let values = self.read_range(&key, offset, budget.key_limit()).await?;
+            msgs.insert(key, budget.fill(values));
+        }
+        Ok(msgs)
+    }
+
+    // up to `limit` (offset, msg) pairs starting at `offset`
+    //
+    // the chunks up to the latest one we know of are read at once, the ones after it
+    // one by one, the log ends at the first chunk which is missing or not full
+    async fn read_range(
+        &self,
+        key: &str,
+        offset: usize,
+        limit: usize,
+    ) -> anyhow::Result<Vec<(u64, u64)>> {
+        let size = self.config.chunk_size;
+        let (first, last) = (offset / size, (offset + limit).div_ceil(size));
+        let latest = self.latest_chunk(key).await?;
+
+        let mut values = Vec::new();
+        let mut chunks = first..last.min(latest + 1).max(first + 1);
+        'read: while chunks.start < last {
+            let end = chunks.end;
+            for (chunk, msgs) in self.read_chunks(key, chunks).await? {
+                let Some(msgs) = msgs else {
+                    break 'read;
+                };
+                self.cache.lock().unwrap().saw_chunk(key, chunk);
+                let full = msgs.len() >= size;
+                values.extend(
+                    msgs.into_iter()
+                        .enumerate()
+                        .map(|(i, msg)| ((chunk * size + i) as u64, msg))
+                        .filter(|&(id, _)| id >= offset as u64),
+                );
+                if !full {
+                    break 'read;
+                }
+            }
+            chunks = end..end + 1;
         }
-        msgs
+        values.truncate(limit);
+        Ok(values)
+    }
+
+    // every chunk is its own kv read, so all of them are sent at once
+    async fn read_chunks(
+        &self,
+        key: &str,
+        chunks: Range<usize>,
+    ) -> anyhow::Result<Vec<(usize, Option<Vec<u64>>)>> {
+        let mut reads = JoinSet::new();
+        for chunk in chunks {
+            let storage = self.storage.clone();
+            let chunk_key = chunk_key(key, chunk);
+            reads.spawn(async move {
+                let msgs: Option<Vec<u64>> = storage.read(&chunk_key).await?;
+                anyhow::Ok((chunk, msgs))
+            });
+        }
+        let mut read = Vec::new();
+        while let Some(chunk) = reads.join_next().await {
+            read.push(chunk??);
+        }
+        read.sort_unstable();
+        Ok(read)
+    }
+
+    // the index of the latest chunk of `key` this node knows about
+    async fn latest_chunk(&self, key: &str) -> anyhow::Result<usize> {
+        if let Some(chunk) = self.cache.lock().unwrap().chunk(key) {
+            return Ok(chunk.index);
+        }
+        let latest = self
+            .storage
+            .read(&latest_chunk_key(key))
+            .await?
+            .unwrap_or(0);
+        self.cache.lock().unwrap().saw_chunk(key, latest);
+        Ok(latest)
     }
 
-    async fn update_offsets(&self, offsets: HashMap<String, u64>, mut handler: Handle) {
+    async fn groups(&self) -> anyhow::Result<Vec<String>> {
+        Ok(self.storage.read(GROUPS_KEY).await?.unwrap_or_default())
+    }
+
+    // add `group` to the list of groups, several nodes may be registering groups at once
+    async fn register_group(&self, group: &str) -> anyhow::Result<()> {
+        if self.cache.lock().unwrap().has_group(group) {
+            return Ok(());
+        }
+        loop {
+            let groups = self.groups().await?;
+            if groups.iter().any(|known| known == group) {
+                break;
+            }
+            let mut updated = groups.clone();
+            updated.push(group.to_string());
+            if self.storage.cas(GROUPS_KEY, groups, updated, true).await? {
+                break;
+            }
+        }
+        self.cache.lock().unwrap().add_group(group);
+        Ok(())
+    }
+
+    // the lowest offset of `key` committed by the registered groups, messages after it
+    // are still needed. `None` if a group never committed `key`: it needs all of it
+    async fn min_committed(&self, key: &str) -> anyhow::Result<Option<u64>> {
+        let mut min: Option<u64> = None;
+        for group in self.groups().await? {
+            let group = Some(group.as_str()).filter(|group| *group != DEFAULT_GROUP);
+            match self.committed(group, key).await? {
+                Some(committed) => min = Some(min.map_or(committed, |min| min.min(committed))),
+                None => return Ok(None),
+            }
+        }
+        Ok(min)
+    }
+
+    async fn update_offsets(
+        &self,
+        group: Option<&str>,
+        offsets: HashMap<String, u64>,
+    ) -> anyhow::Result<()> {
+        self.register_group(group.unwrap_or(DEFAULT_GROUP)).await?;
         for (key, offset) in offsets {
-            let commit_key = format!("committed_offset_for_key_{key}");
-            let result = self
-                .storage
-                .put(handler.spawn_ctx(), commit_key, offset)
-                .await;
+            self.set_committed(group, &key, offset, false).await?;
+            // committed messages were processed already, nobody needs them anymore
+            if self.config.retention_committed {
+                if let Some(committed) = self.min_committed(&key).await? {
+                    self.advance_start(&key, committed + 1).await?;
+                }
+            }
         }
+        Ok(())
     }
 
-    async fn get_offsets(&self, keys: Vec<String>, mut handler: Handle) -> HashMap<String, u64> {
+    async fn get_offsets(
+        &self,
+        group: Option<&str>,
+        keys: Vec<String>,
+    ) -> anyhow::Result<HashMap<String, u64>> {
         let mut offsets = HashMap::new();
 
         for key in keys {
-            let search_offsets_key = format!("committed_offset_for_key_{key}");
-            let result: Result<u64> = self
-                .storage
-                .get(handler.spawn_ctx(), search_offsets_key)
-                .await;
-            let offset = match result {
-                Ok(value) => value,
-                Err(e) => 0,
-            };
+            let offset = self.committed(group, &key).await?.unwrap_or(0);
             offsets.insert(key, offset);
         }
-        offsets
+        Ok(offsets)
+    }
+
+    async fn committed(&self, group: Option<&str>, key: &str) -> anyhow::Result<Option<u64>> {
+        let commit_key = commit_key(group, key);
+        if let Some(offset) = self.cache.lock().unwrap().committed(&commit_key) {
+            return Ok(Some(offset));
+        }
+        let committed = self.storage.read(&commit_key).await?;
+        if let (Some(offset), true) = (committed, self.config.forward) {
+            self.cache
+                .lock()
+                .unwrap()
+                .set_committed(&commit_key, offset);
+        }
+        Ok(committed)
+    }
+
+    // a commit never moves the offset backwards (like on the single-node log),
+    // a reset sets it to whatever it is given
+    async fn set_committed(
+        &self,
+        group: Option<&str>,
+        key: &str,
+        offset: u64,
+        reset: bool,
+    ) -> anyhow::Result<()> {
+        let commit_key = commit_key(group, key);
+        let turn = Arc::clone(
+            self.commits
+                .lock()
+                .unwrap()
+                .entry(commit_key.clone())
+                .or_default(),
+        );
+        let _turn = turn.lock().await;
+        loop {
+            let current = self.committed(group, key).await?;
+            if current.is_some_and(|current| current >= offset && !reset) {
+                return Ok(());
+            }
+            if self
+                .storage
+                .cas(&commit_key, current, Some(offset), true)
+                .await?
+            {
+                if self.config.forward {
+                    self.cache
+                        .lock()
+                        .unwrap()
+                        .set_committed(&commit_key, offset);
+                }
+                return Ok(());
+            }
+            self.cache.lock().unwrap().invalidate_committed(&commit_key);
+        }
     }
 }
 
 #[async_trait]
 impl Node for Handler {
-    async fn process(&self, runtime: Runtime, req: Message) -> Result<()> {
-        let (_context, mut handler) = Context::new();
-        let msg: Result<Request> = req.body.as_obj();
-        match msg {
-            Ok(Request::Send { key, msg }) => {
-                let offset = self.add_message_to_key(key, msg, handler).await;
-                return runtime.reply(req, Response::SendOk { offset }).await;
-            }
-            Ok(Request::Poll { offsets }) => {
-                let results = self.get_messages_for_offsets(offsets, handler).await;
-                return runtime.reply(req, Response::PollOk { msgs: results }).await;
-            }
-            Ok(Request::CommitOffsets { offsets }) => {
-                self.update_offsets(offsets, handler).await;
-                return runtime.reply(req, Response::CommitOffsetsOk {}).await;
-            }
-            Ok(Request::ListCommittedOffsets { keys }) => {
-                return runtime
-                    .reply(
-                        req,
-                        Response::ListCommittedOffsetsOk {
-                            offsets: self.get_offsets(keys, handler).await,
-                        },
-                    )
-                    .await;
-            }
-            _ => done(runtime, req),
+    type Body = Request;
+
+    async fn process(&self, runtime: Runtime, req: Message<Request>) -> anyhow::Result<()> {
+        match req.body {
+            Request::Send {
+                msg_id,
+                key,
+                msg,
+                request,
+            } => {
+                let forwarded = Request::Send {
+                    msg_id,
+                    key: key.clone(),
+                    msg,
+                    request: Some(format!("{}/{}", req.src, msg_id)),
+                };
+                let route = if self.config.forward {
+                    self.forwarder
+                        .route(&runtime, &req.src, &key, &forwarded)
+                        .await?
+                } else {
+                    Route::Local
+                };
+                let offset = match route {
+                    Route::Local => match request {
+                        Some(request) => {
+                            let node = runtime.node_id();
+                            self.append_claimed(node, &request, key, msg).await?
+                        }
+                        None => self.append(key, msg).await?,
+                    },
+                    Route::Remote(Sent { offset }) => offset,
+                };
+                runtime.send(
+                    &req.src,
+                    Response::SendOk {
+                        offset,
+                        in_reply_to: msg_id,
+                    },
+                )
+            }
+            Request::Poll {
+                msg_id,
+                offsets,
+                max_messages,
+                max_bytes,
+            } => {
+                let budget = PollBudget::new(&self.config, max_messages, max_bytes);
+                let results = self.get_messages_for_offsets(offsets, budget).await?;
+                runtime.send(
+                    &req.src,
+                    Response::PollOk {
+                        msgs: results,
+                        in_reply_to: msg_id,
+                    },
+                )
+            }
+            Request::CommitOffsets {
+                msg_id,
+                offsets,
+                group,
+            } => {
+                let mut parts = self.by_owner(&runtime, &req.src, offsets);
+                let local = parts.remove(runtime.node_id()).unwrap_or_default();
+                let commit = |offsets| Request::CommitOffsets {
+                    msg_id: 0,
+                    offsets,
+                    group: group.clone(),
+                };
+                let _: Vec<Value> = self.ask_owners(&runtime, parts, commit).await?;
+                self.update_offsets(group.as_deref(), local).await?;
+                runtime.send(
+                    &req.src,
+                    Response::CommitOffsetsOk {
+                        in_reply_to: msg_id,
+                    },
+                )
+            }
+            Request::ListCommittedOffsets {
+                msg_id,
+                keys,
+                group,
+            } => {
+                let keys = keys.into_iter().map(|key| (key, ())).collect();
+                let mut parts = self.by_owner(&runtime, &req.src, keys);
+                let local = parts.remove(runtime.node_id()).unwrap_or_default();
+                let list = |keys: HashMap<String, ()>| Request::ListCommittedOffsets {
+                    msg_id: 0,
+                    keys: keys.into_keys().collect(),
+                    group: group.clone(),
+                };
+                let listed: Vec<Listed> = self.ask_owners(&runtime, parts, list).await?;
+                let local = local.into_keys().collect();
+                let mut offsets = self.get_offsets(group.as_deref(), local).await?;
+                for listed in listed {
+                    offsets.extend(listed.offsets);
+                }
+                runtime.send(
+                    &req.src,
+                    Response::ListCommittedOffsetsOk {
+                        offsets,
+                        in_reply_to: msg_id,
+                    },
+                )
+            }
+            Request::ListGroups { msg_id } => {
+                let mut groups = self.groups().await?;
+                groups.retain(|group| group != DEFAULT_GROUP);
+                groups.sort();
+                runtime.send(
+                    &req.src,
+                    Response::ListGroupsOk {
+                        groups,
+                        in_reply_to: msg_id,
+                    },
+                )
+            }
+            // unlike a commit a reset may move the offset backwards, and it never
+            // truncates the log
+            Request::ResetOffsets {
+                msg_id,
+                offsets,
+                group,
+            } => {
+                let mut parts = self.by_owner(&runtime, &req.src, offsets);
+                let local = parts.remove(runtime.node_id()).unwrap_or_default();
+                let reset = |offsets| Request::ResetOffsets {
+                    msg_id: 0,
+                    offsets,
+                    group: group.clone(),
+                };
+                let _: Vec<Value> = self.ask_owners(&runtime, parts, reset).await?;
+                self.register_group(group.as_deref().unwrap_or(DEFAULT_GROUP))
+                    .await?;
+                for (key, offset) in local {
+                    self.set_committed(group.as_deref(), &key, offset, true)
+                        .await?;
+                }
+                runtime.send(
+                    &req.src,
+                    Response::ResetOffsetsOk {
+                        in_reply_to: msg_id,
+                    },
+                )
+            }
+            Request::Error { text, .. } => {
+                warn!("{}", text);
+                Ok(())
+            }
         }
     }
 }
 
-fn handler(runtime: Runtime) -> Handler {
+fn handler(runtime: Runtime, mut config: Config) -> Handler {
+    // offsets are computed from the chunk index, an empty chunk can't hold anything
+    config.kafka.chunk_size = config.kafka.chunk_size.max(1);
     // this was for single node kafka challenge
     // we stored everything in-memory on the single server
     // let inner_data = InnerData {
@@ -218,15 +753,52 @@ fn handler(runtime: Runtime) -> Handler {
     Handler {
         // storage: lin_kv(runtime),
         storage: seq_kv(runtime),
+        config: config.kafka.clone(),
+        cache: Arc::new(Mutex::new(Cache::default())),
+        forwarder: Forwarder::new(config.kafka.forward_timeout()),
+        appends: Arc::new(Mutex::new(HashMap::new())),
+        commits: Arc::new(Mutex::new(HashMap::new())),
     }
 }
 
-pub(crate) fn main() -> Result<()> {
-    Runtime::init(try_main())
-}
-
-async fn try_main() -> Result<()> {
-    let runtime = Runtime::new();
-    let handler = Arc::new(handler(runtime.clone()));
-    runtime.with_handler(handler).run().await
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let config = Config::load(Config::default())?;
+    log::init(&config.log);
+    if config.sim.enabled() {
+        // every storage hands out offsets in the order the sends happened
+        let sim = config.sim.clone();
+        let check =
+            |ops: &[serde_json::Value]| linearizability_checker::check_ops(Model::Append, ops);
+        return match config.kafka.storage {
+            KafkaStorage::Kv => {
+                simulate_checked(sim, move |runtime| handler(runtime, config.clone()), check).await
+            }
+            KafkaStorage::Consensus => {
+                let make_node = move |runtime| replicated::handler(runtime, config.clone());
+                simulate_checked(sim, make_node, check).await
+            }
+            KafkaStorage::Chain => {
+                let make_node = move |runtime| chain::handler(runtime, config.clone());
+                simulate_checked(sim, make_node, check).await
+            }
+        };
+    }
+    let runtime = Runtime::new()
+        .with_trace(config.trace.dir.clone())
+        .with_metrics(config.metrics.clone());
+    match config.kafka.storage {
+        KafkaStorage::Kv => {
+            let handler = handler(runtime.clone(), config);
+            runtime.run(handler).await
+        }
+        KafkaStorage::Consensus => {
+            let handler = replicated::handler(runtime.clone(), config);
+            runtime.run(handler).await
+        }
+        KafkaStorage::Chain => {
+            let handler = chain::handler(runtime.clone(), config);
+            runtime.run(handler).await
+        }
+    }
 }
@@ -0,0 +1,153 @@
+use crate::store::Store;
+use crate::{Request, Response};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use shared::config::{Config, KafkaConfig};
+use shared::consensus::{Replica, Role, Rpc, StateMachine};
+use shared::error::{code, RpcError};
+use shared::poll::PollBudget;
+use shared::{Message, Node, Runtime};
+use tracing::warn;
+
+// `storage = "consensus"`: the nodes replicate the logs themselves instead of keeping
+// them in seq-kv, with raft or multi-paxos (see `[consensus]`)
+//
+// every request is a command of the replicated log, polls and lists included, so all
+// of them are linearizable. followers forward requests to the leader
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum Body {
+    Consensus(Rpc<Request, Store>),
+    Client(Request),
+}
+
+// the store is also the snapshot
+struct Logs {
+    store: Store,
+    config: KafkaConfig,
+}
+
+impl StateMachine for Logs {
+    type Command = Request;
+    // the response to the request
+    type Output = Result<Response, RpcError>;
+    type Snapshot = Store;
+
+    fn apply(&mut self, request: &Request) -> Self::Output {
+        let store = &mut self.store;
+        let response = match request.clone() {
+            Request::Send {
+                msg_id, key, msg, ..
+            } => {
+                let log = store.logs.entry(key).or_default();
+                log.push(msg);
+                Response::SendOk {
+                    offset: log.len() - 1,
+                    in_reply_to: msg_id,
+                }
+            }
+            Request::Poll {
+                msg_id,
+                offsets,
+                max_messages,
+                max_bytes,
+            } => {
+                let budget = PollBudget::new(&self.config, max_messages, max_bytes);
+                Response::PollOk {
+                    msgs: store.poll(offsets, budget),
+                    in_reply_to: msg_id,
+                }
+            }
+            Request::CommitOffsets {
+                msg_id,
+                offsets,
+                group,
+            } => {
+                store.commit(group, offsets, false);
+                Response::CommitOffsetsOk {
+                    in_reply_to: msg_id,
+                }
+            }
+            Request::ListCommittedOffsets {
+                msg_id,
+                keys,
+                group,
+            } => Response::ListCommittedOffsetsOk {
+                offsets: store.committed(group.as_deref(), keys),
+                in_reply_to: msg_id,
+            },
+            Request::ListGroups { msg_id } => Response::ListGroupsOk {
+                groups: store.groups(),
+                in_reply_to: msg_id,
+            },
+            Request::ResetOffsets {
+                msg_id,
+                offsets,
+                group,
+            } => {
+                store.commit(group, offsets, true);
+                Response::ResetOffsetsOk {
+                    in_reply_to: msg_id,
+                }
+            }
+            Request::Error { .. } => {
+                let text = "errors are never proposed";
+                return Err(RpcError::new(code::NOT_SUPPORTED, text));
+            }
+        };
+        Ok(response)
+    }
+
+    fn snapshot(&self) -> Store {
+        self.store.clone()
+    }
+
+    fn restore(&mut self, snapshot: Store) {
+        self.store = snapshot;
+    }
+}
+
+pub struct Handler {
+    replica: Replica<Logs>,
+}
+
+#[async_trait]
+impl Node for Handler {
+    type Body = Body;
+
+    async fn init(&self, _runtime: Runtime) -> anyhow::Result<()> {
+        self.replica.start();
+        Ok(())
+    }
+
+    async fn process(&self, runtime: Runtime, req: Message<Body>) -> anyhow::Result<()> {
+        let request = match req.body {
+            Body::Consensus(rpc) => return self.replica.handle(&req.src, rpc),
+            Body::Client(Request::Error { text, .. }) => {
+                warn!("{}", text);
+                return Ok(());
+            }
+            Body::Client(request) => request,
+        };
+        if self.replica.role() != Role::Leader {
+            let msg_id = request.msg_id().unwrap_or_default();
+            return self
+                .replica
+                .forward(&runtime, &req.src, msg_id, &request)
+                .await;
+        }
+        let response = self.replica.propose(request).await??;
+        runtime.send(&req.src, response)
+    }
+}
+
+pub fn handler(runtime: Runtime, config: Config) -> Handler {
+    let logs = Logs {
+        store: Store::default(),
+        config: config.kafka,
+    };
+    Handler {
+        replica: Replica::new(config.consensus.algorithm, runtime, config.raft, logs),
+    }
+}
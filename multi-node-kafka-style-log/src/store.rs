@@ -0,0 +1,90 @@
+use crate::commit_key;
+use serde::{Deserialize, Serialize};
+use shared::poll::PollBudget;
+use std::collections::{BTreeSet, HashMap};
+
+// the logs and committed offsets of the storages keeping them in memory
+// (`consensus` and `chain`). there is no retention, every message is kept
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Store {
+    pub logs: HashMap<String, Vec<u64>>,
+    // by `commit_key(group, key)`, like in the kv store
+    committed: HashMap<String, u64>,
+    groups: BTreeSet<String>,
+}
+
+impl Store {
+    // a commit never moves an offset backwards (like on the single-node log), a reset
+    // sets it to whatever it is given. without retention that's all a reset does
+    pub fn commit(&mut self, group: Option<String>, offsets: HashMap<String, u64>, reset: bool) {
+        for (key, offset) in offsets {
+            let committed = self
+                .committed
+                .entry(commit_key(group.as_deref(), &key))
+                .or_insert(offset);
+            *committed = if reset {
+                offset
+            } else {
+                offset.max(*committed)
+            };
+        }
+        if let Some(group) = group {
+            self.groups.insert(group);
+        }
+    }
+
+    pub fn poll(
+        &self,
+        offsets: HashMap<String, usize>,
+        mut budget: PollBudget,
+    ) -> HashMap<String, Vec<Vec<u64>>> {
+        offsets
+            .into_iter()
+            .map(|(key, offset)| {
+                let log = self.logs.get(&key).map_or(&[][..], Vec::as_slice);
+                let entries = log
+                    .iter()
+                    .enumerate()
+                    .skip(offset)
+                    .map(|(offset, msg)| (offset as u64, *msg));
+                (key, budget.fill(entries))
+            })
+            .collect()
+    }
+
+    // keys nothing was committed for are at 0
+    pub fn committed(&self, group: Option<&str>, keys: Vec<String>) -> HashMap<String, u64> {
+        keys.into_iter()
+            .map(|key| {
+                let offset = self.committed.get(&commit_key(group, &key)).copied();
+                (key, offset.unwrap_or(0))
+            })
+            .collect()
+    }
+
+    pub fn groups(&self) -> Vec<String> {
+        self.groups.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commits_only_move_forward_and_resets_anywhere() {
+        let mut store = Store::default();
+        let group = Some("g".to_string());
+        let keys = || vec!["a".to_string(), "b".to_string()];
+        store.commit(group.clone(), HashMap::from([("a".to_string(), 5)]), false);
+        store.commit(group.clone(), HashMap::from([("a".to_string(), 3)]), false);
+        assert_eq!(store.committed(Some("g"), keys())["a"], 5);
+
+        store.commit(group, HashMap::from([("a".to_string(), 2)]), true);
+        let committed = store.committed(Some("g"), keys());
+        assert_eq!((committed["a"], committed["b"]), (2, 0));
+        // the default group is separate
+        assert_eq!(store.committed(None, keys())["a"], 0);
+        assert_eq!(store.groups(), ["g"]);
+    }
+}
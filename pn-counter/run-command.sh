@@ -0,0 +1,6 @@
+cargo build --release
+
+~/maelstrom/maelstrom/maelstrom test -w pn-counter --bin ~/distributed-systems-challenges/pn-counter/target/release/pn-counter --node-count 3 --rate 100 --time-limit 20 --nemesis partition
+
+# double check the read bounds of the run above
+~/distributed-systems-challenges/pn-counter/target/release/pn-counter-checker store/latest/history.txt
@@ -0,0 +1,283 @@
+use anyhow::{anyhow, Context};
+use std::collections::HashMap;
+use std::process::ExitCode;
+
+// checks the reads of a pn-counter run against the adds around them
+//
+// usage: pn-counter-checker store/latest/history.txt
+//
+// maelstrom writes one operation per line, in the order they happened:
+//   <process> <:invoke|:ok|:fail|:info> <:add|:read> <value>
+//
+// the counter is only eventually consistent, like in maelstrom's checker a read
+// (completed at `c`) may see any of the adds that didn't fail and were invoked before
+// `c`, whether they completed before the read or not. so its value has to lie within
+//   [sum of their negative deltas, sum of their positive deltas]
+// reads invoked after the last add completed are final reads, by then every node has
+// to have seen every add: they have to return the sum of the successful adds plus
+// any of the adds that timed out
+//   [ok + sum of negative timed out deltas, ok + sum of positive timed out deltas]
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Outcome {
+    Ok,
+    Fail,
+    // timed out / crashed, the add may have happened
+    Info,
+}
+
+#[derive(Debug)]
+struct Add {
+    delta: i64,
+    invoked: usize,
+    completed: Option<usize>,
+    outcome: Option<Outcome>,
+}
+
+#[derive(Debug)]
+struct Read {
+    process: String,
+    invoked: usize,
+    completed: usize,
+    value: i64,
+}
+
+#[derive(Debug)]
+struct History {
+    adds: Vec<Add>,
+    reads: Vec<Read>,
+}
+
+fn parse(contents: &str) -> anyhow::Result<History> {
+    let mut adds = Vec::new();
+    let mut reads = Vec::new();
+    // operations that were invoked but did not complete yet, by process
+    let mut open: HashMap<String, (String, usize, Option<usize>)> = HashMap::new();
+
+    for (index, line) in contents.lines().enumerate() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 4 || fields[0].parse::<u64>().is_err() {
+            // nemesis operations, empty lines, ...
+            continue;
+        }
+        let (process, kind, f, value) = (fields[0], fields[1], fields[2], fields[3]);
+        let line_no = index + 1;
+
+        if kind == ":invoke" {
+            let add = if f == ":add" {
+                let delta = value
+                    .parse()
+                    .with_context(|| format!("bad add delta on line {}", line_no))?;
+                adds.push(Add {
+                    delta,
+                    invoked: index,
+                    completed: None,
+                    outcome: None,
+                });
+                Some(adds.len() - 1)
+            } else {
+                None
+            };
+            open.insert(process.to_string(), (f.to_string(), index, add));
+            continue;
+        }
+
+        let outcome = match kind {
+            ":ok" => Outcome::Ok,
+            ":fail" => Outcome::Fail,
+            ":info" => Outcome::Info,
+            _ => return Err(anyhow!("unknown op type {} on line {}", kind, line_no)),
+        };
+        let (invoked_f, invoked, add) = open
+            .remove(process)
+            .ok_or_else(|| anyhow!("completion without invoke on line {}", line_no))?;
+        if invoked_f != f {
+            return Err(anyhow!(
+                "{} completes a {} on line {}",
+                f,
+                invoked_f,
+                line_no
+            ));
+        }
+
+        match add {
+            Some(add) => {
+                adds[add].completed = Some(index);
+                adds[add].outcome = Some(outcome);
+            }
+            None if f == ":read" && outcome == Outcome::Ok => reads.push(Read {
+                process: process.to_string(),
+                invoked,
+                completed: index,
+                value: value
+                    .parse()
+                    .with_context(|| format!("bad read value on line {}", line_no))?,
+            }),
+            None => {}
+        }
+    }
+    Ok(History { adds, reads })
+}
+
+// whether every add completed before `read` was invoked
+fn is_final(adds: &[Add], read: &Read) -> bool {
+    adds.iter().all(|add| {
+        add.completed
+            .is_some_and(|completed| completed < read.invoked)
+    })
+}
+
+// the range of values `read` is allowed to return
+fn bounds(adds: &[Add], read: &Read) -> (i64, i64) {
+    let (mut lower, mut upper) = (0, 0);
+    let is_final = is_final(adds, read);
+    for add in adds {
+        if add.outcome == Some(Outcome::Fail) || add.invoked > read.completed {
+            continue;
+        }
+        if is_final && add.outcome == Some(Outcome::Ok) {
+            lower += add.delta;
+            upper += add.delta;
+        } else if add.delta < 0 {
+            lower += add.delta;
+        } else {
+            upper += add.delta;
+        }
+    }
+    (lower, upper)
+}
+
+// the reads which returned a value out of their bounds and how many reads were final
+#[derive(Debug)]
+struct Report {
+    invalid: Vec<String>,
+    finals: usize,
+}
+
+fn check(history: &History) -> Report {
+    let mut report = Report {
+        invalid: Vec::new(),
+        finals: 0,
+    };
+    for read in &history.reads {
+        let is_final = is_final(&history.adds, read);
+        report.finals += is_final as usize;
+        let (lower, upper) = bounds(&history.adds, read);
+        if read.value < lower || read.value > upper {
+            report.invalid.push(format!(
+                "invalid {}read by process {} on line {}: {} not in [{}, {}]",
+                if is_final { "final " } else { "" },
+                read.process,
+                read.completed + 1,
+                read.value,
+                lower,
+                upper
+            ));
+        }
+    }
+    report
+}
+
+fn main() -> anyhow::Result<ExitCode> {
+    let path = std::env::args()
+        .nth(1)
+        .ok_or_else(|| anyhow!("usage: pn-counter-checker <history.txt>"))?;
+    let contents =
+        std::fs::read_to_string(&path).with_context(|| format!("failed to read {}", path))?;
+    let history = parse(&contents)?;
+
+    let report = check(&history);
+    for invalid in &report.invalid {
+        println!("{}", invalid);
+    }
+    let valid = report.invalid.is_empty();
+    println!(
+        "checked {} reads ({} final) against {} adds: {}",
+        history.reads.len(),
+        report.finals,
+        history.adds.len(),
+        if valid { "valid" } else { "INVALID" }
+    );
+    Ok(if valid {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_lines(lines: &[&str]) -> Report {
+        check(&parse(&lines.join("\n")).unwrap())
+    }
+
+    #[test]
+    fn parses_adds_and_reads() {
+        let history = parse(
+            "0\t:invoke\t:add\t5\n\
+             :nemesis\t:info\t:start-partition\tnil\n\
+             1\t:invoke\t:read\tnil\n\
+             0\t:fail\t:add\t5\n\
+             1\t:ok\t:read\t0\n",
+        )
+        .unwrap();
+        assert_eq!(history.adds.len(), 1);
+        assert_eq!(history.adds[0].outcome, Some(Outcome::Fail));
+        assert_eq!(history.adds[0].completed, Some(3));
+        assert_eq!(history.reads.len(), 1);
+        assert_eq!((history.reads[0].invoked, history.reads[0].value), (2, 0));
+    }
+
+    #[test]
+    fn rejects_malformed_histories() {
+        assert!(parse("0 :ok :read 1").is_err());
+        assert!(parse("0 :invoke :add 1\n0 :ok :read 1").is_err());
+        assert!(parse("0 :invoke :add x").is_err());
+    }
+
+    #[test]
+    fn intermediate_reads_may_see_any_concurrent_add() {
+        let report = check_lines(&[
+            "0 :invoke :add 5",
+            "1 :invoke :add -2",
+            "2 :invoke :read nil",
+            "2 :ok :read 5",
+            "0 :ok :add 5",
+            "2 :invoke :read nil",
+            "2 :ok :read -2",
+            "1 :info :add -2",
+        ]);
+        assert!(report.invalid.is_empty(), "{:?}", report.invalid);
+        assert_eq!(report.finals, 0);
+
+        let report = check_lines(&[
+            "0 :invoke :add 5",
+            "0 :ok :add 5",
+            "2 :invoke :read nil",
+            "2 :ok :read 6",
+        ]);
+        assert_eq!(report.invalid.len(), 1);
+    }
+
+    #[test]
+    fn final_reads_see_every_successful_add() {
+        let adds = [
+            "0 :invoke :add 5",
+            "0 :ok :add 5",
+            "1 :invoke :add 3",
+            "1 :fail :add 3",
+            "0 :invoke :add -1",
+            "0 :info :add -1",
+            "2 :invoke :read nil",
+        ];
+        for (value, valid) in [(5, true), (4, true), (0, false), (8, false)] {
+            let read = format!("2 :ok :read {}", value);
+            let lines: Vec<&str> = adds.iter().copied().chain([read.as_str()]).collect();
+            let report = check_lines(&lines);
+            assert_eq!(report.finals, 1);
+            assert_eq!(report.invalid.is_empty(), valid, "read {}", value);
+        }
+    }
+}
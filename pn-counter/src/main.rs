@@ -0,0 +1,147 @@
+use crate::Body::{AddOk, InternalMessage, ReadOk};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use shared::config::Config;
+use shared::crdt::{Crdt, PNCounter};
+use shared::log;
+use shared::sim::simulate;
+use shared::{Message, Node, Runtime};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::warn;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Body {
+    Read {
+        msg_id: u64,
+    },
+    ReadOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        value: i64,
+    },
+    Add {
+        msg_id: u64,
+        delta: i64,
+    },
+    AddOk {
+        msg_id: u64,
+        in_reply_to: u64,
+    },
+    Error {
+        in_reply_to: u64,
+        code: u64,
+        text: String,
+    },
+    InternalMessage {
+        msg_id: u64,
+        counter: PNCounter,
+    },
+}
+
+#[derive(Clone)]
+struct Handler {
+    // increments and decrements of every node, kept separately
+    // so merging the gossip of other nodes never loses a decrement
+    counter: Arc<Mutex<PNCounter>>,
+    my_msg_id: Arc<AtomicU64>,
+    tick: Duration,
+}
+
+impl Handler {
+    fn new(config: Config) -> Self {
+        Handler {
+            counter: Arc::new(Mutex::new(PNCounter::new())),
+            my_msg_id: Arc::new(AtomicU64::new(0)),
+            tick: config.counter.tick(),
+        }
+    }
+
+    // send this node's counter state to every other node in the cluster
+    fn gossip(&self, runtime: &Runtime) -> anyhow::Result<()> {
+        let counter = self.counter.lock().unwrap().clone();
+        for cluster_node in runtime.other_node_ids() {
+            let msg_id = self.my_msg_id.fetch_add(1, Ordering::Relaxed);
+            runtime.send(
+                cluster_node,
+                InternalMessage {
+                    msg_id,
+                    counter: counter.clone(),
+                },
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Node for Handler {
+    type Body = Body;
+
+    async fn init(&self, runtime: Runtime) -> anyhow::Result<()> {
+        let handler = self.clone();
+        let gossip_runtime = runtime.clone();
+        runtime.every(self.tick, move || {
+            let result = handler.gossip(&gossip_runtime);
+            async move { result }
+        });
+        Ok(())
+    }
+
+    // one last round, so the peers see the adds since the previous tick
+    async fn shutdown(&self, runtime: Runtime) -> anyhow::Result<()> {
+        self.gossip(&runtime)
+    }
+
+    async fn process(&self, runtime: Runtime, input: Message<Body>) -> anyhow::Result<()> {
+        match input.body {
+            Body::Read { msg_id } => {
+                let value = self.counter.lock().unwrap().value();
+                runtime.send(
+                    &input.src,
+                    ReadOk {
+                        msg_id,
+                        in_reply_to: msg_id,
+                        value,
+                    },
+                )?;
+            }
+            Body::Add { msg_id, delta } => {
+                self.counter.lock().unwrap().add(runtime.node_id(), delta);
+                runtime.send(
+                    &input.src,
+                    AddOk {
+                        msg_id,
+                        in_reply_to: msg_id,
+                    },
+                )?;
+            }
+            ReadOk { .. } | AddOk { .. } => {
+                warn!("impossible input");
+            }
+            Body::Error { text, .. } => {
+                warn!("{}", text);
+            }
+            Body::InternalMessage { counter, .. } => {
+                self.counter.lock().unwrap().merge(&counter);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let config = Config::load(Config::default())?;
+    log::init(&config.log);
+    if config.sim.enabled() {
+        return simulate(config.sim.clone(), move |_| Handler::new(config.clone())).await;
+    }
+    let runtime = Runtime::new()
+        .with_trace(config.trace.dir.clone())
+        .with_metrics(config.metrics.clone());
+    runtime.run(Handler::new(config)).await
+}
@@ -0,0 +1,920 @@
+use crate::gossip::Settings;
+use anyhow::{anyhow, Context};
+use clap::{Parser, ValueEnum};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// runtime configuration for all the challenge binaries
+//
+// every binary starts from its own defaults, which are then overridden (in this order) by
+// - a TOML file passed with `--config <path>` / `DSC_CONFIG=<path>`
+// - environment variables, e.g. `DSC_GOSSIP_LATENCY_TARGET_MS=1000`
+// - command line flags, e.g. `--gossip-latency-target-ms 1000`
+//
+// maelstrom starts the binaries without any arguments, so for experiments
+// the env variables (or a small wrapper script passing flags) are the easiest way in
+//
+// example config file:
+//
+//   [gossip]
+//   latency_target_ms = 1400
+//   max_batch = 50
+//
+//   [counter]
+//   mode = "seq-kv"
+//   tick_ms = 50
+//
+//   [kafka]
+//   poll_limit = 100
+//   data_dir = "/tmp/kafka"
+//   fsync = "interval"
+//   retention_ms = 60000
+//   retention_committed = true
+//
+//   [consensus]
+//   algorithm = "paxos"
+//
+//   [raft]
+//   election_timeout_ms = 1000
+//   heartbeat_ms = 100
+//
+//   [trace]
+//   dir = "/tmp/traces"
+//
+//   [metrics]
+//   interval_ms = 5000
+//   dir = "/tmp/metrics"
+//
+//   [log]
+//   filter = "info,shared::raft=debug"
+//
+//   [sim]
+//   requests = "requests.jsonl"
+//   nodes = 5
+//   loss = 0.1
+//   partition_ms = 500
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Config {
+    pub gossip: GossipConfig,
+    pub counter: CounterConfig,
+    pub kafka: KafkaConfig,
+    pub consensus: ConsensusConfig,
+    pub raft: RaftConfig,
+    pub trace: TraceConfig,
+    pub metrics: MetricsConfig,
+    pub log: LogConfig,
+    pub sim: SimConfig,
+}
+
+// broadcast binaries, see `gossip::Settings`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct GossipConfig {
+    pub latency_target_ms: u64,
+    pub min_interval_ms: u64,
+    pub idle_interval_ms: u64,
+    pub batch_target: usize,
+    pub max_batch: usize,
+    // how often the gossip thread checks whether it should flush
+    pub tick_ms: u64,
+}
+
+impl Default for GossipConfig {
+    fn default() -> Self {
+        let settings = Settings::default();
+        GossipConfig {
+            latency_target_ms: settings.latency_target.as_millis() as u64,
+            min_interval_ms: settings.min_interval.as_millis() as u64,
+            idle_interval_ms: settings.idle_interval.as_millis() as u64,
+            batch_target: settings.batch_target,
+            max_batch: settings.max_batch,
+            tick_ms: 10,
+        }
+    }
+}
+
+impl GossipConfig {
+    // `hops` depends on the topology the binary builds, so it is not configurable
+    pub fn settings(&self, hops: u32) -> Settings {
+        Settings {
+            latency_target: Duration::from_millis(self.latency_target_ms),
+            hops,
+            min_interval: Duration::from_millis(self.min_interval_ms),
+            idle_interval: Duration::from_millis(self.idle_interval_ms),
+            batch_target: self.batch_target,
+            max_batch: self.max_batch,
+        }
+    }
+
+    pub fn tick(&self) -> Duration {
+        Duration::from_millis(self.tick_ms)
+    }
+}
+
+// grow-only-counter, pn-counter
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CounterConfig {
+    // only used by grow-only-counter
+    pub mode: CounterMode,
+    // how often the latest value is gossiped to the other nodes
+    pub tick_ms: u64,
+}
+
+impl Default for CounterConfig {
+    fn default() -> Self {
+        CounterConfig {
+            mode: CounterMode::Gossip,
+            tick_ms: 20,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum CounterMode {
+    // every node gossips its state to all the others
+    Gossip,
+    // a single counter in maelstrom's seq-kv, updated with cas
+    SeqKv,
+}
+
+impl CounterConfig {
+    pub fn tick(&self) -> Duration {
+        Duration::from_millis(self.tick_ms)
+    }
+}
+
+// kafka-style logs
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct KafkaConfig {
+    // max messages returned per key in a single poll
+    pub poll_limit: usize,
+    // single-node log only: keep the log on disk below `<data_dir>/<node id>`,
+    // the log is in-memory only if this is not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data_dir: Option<PathBuf>,
+    pub fsync: FsyncPolicy,
+    // how often the log is synced with `fsync = "interval"`
+    pub fsync_interval_ms: u64,
+    // a new segment file is started once the current one reaches this size
+    pub segment_bytes: u64,
+    // retention, every enabled rule can drop a prefix of a key's log on its own
+    // - messages older than this (single-node only, the kv services have no timestamps)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retention_ms: Option<u64>,
+    // - all but the latest this many messages
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retention_messages: Option<u64>,
+    // - messages at or below the committed offset
+    pub retention_committed: bool,
+    // keep only the latest message for every message value, offsets stay as they are
+    // (single-node only)
+    pub compact: bool,
+    // how often retention and compaction run on the single-node log,
+    // the multi-node log applies retention right on every send / commit
+    pub retention_check_ms: u64,
+    // what `poll` does with an offset that was dropped by retention
+    pub truncated_poll: TruncatedPoll,
+    // multi-node log only: messages stored together under a single kv key
+    pub chunk_size: usize,
+    // multi-node log only: sends are handled by the node owning the key, the others
+    // forward them and fall back to the next node if the owner doesn't reply in time.
+    // the owner makes several 1s kv calls per send, so this has to be well above that.
+    // the timeout also applies to chain members talking to each other
+    pub forward: bool,
+    pub forward_timeout_ms: u64,
+    // multi-node log only: where the logs and offsets live
+    pub storage: KafkaStorage,
+}
+
+impl Default for KafkaConfig {
+    fn default() -> Self {
+        KafkaConfig {
+            poll_limit: 100,
+            data_dir: None,
+            fsync: FsyncPolicy::Interval,
+            fsync_interval_ms: 100,
+            segment_bytes: 1024 * 1024,
+            retention_ms: None,
+            retention_messages: None,
+            retention_committed: false,
+            compact: false,
+            retention_check_ms: 1000,
+            truncated_poll: TruncatedPoll::Advance,
+            chunk_size: 32,
+            forward: true,
+            forward_timeout_ms: 5000,
+            storage: KafkaStorage::Kv,
+        }
+    }
+}
+
+impl KafkaConfig {
+    pub fn fsync_interval(&self) -> Duration {
+        Duration::from_millis(self.fsync_interval_ms)
+    }
+
+    pub fn retention(&self) -> Option<Duration> {
+        self.retention_ms.map(Duration::from_millis)
+    }
+
+    pub fn retention_check(&self) -> Duration {
+        Duration::from_millis(self.retention_check_ms)
+    }
+
+    pub fn forward_timeout(&self) -> Duration {
+        Duration::from_millis(self.forward_timeout_ms)
+    }
+
+    // whether anything ever has to be dropped from the logs
+    pub fn retention_enabled(&self) -> bool {
+        self.retention_ms.is_some()
+            || self.retention_messages.is_some()
+            || self.retention_committed
+            || self.compact
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum TruncatedPoll {
+    // continue at the first message that is still there
+    Advance,
+    // fail the poll with an `offset truncated` error
+    Error,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum KafkaStorage {
+    // maelstrom's seq-kv service
+    Kv,
+    // a state machine replicated by the nodes themselves, see `[consensus]`
+    // (no retention, the logs are kept in memory in full)
+    Consensus,
+    // replicated along a chain of the nodes, the chain itself lives in lin-kv
+    // (no retention either)
+    Chain,
+}
+
+// when appended records are forced to disk
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum FsyncPolicy {
+    // before every send is acknowledged
+    Always,
+    // every `fsync_interval_ms`, a crash loses at most that much
+    Interval,
+    // whenever the OS decides to (and on shutdown)
+    Never,
+}
+
+// binaries replicating a state machine, see `consensus::Replica`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct ConsensusConfig {
+    pub algorithm: Algorithm,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Algorithm {
+    #[default]
+    Raft,
+    // multi-paxos, with the same timers as raft
+    Paxos,
+}
+
+// the timers of `raft::Raft`, `paxos::Paxos` uses them the same way
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RaftConfig {
+    // followers start an election after somewhere between this and twice this
+    // without hearing from a leader
+    pub election_timeout_ms: u64,
+    // how often the leader sends (possibly empty) append_entries to every follower
+    pub heartbeat_ms: u64,
+    // max log entries sent in a single append_entries
+    pub max_entries: usize,
+    // the log is compacted into a snapshot once this many entries were applied, 0 never does
+    pub snapshot_entries: u64,
+    // how long a proposed command may take to be committed
+    pub propose_timeout_ms: u64,
+}
+
+impl Default for RaftConfig {
+    fn default() -> Self {
+        RaftConfig {
+            election_timeout_ms: 600,
+            heartbeat_ms: 100,
+            max_entries: 100,
+            snapshot_entries: 1000,
+            propose_timeout_ms: 1000,
+        }
+    }
+}
+
+impl RaftConfig {
+    pub fn election_timeout(&self) -> Duration {
+        Duration::from_millis(self.election_timeout_ms)
+    }
+
+    pub fn heartbeat(&self) -> Duration {
+        Duration::from_millis(self.heartbeat_ms)
+    }
+
+    pub fn propose_timeout(&self) -> Duration {
+        Duration::from_millis(self.propose_timeout_ms)
+    }
+}
+
+// recording every message a node reads and writes, see `Runtime::with_trace`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct TraceConfig {
+    // every node writes `<node id>.jsonl` in here, nothing is recorded if not set
+    pub dir: Option<PathBuf>,
+}
+
+// counting messages, bytes, handler latencies and the output queue, see `metrics`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct MetricsConfig {
+    // how often the numbers are dumped, nothing is counted if not set
+    pub interval_ms: Option<u64>,
+    // every node writes `<node id>.jsonl` in here, they go to stderr if not set
+    pub dir: Option<PathBuf>,
+}
+
+impl MetricsConfig {
+    pub fn enabled(&self) -> bool {
+        self.interval_ms.is_some_and(|ms| ms > 0)
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms.unwrap_or_default())
+    }
+}
+
+// what ends up on stderr, see `log`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct LogConfig {
+    // `tracing_subscriber::EnvFilter` directives: a default level, then levels per
+    // module, e.g. "warn,shared::runtime=debug,multi_node_kafka_style_log::chain=info"
+    pub filter: String,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            filter: "info".to_string(),
+        }
+    }
+}
+
+// the deterministic simulator, see `sim`. binaries run under it instead of talking
+// to maelstrom when `requests` is set
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SimConfig {
+    // client request bodies, one json object per line, sent in file order
+    pub requests: Option<PathBuf>,
+    // decides everything random in a run, a fresh one is picked (and printed) if not set
+    pub seed: Option<u64>,
+    // how many seeds to try, starting at `seed`, stopping at the first failing run
+    pub runs: u64,
+    pub nodes: usize,
+    // clients sending the requests concurrently
+    pub clients: usize,
+    // a client pauses between 0 and twice this before every request
+    pub interval_ms: u64,
+    // every message takes between 0 and this long
+    pub latency_ms: u64,
+    // share of the messages between nodes which is dropped, 0.0 to 1.0
+    pub loss: f64,
+    // how long a client waits for a reply before sending its next request
+    pub timeout_ms: u64,
+    // how long the nodes keep running after the last reply, e.g. to finish gossiping
+    pub settle_ms: u64,
+    // every this often one random node is cut off from the other nodes for as long,
+    // until the last reply. 0 never partitions the network
+    pub partition_ms: u64,
+    // every message of the (last) run as json lines, with the virtual send and
+    // receive times
+    pub history: Option<PathBuf>,
+    // the clients' operations of the (last) run, as `linearizability-checker --json`
+    // reads them
+    pub ops: Option<PathBuf>,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        SimConfig {
+            requests: None,
+            seed: None,
+            runs: 1,
+            nodes: 3,
+            clients: 2,
+            interval_ms: 20,
+            latency_ms: 10,
+            loss: 0.0,
+            timeout_ms: 1000,
+            settle_ms: 1000,
+            partition_ms: 0,
+            history: None,
+            ops: None,
+        }
+    }
+}
+
+impl SimConfig {
+    pub fn enabled(&self) -> bool {
+        self.requests.is_some()
+    }
+
+    pub fn latency(&self) -> Duration {
+        Duration::from_millis(self.latency_ms)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+
+    pub fn settle(&self) -> Duration {
+        Duration::from_millis(self.settle_ms)
+    }
+
+    pub fn partition(&self) -> Duration {
+        Duration::from_millis(self.partition_ms)
+    }
+}
+
+#[derive(Parser, Debug)]
+#[command(about = "maelstrom node for the fly.io distributed systems challenges")]
+struct Args {
+    /// path of a TOML config file
+    #[arg(long, env = "DSC_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// how long a broadcast may take to reach every node
+    #[arg(long, env = "DSC_GOSSIP_LATENCY_TARGET_MS")]
+    gossip_latency_target_ms: Option<u64>,
+    /// never flush gossip more often than this
+    #[arg(long, env = "DSC_GOSSIP_MIN_INTERVAL_MS")]
+    gossip_min_interval_ms: Option<u64>,
+    /// anti-entropy flush interval when nothing new arrived
+    #[arg(long, env = "DSC_GOSSIP_IDLE_INTERVAL_MS")]
+    gossip_idle_interval_ms: Option<u64>,
+    /// new messages we would like to carry in every flush
+    #[arg(long, env = "DSC_GOSSIP_BATCH_TARGET")]
+    gossip_batch_target: Option<usize>,
+    /// upper bound of new messages carried in a single flush
+    #[arg(long, env = "DSC_GOSSIP_MAX_BATCH")]
+    gossip_max_batch: Option<usize>,
+    /// how often the gossip thread checks whether it should flush
+    #[arg(long, env = "DSC_GOSSIP_TICK_MS")]
+    gossip_tick_ms: Option<u64>,
+
+    /// where grow-only-counter keeps its value
+    #[arg(long, env = "DSC_COUNTER_MODE", value_enum)]
+    counter_mode: Option<CounterMode>,
+    /// how often the counter value is gossiped
+    #[arg(long, env = "DSC_COUNTER_TICK_MS")]
+    counter_tick_ms: Option<u64>,
+
+    /// max messages returned per key in a single poll
+    #[arg(long, env = "DSC_KAFKA_POLL_LIMIT")]
+    kafka_poll_limit: Option<usize>,
+    /// directory for the single-node log segments (in-memory if not set)
+    #[arg(long, env = "DSC_KAFKA_DATA_DIR")]
+    kafka_data_dir: Option<PathBuf>,
+    /// when appended records are forced to disk
+    #[arg(long, env = "DSC_KAFKA_FSYNC", value_enum)]
+    kafka_fsync: Option<FsyncPolicy>,
+    /// how often the log is synced with `--kafka-fsync interval`
+    #[arg(long, env = "DSC_KAFKA_FSYNC_INTERVAL_MS")]
+    kafka_fsync_interval_ms: Option<u64>,
+    /// max size of a single log segment file
+    #[arg(long, env = "DSC_KAFKA_SEGMENT_BYTES")]
+    kafka_segment_bytes: Option<u64>,
+    /// drop messages older than this
+    #[arg(long, env = "DSC_KAFKA_RETENTION_MS")]
+    kafka_retention_ms: Option<u64>,
+    /// keep at most this many messages per key
+    #[arg(long, env = "DSC_KAFKA_RETENTION_MESSAGES")]
+    kafka_retention_messages: Option<u64>,
+    /// drop messages at or below the committed offset
+    #[arg(long, env = "DSC_KAFKA_RETENTION_COMMITTED")]
+    kafka_retention_committed: Option<bool>,
+    /// keep only the latest message for every message value
+    #[arg(long, env = "DSC_KAFKA_COMPACT")]
+    kafka_compact: Option<bool>,
+    /// how often retention and compaction run
+    #[arg(long, env = "DSC_KAFKA_RETENTION_CHECK_MS")]
+    kafka_retention_check_ms: Option<u64>,
+    /// what poll does with an offset that was dropped by retention
+    #[arg(long, env = "DSC_KAFKA_TRUNCATED_POLL", value_enum)]
+    kafka_truncated_poll: Option<TruncatedPoll>,
+    /// messages stored under a single kv key by the multi-node log
+    #[arg(long, env = "DSC_KAFKA_CHUNK_SIZE")]
+    kafka_chunk_size: Option<usize>,
+    /// forward sends to the node owning the key
+    #[arg(long, env = "DSC_KAFKA_FORWARD")]
+    kafka_forward: Option<bool>,
+    /// how long to wait for the owner of a key (and then the next node) before giving up
+    #[arg(long, env = "DSC_KAFKA_FORWARD_TIMEOUT_MS")]
+    kafka_forward_timeout_ms: Option<u64>,
+    /// where the multi-node log keeps the logs and offsets
+    #[arg(long, env = "DSC_KAFKA_STORAGE", value_enum)]
+    kafka_storage: Option<KafkaStorage>,
+
+    /// consensus algorithm replicating the state machine
+    #[arg(long, env = "DSC_CONSENSUS_ALGORITHM", value_enum)]
+    consensus_algorithm: Option<Algorithm>,
+
+    /// how long followers wait for a leader before starting an election
+    #[arg(long, env = "DSC_RAFT_ELECTION_TIMEOUT_MS")]
+    raft_election_timeout_ms: Option<u64>,
+    /// how often the raft leader sends heartbeats
+    #[arg(long, env = "DSC_RAFT_HEARTBEAT_MS")]
+    raft_heartbeat_ms: Option<u64>,
+    /// max log entries sent in a single append_entries
+    #[arg(long, env = "DSC_RAFT_MAX_ENTRIES")]
+    raft_max_entries: Option<usize>,
+    /// applied entries between two snapshots (0 never takes one)
+    #[arg(long, env = "DSC_RAFT_SNAPSHOT_ENTRIES")]
+    raft_snapshot_entries: Option<u64>,
+    /// how long a proposed command may take to be committed
+    #[arg(long, env = "DSC_RAFT_PROPOSE_TIMEOUT_MS")]
+    raft_propose_timeout_ms: Option<u64>,
+
+    /// directory receiving a trace of every message in and out, per node
+    #[arg(long, env = "DSC_TRACE_DIR")]
+    trace_dir: Option<PathBuf>,
+
+    /// dump per-node metrics this often
+    #[arg(long, env = "DSC_METRICS_INTERVAL_MS")]
+    metrics_interval_ms: Option<u64>,
+    /// directory receiving the metrics dumps, per node, instead of stderr
+    #[arg(long, env = "DSC_METRICS_DIR")]
+    metrics_dir: Option<PathBuf>,
+
+    /// log levels, e.g. "info,shared::runtime=debug" (debug logs every message)
+    #[arg(long, env = "DSC_LOG_FILTER")]
+    log_filter: Option<String>,
+
+    /// run under the simulator, sending the request bodies in this file
+    #[arg(long, env = "DSC_SIM_REQUESTS")]
+    sim_requests: Option<PathBuf>,
+    /// seed of the (first) simulated run
+    #[arg(long, env = "DSC_SIM_SEED")]
+    sim_seed: Option<u64>,
+    /// simulated runs with consecutive seeds, stopping at the first failure
+    #[arg(long, env = "DSC_SIM_RUNS")]
+    sim_runs: Option<u64>,
+    /// simulated nodes
+    #[arg(long, env = "DSC_SIM_NODES")]
+    sim_nodes: Option<usize>,
+    /// simulated clients sending requests concurrently
+    #[arg(long, env = "DSC_SIM_CLIENTS")]
+    sim_clients: Option<usize>,
+    /// average pause of a simulated client between two requests
+    #[arg(long, env = "DSC_SIM_INTERVAL_MS")]
+    sim_interval_ms: Option<u64>,
+    /// max delay of a simulated message
+    #[arg(long, env = "DSC_SIM_LATENCY_MS")]
+    sim_latency_ms: Option<u64>,
+    /// share of simulated messages between nodes which is dropped
+    #[arg(long, env = "DSC_SIM_LOSS")]
+    sim_loss: Option<f64>,
+    /// how long a simulated client waits for a reply
+    #[arg(long, env = "DSC_SIM_TIMEOUT_MS")]
+    sim_timeout_ms: Option<u64>,
+    /// how long the simulated nodes keep running after the last reply
+    #[arg(long, env = "DSC_SIM_SETTLE_MS")]
+    sim_settle_ms: Option<u64>,
+    /// how often and for how long a simulated node is cut off from the others
+    #[arg(long, env = "DSC_SIM_PARTITION_MS")]
+    sim_partition_ms: Option<u64>,
+    /// file receiving every simulated message
+    #[arg(long, env = "DSC_SIM_HISTORY")]
+    sim_history: Option<PathBuf>,
+    /// file receiving the operations of the simulated clients
+    #[arg(long, env = "DSC_SIM_OPS")]
+    sim_ops: Option<PathBuf>,
+}
+
+impl Config {
+    // reads the config for this process on top of the binary's `defaults`
+    pub fn load(defaults: Config) -> anyhow::Result<Config> {
+        let args = Args::parse();
+        let mut config = match &args.config {
+            Some(path) => Self::merge_file(defaults, path)?,
+            None => defaults,
+        };
+        args.apply(&mut config);
+        config.validate()?;
+        Ok(config)
+    }
+
+    // values the binaries can't run with, e.g. a zero period makes `Runtime::every` panic
+    fn validate(&self) -> anyhow::Result<()> {
+        let positive = [
+            ("gossip.tick_ms", self.gossip.tick_ms),
+            ("counter.tick_ms", self.counter.tick_ms),
+            ("kafka.fsync_interval_ms", self.kafka.fsync_interval_ms),
+            ("kafka.retention_check_ms", self.kafka.retention_check_ms),
+            ("kafka.forward_timeout_ms", self.kafka.forward_timeout_ms),
+            ("kafka.chunk_size", self.kafka.chunk_size as u64),
+            ("kafka.poll_limit", self.kafka.poll_limit as u64),
+            ("raft.election_timeout_ms", self.raft.election_timeout_ms),
+            ("raft.heartbeat_ms", self.raft.heartbeat_ms),
+            ("raft.propose_timeout_ms", self.raft.propose_timeout_ms),
+            ("sim.runs", self.sim.runs),
+            ("sim.nodes", self.sim.nodes as u64),
+            ("sim.clients", self.sim.clients as u64),
+            ("sim.timeout_ms", self.sim.timeout_ms),
+        ];
+        for (name, value) in positive {
+            if value == 0 {
+                return Err(anyhow!("{} must be greater than 0", name));
+            }
+        }
+        if self.metrics.interval_ms == Some(0) {
+            return Err(anyhow!(
+                "metrics.interval_ms must be greater than 0, leave it unset to disable metrics"
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.sim.loss) {
+            return Err(anyhow!(
+                "sim.loss must be between 0.0 and 1.0, got {}",
+                self.sim.loss
+            ));
+        }
+        let gossip = &self.gossip;
+        // the scheduler clamps the batch size and the flush interval between these
+        if gossip.batch_target > gossip.max_batch {
+            return Err(anyhow!(
+                "gossip.batch_target ({}) must not be greater than gossip.max_batch ({})",
+                gossip.batch_target,
+                gossip.max_batch
+            ));
+        }
+        if gossip.min_interval_ms > gossip.latency_target_ms {
+            return Err(anyhow!(
+                "gossip.min_interval_ms ({}) must not be greater than gossip.latency_target_ms ({})",
+                gossip.min_interval_ms,
+                gossip.latency_target_ms
+            ));
+        }
+        Ok(())
+    }
+
+    // only the keys present in the file override the defaults
+    fn merge_file(defaults: Config, path: &Path) -> anyhow::Result<Config> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config file {}", path.display()))?;
+        let overrides: toml::Table = toml::from_str(&contents)
+            .with_context(|| format!("failed to parse config file {}", path.display()))?;
+        let mut merged = toml::Table::try_from(defaults)?;
+        merge_tables(&mut merged, overrides);
+        Ok(merged.try_into()?)
+    }
+}
+
+fn merge_tables(base: &mut toml::Table, overrides: toml::Table) {
+    for (key, value) in overrides {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(value)) => {
+                merge_tables(base, value)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+impl Args {
+    fn apply(self, config: &mut Config) {
+        let gossip = &mut config.gossip;
+        if let Some(value) = self.gossip_latency_target_ms {
+            gossip.latency_target_ms = value;
+        }
+        if let Some(value) = self.gossip_min_interval_ms {
+            gossip.min_interval_ms = value;
+        }
+        if let Some(value) = self.gossip_idle_interval_ms {
+            gossip.idle_interval_ms = value;
+        }
+        if let Some(value) = self.gossip_batch_target {
+            gossip.batch_target = value;
+        }
+        if let Some(value) = self.gossip_max_batch {
+            gossip.max_batch = value;
+        }
+        if let Some(value) = self.gossip_tick_ms {
+            gossip.tick_ms = value;
+        }
+        if let Some(value) = self.counter_mode {
+            config.counter.mode = value;
+        }
+        if let Some(value) = self.counter_tick_ms {
+            config.counter.tick_ms = value;
+        }
+        let kafka = &mut config.kafka;
+        if let Some(value) = self.kafka_poll_limit {
+            kafka.poll_limit = value;
+        }
+        if let Some(value) = self.kafka_data_dir {
+            kafka.data_dir = Some(value);
+        }
+        if let Some(value) = self.kafka_fsync {
+            kafka.fsync = value;
+        }
+        if let Some(value) = self.kafka_fsync_interval_ms {
+            kafka.fsync_interval_ms = value;
+        }
+        if let Some(value) = self.kafka_segment_bytes {
+            kafka.segment_bytes = value;
+        }
+        if let Some(value) = self.kafka_retention_ms {
+            kafka.retention_ms = Some(value);
+        }
+        if let Some(value) = self.kafka_retention_messages {
+            kafka.retention_messages = Some(value);
+        }
+        if let Some(value) = self.kafka_retention_committed {
+            kafka.retention_committed = value;
+        }
+        if let Some(value) = self.kafka_compact {
+            kafka.compact = value;
+        }
+        if let Some(value) = self.kafka_retention_check_ms {
+            kafka.retention_check_ms = value;
+        }
+        if let Some(value) = self.kafka_truncated_poll {
+            kafka.truncated_poll = value;
+        }
+        if let Some(value) = self.kafka_chunk_size {
+            kafka.chunk_size = value;
+        }
+        if let Some(value) = self.kafka_forward {
+            kafka.forward = value;
+        }
+        if let Some(value) = self.kafka_forward_timeout_ms {
+            kafka.forward_timeout_ms = value;
+        }
+        if let Some(value) = self.kafka_storage {
+            kafka.storage = value;
+        }
+        if let Some(value) = self.consensus_algorithm {
+            config.consensus.algorithm = value;
+        }
+        let raft = &mut config.raft;
+        if let Some(value) = self.raft_election_timeout_ms {
+            raft.election_timeout_ms = value;
+        }
+        if let Some(value) = self.raft_heartbeat_ms {
+            raft.heartbeat_ms = value;
+        }
+        if let Some(value) = self.raft_max_entries {
+            raft.max_entries = value;
+        }
+        if let Some(value) = self.raft_snapshot_entries {
+            raft.snapshot_entries = value;
+        }
+        if let Some(value) = self.raft_propose_timeout_ms {
+            raft.propose_timeout_ms = value;
+        }
+        if let Some(value) = self.trace_dir {
+            config.trace.dir = Some(value);
+        }
+        if let Some(value) = self.metrics_interval_ms {
+            config.metrics.interval_ms = Some(value);
+        }
+        if let Some(value) = self.metrics_dir {
+            config.metrics.dir = Some(value);
+        }
+        if let Some(value) = self.log_filter {
+            config.log.filter = value;
+        }
+        let sim = &mut config.sim;
+        if let Some(value) = self.sim_requests {
+            sim.requests = Some(value);
+        }
+        if let Some(value) = self.sim_seed {
+            sim.seed = Some(value);
+        }
+        if let Some(value) = self.sim_runs {
+            sim.runs = value;
+        }
+        if let Some(value) = self.sim_nodes {
+            sim.nodes = value;
+        }
+        if let Some(value) = self.sim_clients {
+            sim.clients = value;
+        }
+        if let Some(value) = self.sim_interval_ms {
+            sim.interval_ms = value;
+        }
+        if let Some(value) = self.sim_latency_ms {
+            sim.latency_ms = value;
+        }
+        if let Some(value) = self.sim_loss {
+            sim.loss = value;
+        }
+        if let Some(value) = self.sim_timeout_ms {
+            sim.timeout_ms = value;
+        }
+        if let Some(value) = self.sim_settle_ms {
+            sim.settle_ms = value;
+        }
+        if let Some(value) = self.sim_partition_ms {
+            sim.partition_ms = value;
+        }
+        if let Some(value) = self.sim_history {
+            sim.history = Some(value);
+        }
+        if let Some(value) = self.sim_ops {
+            sim.ops = Some(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rejects(change: impl FnOnce(&mut Config), message: &str) {
+        let mut config = Config::default();
+        change(&mut config);
+        let err = config.validate().unwrap_err().to_string();
+        assert!(
+            err.contains(message),
+            "{:?} doesn't mention {:?}",
+            err,
+            message
+        );
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        Config::default().validate().unwrap();
+    }
+
+    #[test]
+    fn rejects_zero_periods() {
+        rejects(|config| config.gossip.tick_ms = 0, "gossip.tick_ms");
+        rejects(|config| config.raft.heartbeat_ms = 0, "raft.heartbeat_ms");
+        rejects(
+            |config| config.metrics.interval_ms = Some(0),
+            "metrics.interval_ms",
+        );
+    }
+
+    #[test]
+    fn rejects_a_loss_outside_of_0_and_1() {
+        rejects(|config| config.sim.loss = 1.5, "sim.loss");
+        rejects(|config| config.sim.loss = -0.1, "sim.loss");
+    }
+
+    #[test]
+    fn rejects_inverted_gossip_bounds() {
+        rejects(
+            |config| {
+                config.gossip.batch_target = 20;
+                config.gossip.max_batch = 10;
+            },
+            "gossip.batch_target",
+        );
+        rejects(
+            |config| {
+                config.gossip.min_interval_ms = 500;
+                config.gossip.latency_target_ms = 100;
+            },
+            "gossip.min_interval_ms",
+        );
+    }
+
+    #[test]
+    fn file_overrides_only_the_keys_it_sets() {
+        let path = std::env::temp_dir().join(format!("dsc-config-{}.toml", std::process::id()));
+        std::fs::write(&path, "[gossip]\nmax_batch = 7\n").unwrap();
+        let mut defaults = Config::default();
+        defaults.gossip.batch_target = 3;
+        let config = Config::merge_file(defaults, &path);
+        std::fs::remove_file(&path).unwrap();
+
+        let config = config.unwrap();
+        assert_eq!(config.gossip.max_batch, 7);
+        assert_eq!(config.gossip.batch_target, 3);
+        assert_eq!(config.gossip.tick_ms, GossipConfig::default().tick_ms);
+    }
+}
@@ -0,0 +1,156 @@
+use crate::config::{Algorithm, RaftConfig};
+use crate::error::{code, RpcError};
+use crate::paxos::{self, Paxos};
+use crate::raft::{self, Raft};
+use crate::runtime::Runtime;
+use anyhow::anyhow;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// replicated state machines, independent of the consensus algorithm
+//
+// a binary implements `StateMachine` and talks to a `Replica`, which runs either
+// `raft::Raft` or `paxos::Paxos` depending on `[consensus] algorithm`
+
+pub trait StateMachine: Send + 'static {
+    type Command: Serialize + DeserializeOwned + Clone + Send + Sync + 'static;
+    type Output: Send + 'static;
+    type Snapshot: Serialize + DeserializeOwned + Clone + Send + Sync + 'static;
+
+    // called with every committed command, in log order, on every node
+    fn apply(&mut self, command: &Self::Command) -> Self::Output;
+    // the state after applying every command so far
+    fn snapshot(&self) -> Self::Snapshot;
+    // replace the state with a snapshot taken by `snapshot` (on another node)
+    fn restore(&mut self, snapshot: Self::Snapshot);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+// the messages replicas send each other, the message types of both algorithms differ
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+#[serde(bound = "C: Serialize + DeserializeOwned, S: Serialize + DeserializeOwned")]
+pub enum Rpc<C, S> {
+    Raft(raft::Rpc<C, S>),
+    Paxos(paxos::Rpc<C, S>),
+}
+
+pub enum Replica<M: StateMachine> {
+    Raft(Raft<M>),
+    Paxos(Paxos<M>),
+}
+
+impl<M: StateMachine> Clone for Replica<M> {
+    fn clone(&self) -> Self {
+        match self {
+            Replica::Raft(raft) => Replica::Raft(raft.clone()),
+            Replica::Paxos(paxos) => Replica::Paxos(paxos.clone()),
+        }
+    }
+}
+
+impl<M: StateMachine> Replica<M> {
+    pub fn new(algorithm: Algorithm, runtime: Runtime, config: RaftConfig, machine: M) -> Self {
+        match algorithm {
+            Algorithm::Raft => Replica::Raft(Raft::new(runtime, config, machine)),
+            Algorithm::Paxos => Replica::Paxos(Paxos::new(runtime, config, machine)),
+        }
+    }
+
+    // start the timers, call this from `Node::init`
+    pub fn start(&self) {
+        match self {
+            Replica::Raft(raft) => raft.start(),
+            Replica::Paxos(paxos) => paxos.start(),
+        }
+    }
+
+    pub fn role(&self) -> Role {
+        match self {
+            Replica::Raft(raft) => raft.role(),
+            Replica::Paxos(paxos) => paxos.role(),
+        }
+    }
+
+    // the current leader, as far as this node knows
+    pub fn leader(&self) -> Option<String> {
+        match self {
+            Replica::Raft(raft) => raft.leader(),
+            Replica::Paxos(paxos) => paxos.leader(),
+        }
+    }
+
+    // replicate `command` and wait until it is applied, only works on the leader,
+    // see `Raft::propose` for the errors
+    pub async fn propose(&self, command: M::Command) -> anyhow::Result<M::Output> {
+        match self {
+            Replica::Raft(raft) => raft.propose(command).await,
+            Replica::Paxos(paxos) => paxos.propose(command).await,
+        }
+    }
+
+    // answer a request of another replica
+    pub fn handle(&self, src: &str, rpc: Rpc<M::Command, M::Snapshot>) -> anyhow::Result<()> {
+        match (self, rpc) {
+            (Replica::Raft(raft), Rpc::Raft(rpc)) => raft.handle(src, rpc),
+            (Replica::Paxos(paxos), Rpc::Paxos(rpc)) => paxos.handle(src, rpc),
+            _ => Err(anyhow!("{} runs another consensus algorithm", src)),
+        }
+    }
+
+    // proxy the client `request` to the leader and relay its reply to `src`,
+    // a request another node forwarded is never passed on
+    pub async fn forward<T: Serialize>(
+        &self,
+        runtime: &Runtime,
+        src: &str,
+        msg_id: u64,
+        request: &T,
+    ) -> anyhow::Result<()> {
+        let leader = match self.leader() {
+            Some(leader) if !runtime.node_ids().iter().any(|node| node == src) => leader,
+            _ => {
+                let text = "not the leader and no leader to forward to";
+                return Err(RpcError::new(code::TEMPORARILY_UNAVAILABLE, text).into());
+            }
+        };
+        let mut reply: Value = runtime.call(&leader, request).await?;
+        reply["in_reply_to"] = msg_id.into();
+        runtime.send(src, reply)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::StateMachine;
+
+    // appends every command, the output is the length of the log
+    #[derive(Debug, Default)]
+    pub struct Log(pub Vec<u64>);
+
+    impl StateMachine for Log {
+        type Command = u64;
+        type Output = usize;
+        type Snapshot = Vec<u64>;
+
+        fn apply(&mut self, command: &u64) -> usize {
+            self.0.push(*command);
+            self.0.len()
+        }
+
+        fn snapshot(&self) -> Vec<u64> {
+            self.0.clone()
+        }
+
+        fn restore(&mut self, snapshot: Vec<u64>) {
+            self.0 = snapshot;
+        }
+    }
+}
@@ -0,0 +1,541 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::hash::Hash;
+
+// state-based CRDTs (convergent replicated data types)
+//
+// every replica updates its own copy and ships the whole state to the others,
+// `merge` is commutative, associative and idempotent, so replicas converge no matter
+// how often the states are sent, in which order they arrive or whether some are lost
+//
+// - GCounter    : grow-only counter, one entry per node
+// - PNCounter   : counter which can also decrease (two GCounters)
+// - GSet        : grow-only set
+// - TwoPSet     : set with removes, a removed element can never be added again
+// - ORSet       : observed-remove set, re-adding after a remove works
+// - LwwRegister : single value, the latest write (by timestamp) wins
+// - MvRegister  : keeps all concurrently written values
+
+pub trait Crdt {
+    // fold `other` into `self`
+    fn merge(&mut self, other: &Self);
+}
+
+// per-node counters, used by the counters and as a version vector
+pub type VersionVector = BTreeMap<String, u64>;
+
+// `a` has seen everything `b` has seen
+fn dominates(a: &VersionVector, b: &VersionVector) -> bool {
+    b.iter()
+        .all(|(node, count)| a.get(node).copied().unwrap_or(0) >= *count)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, Hash)]
+#[serde(transparent)]
+pub struct GCounter {
+    counts: VersionVector,
+}
+
+impl GCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // only `node` itself may increment its own entry
+    pub fn increment(&mut self, node: &str, delta: u64) {
+        *self.counts.entry(node.to_string()).or_insert(0) += delta;
+    }
+
+    pub fn value(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    // the part of the value contributed by `node`
+    pub fn get(&self, node: &str) -> u64 {
+        self.counts.get(node).copied().unwrap_or(0)
+    }
+
+    pub fn counts(&self) -> &VersionVector {
+        &self.counts
+    }
+}
+
+impl Crdt for GCounter {
+    fn merge(&mut self, other: &Self) {
+        for (node, count) in &other.counts {
+            let entry = self.counts.entry(node.clone()).or_insert(0);
+            *entry = (*entry).max(*count);
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct PNCounter {
+    increments: GCounter,
+    decrements: GCounter,
+}
+
+impl PNCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, node: &str, delta: i64) {
+        if delta >= 0 {
+            self.increments.increment(node, delta as u64);
+        } else {
+            self.decrements.increment(node, delta.unsigned_abs());
+        }
+    }
+
+    pub fn value(&self) -> i64 {
+        self.increments.value() as i64 - self.decrements.value() as i64
+    }
+
+    pub fn increments(&self) -> &GCounter {
+        &self.increments
+    }
+
+    pub fn decrements(&self) -> &GCounter {
+        &self.decrements
+    }
+}
+
+impl Crdt for PNCounter {
+    fn merge(&mut self, other: &Self) {
+        self.increments.merge(&other.increments);
+        self.decrements.merge(&other.decrements);
+    }
+}
+
+// serialized as a plain json array, so it can replace a `HashSet` on the wire
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(transparent)]
+#[serde(bound(
+    serialize = "T: Serialize",
+    deserialize = "T: Deserialize<'de> + Eq + Hash"
+))]
+pub struct GSet<T: Eq + Hash> {
+    elements: HashSet<T>,
+}
+
+impl<T: Eq + Hash> Default for GSet<T> {
+    fn default() -> Self {
+        GSet {
+            elements: HashSet::new(),
+        }
+    }
+}
+
+impl<T: Eq + Hash> GSet<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // returns whether the element is new
+    pub fn insert(&mut self, value: T) -> bool {
+        self.elements.insert(value)
+    }
+
+    pub fn contains(&self, value: &T) -> bool {
+        self.elements.contains(value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.elements.iter()
+    }
+
+    pub fn elements(&self) -> &HashSet<T> {
+        &self.elements
+    }
+}
+
+impl<T: Eq + Hash + Clone> Crdt for GSet<T> {
+    fn merge(&mut self, other: &Self) {
+        self.elements.extend(other.elements.iter().cloned());
+    }
+}
+
+impl<T: Eq + Hash> FromIterator<T> for GSet<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        GSet {
+            elements: iter.into_iter().collect(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(bound(
+    serialize = "T: Serialize",
+    deserialize = "T: Deserialize<'de> + Eq + Hash"
+))]
+pub struct TwoPSet<T: Eq + Hash> {
+    added: GSet<T>,
+    removed: GSet<T>,
+}
+
+impl<T: Eq + Hash> Default for TwoPSet<T> {
+    fn default() -> Self {
+        TwoPSet {
+            added: GSet::new(),
+            removed: GSet::new(),
+        }
+    }
+}
+
+impl<T: Eq + Hash + Clone> TwoPSet<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // returns false if the element was removed before, it can't come back
+    pub fn insert(&mut self, value: T) -> bool {
+        if self.removed.contains(&value) {
+            return false;
+        }
+        self.added.insert(value)
+    }
+
+    // only elements which were added can be removed
+    pub fn remove(&mut self, value: &T) -> bool {
+        if !self.added.contains(value) {
+            return false;
+        }
+        self.removed.insert(value.clone())
+    }
+
+    pub fn contains(&self, value: &T) -> bool {
+        self.added.contains(value) && !self.removed.contains(value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.added
+            .iter()
+            .filter(|value| !self.removed.contains(value))
+    }
+}
+
+impl<T: Eq + Hash + Clone> Crdt for TwoPSet<T> {
+    fn merge(&mut self, other: &Self) {
+        self.added.merge(&other.added);
+        self.removed.merge(&other.removed);
+    }
+}
+
+// unique tag of a single add to an `ORSet`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Dot {
+    pub node: String,
+    pub counter: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+struct Tagged<T> {
+    dot: Dot,
+    value: T,
+}
+
+// every add is tagged with a fresh dot, a remove only removes the dots it has seen,
+// so an add concurrent with a remove survives the merge (add wins)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(bound(
+    serialize = "T: Serialize",
+    deserialize = "T: Deserialize<'de> + Eq + Hash"
+))]
+pub struct ORSet<T: Eq + Hash> {
+    added: HashSet<Tagged<T>>,
+    removed: HashSet<Dot>,
+    // highest dot counter used by every node
+    clock: VersionVector,
+}
+
+impl<T: Eq + Hash> Default for ORSet<T> {
+    fn default() -> Self {
+        ORSet {
+            added: HashSet::new(),
+            removed: HashSet::new(),
+            clock: VersionVector::new(),
+        }
+    }
+}
+
+impl<T: Eq + Hash + Clone> ORSet<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, node: &str, value: T) {
+        let counter = self.clock.entry(node.to_string()).or_insert(0);
+        *counter += 1;
+        let dot = Dot {
+            node: node.to_string(),
+            counter: *counter,
+        };
+        self.added.insert(Tagged { dot, value });
+    }
+
+    // returns whether the element was present
+    pub fn remove(&mut self, value: &T) -> bool {
+        let observed: Vec<Tagged<T>> = self
+            .added
+            .iter()
+            .filter(|tagged| tagged.value == *value)
+            .cloned()
+            .collect();
+        for tagged in &observed {
+            self.added.remove(tagged);
+            self.removed.insert(tagged.dot.clone());
+        }
+        !observed.is_empty()
+    }
+
+    pub fn contains(&self, value: &T) -> bool {
+        self.added.iter().any(|tagged| tagged.value == *value)
+    }
+
+    // every element once, even if it was added several times
+    pub fn elements(&self) -> HashSet<&T> {
+        self.added.iter().map(|tagged| &tagged.value).collect()
+    }
+}
+
+impl<T: Eq + Hash + Clone> Crdt for ORSet<T> {
+    fn merge(&mut self, other: &Self) {
+        self.removed.extend(other.removed.iter().cloned());
+        self.added.extend(other.added.iter().cloned());
+        let removed = &self.removed;
+        self.added.retain(|tagged| !removed.contains(&tagged.dot));
+        for (node, counter) in &other.clock {
+            let entry = self.clock.entry(node.clone()).or_insert(0);
+            *entry = (*entry).max(*counter);
+        }
+    }
+}
+
+// ties on the timestamp are broken by the node id, so every replica picks the same value
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LwwRegister<T> {
+    value: Option<T>,
+    timestamp: u64,
+    node: String,
+}
+
+impl<T> Default for LwwRegister<T> {
+    fn default() -> Self {
+        LwwRegister {
+            value: None,
+            timestamp: 0,
+            node: String::new(),
+        }
+    }
+}
+
+impl<T: Clone> LwwRegister<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // returns false if the register already holds a newer write
+    pub fn set(&mut self, node: &str, timestamp: u64, value: T) -> bool {
+        if (timestamp, node) <= (self.timestamp, self.node.as_str()) && self.value.is_some() {
+            return false;
+        }
+        self.value = Some(value);
+        self.timestamp = timestamp;
+        self.node = node.to_string();
+        true
+    }
+
+    pub fn get(&self) -> Option<&T> {
+        self.value.as_ref()
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+}
+
+impl<T: Clone> Crdt for LwwRegister<T> {
+    fn merge(&mut self, other: &Self) {
+        if let Some(value) = &other.value {
+            self.set(&other.node, other.timestamp, value.clone());
+        }
+    }
+}
+
+// a write replaces every value it has seen, concurrent writes are all kept
+// until a later write (which has seen them) replaces them. the values are kept sorted,
+// so replicas which merged the same writes are equal whatever order they merged them in
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(bound(serialize = "T: Serialize", deserialize = "T: Deserialize<'de> + Ord"))]
+pub struct MvRegister<T: Ord> {
+    values: BTreeSet<(VersionVector, T)>,
+}
+
+impl<T: Ord> Default for MvRegister<T> {
+    fn default() -> Self {
+        MvRegister {
+            values: BTreeSet::new(),
+        }
+    }
+}
+
+impl<T: Clone + Ord> MvRegister<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&mut self, node: &str, value: T) {
+        let mut version = VersionVector::new();
+        for (seen, _) in &self.values {
+            for (seen_node, counter) in seen {
+                let entry = version.entry(seen_node.clone()).or_insert(0);
+                *entry = (*entry).max(*counter);
+            }
+        }
+        *version.entry(node.to_string()).or_insert(0) += 1;
+        self.values = BTreeSet::from([(version, value)]);
+    }
+
+    // more than one value means there were concurrent writes
+    pub fn get(&self) -> Vec<&T> {
+        self.values.iter().map(|(_, value)| value).collect()
+    }
+}
+
+impl<T: Clone + Ord> Crdt for MvRegister<T> {
+    fn merge(&mut self, other: &Self) {
+        let values: BTreeSet<_> = self.values.union(&other.values).cloned().collect();
+        // drop every value that another (different) write has already seen
+        let merged = values
+            .iter()
+            .filter(|(version, _)| {
+                !values
+                    .iter()
+                    .any(|(other, _)| other != version && dominates(other, version))
+            })
+            .cloned()
+            .collect();
+        self.values = merged;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt::Debug;
+
+    fn merged<C: Crdt + Clone>(a: &C, b: &C) -> C {
+        let mut merged = a.clone();
+        merged.merge(b);
+        merged
+    }
+
+    fn assert_laws<C: Crdt + Clone + PartialEq + Debug>(a: &C, b: &C, c: &C) {
+        assert_eq!(merged(a, b), merged(b, a), "commutative");
+        assert_eq!(
+            merged(&merged(a, b), c),
+            merged(a, &merged(b, c)),
+            "associative"
+        );
+        assert_eq!(merged(a, a), *a, "idempotent");
+        assert_eq!(merged(&merged(a, b), b), merged(a, b), "idempotent");
+    }
+
+    #[test]
+    fn counters_obey_the_merge_laws() {
+        let (mut a, mut b, mut c) = (PNCounter::new(), PNCounter::new(), PNCounter::new());
+        a.add("n0", 3);
+        a.add("n0", -1);
+        b.add("n1", 5);
+        c.add("n2", -4);
+        assert_laws(&a, &b, &c);
+        assert_eq!(merged(&merged(&a, &b), &c).value(), 3);
+        assert_laws(a.increments(), b.increments(), c.increments());
+    }
+
+    #[test]
+    fn sets_obey_the_merge_laws() {
+        let a: GSet<u64> = [1, 2].into_iter().collect();
+        let b: GSet<u64> = [2, 3].into_iter().collect();
+        let c: GSet<u64> = [4].into_iter().collect();
+        assert_laws(&a, &b, &c);
+
+        let (mut a, mut b, mut c) = (TwoPSet::new(), TwoPSet::new(), TwoPSet::new());
+        a.insert(1);
+        a.insert(2);
+        a.remove(&1);
+        b.insert(1);
+        c.insert(3);
+        assert_laws(&a, &b, &c);
+        // a removed element can't come back
+        assert!(!merged(&a, &b).contains(&1));
+
+        let (mut a, mut b, mut c) = (ORSet::new(), ORSet::new(), ORSet::new());
+        a.insert("n0", 1);
+        a.insert("n0", 2);
+        b.merge(&a);
+        b.remove(&1);
+        c.insert("n2", 1);
+        assert_laws(&a, &b, &c);
+    }
+
+    #[test]
+    fn or_set_add_wins_over_a_concurrent_remove() {
+        let mut a = ORSet::new();
+        a.insert("n0", "x");
+        let mut b = a.clone();
+        // b removes the add it has seen while a adds the element again
+        b.remove(&"x");
+        a.insert("n0", "x");
+        assert!(merged(&a, &b).contains(&"x"));
+        assert!(merged(&b, &a).contains(&"x"));
+
+        // a remove of everything that was added wins
+        let mut c = merged(&a, &b);
+        c.remove(&"x");
+        assert!(!merged(&a, &c).contains(&"x"));
+        // and re-adding afterwards works
+        c.insert("n1", "x");
+        assert!(merged(&a, &c).contains(&"x"));
+    }
+
+    #[test]
+    fn lww_register_breaks_ties_by_node() {
+        let (mut a, mut b) = (LwwRegister::new(), LwwRegister::new());
+        a.set("n0", 5, "a");
+        b.set("n1", 5, "b");
+        assert_eq!(merged(&a, &b).get(), Some(&"b"));
+        assert_eq!(merged(&b, &a).get(), Some(&"b"));
+
+        let mut c = LwwRegister::new();
+        c.set("n0", 6, "c");
+        assert_eq!(merged(&b, &c).get(), Some(&"c"));
+        assert!(!c.set("n1", 5, "old"));
+        assert_laws(&a, &b, &c);
+    }
+
+    #[test]
+    fn mv_register_keeps_concurrent_writes() {
+        let (mut a, mut b, mut c) = (MvRegister::new(), MvRegister::new(), MvRegister::new());
+        a.set("n0", 1);
+        b.set("n1", 2);
+        c.set("n2", 0);
+        assert_laws(&a, &b, &c);
+        let mut both = merged(&a, &b);
+        assert_eq!(both.get(), [&1, &2]);
+        // a write which has seen both replaces them
+        both.set("n0", 3);
+        assert_eq!(merged(&both, &a).get(), [&3]);
+        assert_eq!(merged(&b, &both).get(), [&3]);
+        assert_laws(&both, &a, &c);
+    }
+}
@@ -0,0 +1,70 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fmt;
+
+// maelstrom error codes
+// see https://github.com/jepsen-io/maelstrom/blob/main/doc/protocol.md#errors
+pub mod code {
+    pub const TIMEOUT: u64 = 0;
+    pub const NODE_NOT_FOUND: u64 = 1;
+    pub const NOT_SUPPORTED: u64 = 10;
+    pub const TEMPORARILY_UNAVAILABLE: u64 = 11;
+    pub const MALFORMED_REQUEST: u64 = 12;
+    pub const CRASH: u64 = 13;
+    pub const ABORT: u64 = 14;
+    pub const KEY_DOES_NOT_EXIST: u64 = 20;
+    pub const KEY_ALREADY_EXISTS: u64 = 21;
+    pub const PRECONDITION_FAILED: u64 = 22;
+    pub const TXN_CONFLICT: u64 = 30;
+
+    // our own codes, maelstrom leaves 1000 and above to the nodes
+    // a kafka poll asked for an offset which was dropped by retention
+    pub const OFFSET_TRUNCATED: u64 = 1000;
+
+    // whether an error means the operation didn't happen, after a timeout or a crash
+    // it may have
+    pub fn is_definite(code: u64) -> bool {
+        !matches!(code, TIMEOUT | CRASH)
+    }
+}
+
+// an `error` message, either received as the reply of an rpc
+// or returned by a handler to be sent back to the caller
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RpcError {
+    pub code: u64,
+    #[serde(default)]
+    pub text: String,
+}
+
+impl RpcError {
+    pub fn new(code: u64, text: impl Into<String>) -> Self {
+        RpcError {
+            code,
+            text: text.into(),
+        }
+    }
+
+    // body of the `error` message replying to `in_reply_to`
+    pub fn reply_body(&self, in_reply_to: u64) -> Value {
+        json!({
+            "type": "error",
+            "in_reply_to": in_reply_to,
+            "code": self.code,
+            "text": self.text,
+        })
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "error {}: {}", self.code, self.text)
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+// the maelstrom error code, if `err` came from an `error` message
+pub fn error_code(err: &anyhow::Error) -> Option<u64> {
+    err.downcast_ref::<RpcError>().map(|err| err.code)
+}
@@ -0,0 +1,85 @@
+use crate::error::{code, error_code};
+use crate::runtime::Runtime;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::time::Duration;
+
+// key ownership and request forwarding
+//
+// every key is owned by a single node, picked by rendezvous hashing so all nodes agree
+// without talking to each other. a node receiving a request for a key it doesn't own
+// proxies it to the owner and relays the reply. if the owner doesn't answer in time
+// the request goes to the next node of the key's preference list, and fails with a
+// timeout if that one doesn't answer either. the owner may still handle it after the
+// timeout, so the receivers have to apply a request at most once (the kafka log claims
+// every forwarded send in the kv store first). the timeout has to cover the owner's own
+// work, a timeout only means the owner is down or partitioned
+//
+// requests coming from other nodes were forwarded already and are always handled
+// locally, so a request is never passed on twice
+
+// where a request has to be handled
+pub enum Route<R> {
+    // this node is responsible, handle the request itself
+    Local,
+    // another node handled the request, this is its reply
+    Remote(R),
+}
+
+#[derive(Clone)]
+pub struct Forwarder {
+    timeout: Duration,
+}
+
+impl Forwarder {
+    pub fn new(timeout: Duration) -> Self {
+        Forwarder { timeout }
+    }
+
+    // every node of the cluster, the owner of `key` first
+    pub fn preference_list<'a>(runtime: &'a Runtime, key: &str) -> Vec<&'a String> {
+        let mut nodes: Vec<&String> = runtime.node_ids().iter().collect();
+        nodes.sort_by_key(|node| std::cmp::Reverse(weight(key, node)));
+        nodes
+    }
+
+    // send `body` to the owner of `key`, `src` is whoever sent us the request
+    pub async fn route<T, R>(
+        &self,
+        runtime: &Runtime,
+        src: &str,
+        key: &str,
+        body: &T,
+    ) -> anyhow::Result<Route<R>>
+    where
+        T: Serialize,
+        R: DeserializeOwned,
+    {
+        if runtime.node_ids().iter().any(|node| node == src) {
+            return Ok(Route::Local);
+        }
+        let nodes = Self::preference_list(runtime, key);
+        if nodes[0] == runtime.node_id() {
+            return Ok(Route::Local);
+        }
+        let reply = match runtime.call_timeout(nodes[0], body, self.timeout).await {
+            Err(err) if error_code(&err) == Some(code::TIMEOUT) => {
+                let next = nodes[1..].iter().find(|node| **node != runtime.node_id());
+                match next {
+                    Some(next) => runtime.call_timeout(next, body, self.timeout).await?,
+                    None => return Err(err),
+                }
+            }
+            reply => reply?,
+        };
+        Ok(Route::Remote(reply))
+    }
+}
+
+fn weight(key: &str, node: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (key, node).hash(&mut hasher);
+    hasher.finish()
+}
@@ -0,0 +1,205 @@
+use std::time::Duration;
+use tokio::time::Instant;
+
+// the batch scheduler used by the broadcast binaries
+//
+// instead of flushing gossip every fixed N ms, we keep track of how fast
+// new messages are coming in and pick the flush interval / batch size from that:
+// - low load  : hold messages as long as the latency target allows (few msgs-per-op)
+// - high load : a batch fills up quickly, so flush as soon as it is full (low latency)
+// - no load   : only send an occasional anti-entropy round, so partitions still heal
+
+// how often the incoming rate estimate is refreshed
+const RATE_WINDOW: Duration = Duration::from_millis(250);
+// weight of the newest sample in the rate estimate (exponential moving average)
+const RATE_SMOOTHING: f64 = 0.3;
+
+#[derive(Debug, Clone)]
+pub struct Settings {
+    // how long a message may take to reach every node in the cluster
+    pub latency_target: Duration,
+    // number of gossip hops needed to reach every node in the topology
+    // (all-to-all = 1, star = 2, ...), the latency target is split between them
+    pub hops: u32,
+    // never flush more often than this
+    pub min_interval: Duration,
+    // flush even if nothing new arrived once in this interval (anti-entropy)
+    pub idle_interval: Duration,
+    // number of new messages we would like to carry in every flush
+    pub batch_target: usize,
+    // upper bound of new messages carried in a single flush
+    pub max_batch: usize,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            latency_target: Duration::from_millis(400),
+            hops: 1,
+            min_interval: Duration::from_millis(20),
+            idle_interval: Duration::from_millis(1000),
+            batch_target: 10,
+            max_batch: 50,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Scheduler {
+    settings: Settings,
+    // estimated rate of new messages (per second)
+    rate: f64,
+    window_start: Instant,
+    window_count: usize,
+    // new messages that were not flushed yet
+    pending: usize,
+    oldest_pending: Option<Instant>,
+    last_flush: Instant,
+}
+
+impl Scheduler {
+    pub fn new(settings: Settings) -> Self {
+        let now = Instant::now();
+        Scheduler {
+            settings,
+            rate: 0.0,
+            window_start: now,
+            window_count: 0,
+            pending: 0,
+            oldest_pending: None,
+            last_flush: now,
+        }
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    // estimated number of new messages per second
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    // call this for every new message that has to be gossiped
+    pub fn record(&mut self, count: usize) {
+        if count == 0 {
+            return;
+        }
+        let now = Instant::now();
+        self.refresh_rate(now);
+        self.window_count += count;
+        self.pending += count;
+        self.oldest_pending.get_or_insert(now);
+    }
+
+    // the part of the latency target a single hop is allowed to spend
+    fn hop_budget(&self) -> Duration {
+        let budget = self.settings.latency_target / self.settings.hops.max(1);
+        budget.max(self.settings.min_interval)
+    }
+
+    // how long a message may wait before it gets flushed
+    pub fn interval(&self) -> Duration {
+        let budget = self.hop_budget();
+        if self.rate <= 0.0 {
+            return budget;
+        }
+        // time needed to collect a full batch at the current rate, clamped before the
+        // conversion: the rate decays towards 0 while idle and the quotient overflows
+        let fill_time = self.settings.batch_target as f64 / self.rate;
+        if fill_time >= budget.as_secs_f64() {
+            return budget;
+        }
+        Duration::from_secs_f64(fill_time).max(self.settings.min_interval)
+    }
+
+    // how many new messages should be carried in the next flush
+    pub fn batch_size(&self) -> usize {
+        // leave some headroom over the expected arrivals so the queue drains
+        let expected = (self.rate * self.interval().as_secs_f64() * 2.0).ceil() as usize;
+        expected.clamp(
+            self.settings.batch_target.max(1),
+            self.settings.max_batch.max(1),
+        )
+    }
+
+    pub fn should_flush(&mut self, now: Instant) -> bool {
+        self.refresh_rate(now);
+        let since_flush = now.saturating_duration_since(self.last_flush);
+        match self.oldest_pending {
+            None => since_flush >= self.settings.idle_interval,
+            Some(oldest) => {
+                if since_flush < self.settings.min_interval {
+                    return false;
+                }
+                self.pending >= self.batch_size()
+                    || now.saturating_duration_since(oldest) >= self.interval()
+            }
+        }
+    }
+
+    // call this after a flush which carried `sent` of the pending messages
+    pub fn flushed(&mut self, now: Instant, sent: usize) {
+        self.last_flush = now;
+        self.pending = self.pending.saturating_sub(sent);
+        self.oldest_pending = if self.pending == 0 { None } else { Some(now) };
+    }
+
+    fn refresh_rate(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.window_start);
+        if elapsed < RATE_WINDOW {
+            return;
+        }
+        let sample = self.window_count as f64 / elapsed.as_secs_f64();
+        self.rate = RATE_SMOOTHING * sample + (1.0 - RATE_SMOOTHING) * self.rate;
+        self.window_start = now;
+        self.window_count = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn broadcast_after_a_long_idle_gap() {
+        let mut scheduler = Scheduler::new(Settings::default());
+        scheduler.record(100);
+        tokio::time::advance(RATE_WINDOW).await;
+        assert!(scheduler.should_flush(Instant::now()));
+        scheduler.flushed(Instant::now(), 100);
+
+        // the rate decays every window but never reaches 0
+        for _ in 0..400 {
+            tokio::time::advance(RATE_WINDOW).await;
+            scheduler.should_flush(Instant::now());
+        }
+        assert!(scheduler.rate() > 0.0);
+
+        scheduler.record(1);
+        assert_eq!(scheduler.interval(), scheduler.settings().latency_target);
+        assert_eq!(scheduler.batch_size(), scheduler.settings().batch_target);
+        assert!(!scheduler.should_flush(Instant::now()));
+        tokio::time::advance(scheduler.interval()).await;
+        assert!(scheduler.should_flush(Instant::now()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn busy_rate_flushes_full_batches_early() {
+        let mut scheduler = Scheduler::new(Settings::default());
+        for _ in 0..8 {
+            scheduler.record(250);
+            tokio::time::advance(RATE_WINDOW).await;
+            scheduler.should_flush(Instant::now());
+            scheduler.flushed(Instant::now(), scheduler.pending());
+        }
+        let settings = scheduler.settings().clone();
+        assert!(scheduler.interval() < settings.latency_target);
+        assert!(scheduler.interval() >= settings.min_interval);
+        assert!(scheduler.batch_size() <= settings.max_batch);
+    }
+}
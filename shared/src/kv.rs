@@ -0,0 +1,112 @@
+use crate::error::{code, error_code};
+use crate::runtime::Runtime;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// client for maelstrom's built-in key/value services
+// see https://github.com/jepsen-io/maelstrom/blob/main/doc/services.md
+#[derive(Clone)]
+pub struct Kv {
+    runtime: Runtime,
+    service: &'static str,
+}
+
+// sequentially consistent
+pub fn seq_kv(runtime: Runtime) -> Kv {
+    Kv {
+        runtime,
+        service: "seq-kv",
+    }
+}
+
+// linearizable
+pub fn lin_kv(runtime: Runtime) -> Kv {
+    Kv {
+        runtime,
+        service: "lin-kv",
+    }
+}
+
+// last-write-wins
+pub fn lww_kv(runtime: Runtime) -> Kv {
+    Kv {
+        runtime,
+        service: "lww-kv",
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Request<'a> {
+    Read {
+        key: &'a str,
+    },
+    Write {
+        key: &'a str,
+        value: Value,
+    },
+    Cas {
+        key: &'a str,
+        from: Value,
+        to: Value,
+        create_if_not_exists: bool,
+    },
+}
+
+#[derive(Deserialize, Debug)]
+struct ReadOk {
+    value: Value,
+}
+
+impl Kv {
+    // `None` if the key does not exist
+    pub async fn read<T: DeserializeOwned>(&self, key: &str) -> anyhow::Result<Option<T>> {
+        let reply = self
+            .runtime
+            .call::<_, ReadOk>(self.service, Request::Read { key })
+            .await;
+        match reply {
+            Ok(reply) => Ok(Some(serde_json::from_value(reply.value)?)),
+            Err(err) if error_code(&err) == Some(code::KEY_DOES_NOT_EXIST) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub async fn write<T: Serialize>(&self, key: &str, value: T) -> anyhow::Result<()> {
+        let value = serde_json::to_value(value)?;
+        self.runtime
+            .call::<_, Value>(self.service, Request::Write { key, value })
+            .await?;
+        Ok(())
+    }
+
+    // `false` if the current value is not `from` (or the key does not exist)
+    pub async fn cas<T: Serialize>(
+        &self,
+        key: &str,
+        from: T,
+        to: T,
+        create_if_not_exists: bool,
+    ) -> anyhow::Result<bool> {
+        let request = Request::Cas {
+            key,
+            from: serde_json::to_value(from)?,
+            to: serde_json::to_value(to)?,
+            create_if_not_exists,
+        };
+        match self.runtime.call::<_, Value>(self.service, request).await {
+            Ok(_) => Ok(true),
+            Err(err)
+                if matches!(
+                    error_code(&err),
+                    Some(code::PRECONDITION_FAILED) | Some(code::KEY_DOES_NOT_EXIST)
+                ) =>
+            {
+                Ok(false)
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
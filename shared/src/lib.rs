@@ -0,0 +1,19 @@
+// building blocks shared by all the challenge binaries
+pub mod config;
+pub mod consensus;
+pub mod crdt;
+pub mod error;
+pub mod forward;
+pub mod gossip;
+pub mod kv;
+pub mod log;
+pub mod message;
+pub mod metrics;
+pub mod paxos;
+pub mod poll;
+pub mod raft;
+pub mod runtime;
+pub mod sim;
+
+pub use message::Message;
+pub use runtime::{Node, Runtime};
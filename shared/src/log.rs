@@ -0,0 +1,30 @@
+use crate::config::LogConfig;
+use tracing_subscriber::EnvFilter;
+
+// leveled logging to stderr (stdout belongs to maelstrom) through `tracing`
+//
+// the runtime puts every node in a `node{id=..}` span and every message it hands to
+// `Node::process` in a `msg{src=.. msg_id=.. type=..}` span inside it, so events
+// logged while handling a message carry both without passing them around:
+//   INFO node{id=n1}:msg{src=c4 msg_id=12 type=send}: shared::raft: became leader term=3
+// with `shared::runtime=debug` every message read and written is logged as well
+
+// call once at startup, before creating the `Runtime`: spans created earlier stay
+// disabled. an invalid filter falls back to "info" with a warning
+pub fn init(config: &LogConfig) {
+    let (filter, invalid) = match EnvFilter::try_new(&config.filter) {
+        Ok(filter) => (filter, None),
+        Err(err) => (EnvFilter::new("info"), Some(err)),
+    };
+    let installed = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .with_ansi(false)
+        .try_init();
+    if installed.is_err() {
+        return;
+    }
+    if let Some(err) = invalid {
+        tracing::warn!("invalid log filter {:?}: {}", config.filter, err);
+    }
+}
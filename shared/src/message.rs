@@ -0,0 +1,10 @@
+use serde::{Deserialize, Serialize};
+
+// generic type for json received for all problems
+// `body` is the challenge specific part, usually an enum tagged by `type`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Message<B> {
+    pub src: String,
+    pub dest: String,
+    pub body: B,
+}
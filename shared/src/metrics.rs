@@ -0,0 +1,252 @@
+use crate::config::MetricsConfig;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{LineWriter, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::Instant;
+use tracing::warn;
+
+// counters the runtime keeps about a node when started `with_metrics`
+//
+// - messages and bytes read and written, per message type
+// - how long `Node::process` took, per message type, as a histogram
+// - messages queued for the output task that aren't written yet
+// - messages to other nodes per client request, maelstrom's msgs-per-op
+//
+// everything but the max queue depth counts from the start of the node, so two
+// dumps can be subtracted to get the rate in between. a dump is a json line:
+//   {"time":..,"node":"n0","uptime_ms":..,"sent":{"gossip":{"count":..,"bytes":..}},
+//    "received":{..},"handlers":{"broadcast":{"count":..,"p50_ms":..,..}},
+//    "internal_sent":..,"client_requests":..,"msgs_per_op":..,
+//    "queue":{"depth":..,"max_depth":..}}
+
+pub struct Metrics {
+    config: MetricsConfig,
+    started: Instant,
+    state: Mutex<State>,
+    // incremented by `Runtime::send`, decremented by the output task
+    queue_depth: AtomicU64,
+    // opened at the first dump, once the node id is known
+    file: Mutex<Option<LineWriter<File>>>,
+}
+
+#[derive(Default)]
+struct State {
+    sent: BTreeMap<String, Counter>,
+    received: BTreeMap<String, Counter>,
+    handlers: BTreeMap<String, Histogram>,
+    internal_sent: u64,
+    client_requests: u64,
+    // since the last dump
+    max_queue_depth: u64,
+}
+
+#[derive(Serialize, Default)]
+struct Counter {
+    count: u64,
+    bytes: u64,
+}
+
+impl Counter {
+    fn add(&mut self, bytes: usize) {
+        self.count += 1;
+        self.bytes += bytes as u64;
+    }
+}
+
+// bucket i counts the durations of 2^i to 2^(i+1) microseconds (bucket 0 from 0)
+#[derive(Default)]
+struct Histogram {
+    buckets: Vec<u64>,
+    count: u64,
+    total_us: u64,
+    max_us: u64,
+}
+
+impl Histogram {
+    fn record(&mut self, elapsed: Duration) {
+        let us = elapsed.as_micros() as u64;
+        let bucket = (u64::BITS - us.leading_zeros()).saturating_sub(1) as usize;
+        if self.buckets.len() <= bucket {
+            self.buckets.resize(bucket + 1, 0);
+        }
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.total_us += us;
+        self.max_us = self.max_us.max(us);
+    }
+
+    // upper bound of the bucket holding the `quantile`, so at most 2x too high
+    fn quantile_us(&self, quantile: f64) -> u64 {
+        let rank = (quantile * self.count as f64).ceil() as u64;
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank.max(1) {
+                return (2u64 << bucket).min(self.max_us);
+            }
+        }
+        self.max_us
+    }
+
+    fn summary(&self) -> Value {
+        let ms = |us: u64| us as f64 / 1000.0;
+        let buckets: Vec<(u64, u64)> = self
+            .buckets
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(bucket, count)| (2u64 << bucket, *count))
+            .collect();
+        json!({
+            "count": self.count,
+            "mean_ms": ms(self.total_us / self.count.max(1)),
+            "p50_ms": ms(self.quantile_us(0.5)),
+            "p90_ms": ms(self.quantile_us(0.9)),
+            "p99_ms": ms(self.quantile_us(0.99)),
+            "max_ms": ms(self.max_us),
+            // [below this many microseconds, count]
+            "buckets_us": buckets,
+        })
+    }
+}
+
+// the fields of a written message the metrics need, without parsing the rest
+#[derive(Deserialize)]
+struct Written<'a> {
+    #[serde(borrow)]
+    dest: &'a str,
+    body: WrittenBody<'a>,
+}
+
+#[derive(Deserialize)]
+struct WrittenBody<'a> {
+    #[serde(borrow, rename = "type")]
+    kind: Option<&'a str>,
+}
+
+impl Metrics {
+    pub fn new(config: MetricsConfig) -> Self {
+        Metrics {
+            config,
+            started: Instant::now(),
+            state: Mutex::new(State::default()),
+            queue_depth: AtomicU64::new(0),
+            file: Mutex::new(None),
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.config.interval()
+    }
+
+    pub(crate) fn queued(&self) {
+        let depth = self.queue_depth.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut state = self.state.lock().unwrap();
+        state.max_queue_depth = state.max_queue_depth.max(depth);
+    }
+
+    pub(crate) fn dequeued(&self) {
+        self.queue_depth.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    // `line` was taken off the output queue and written, `is_node` tells
+    // whether its destination is another node of the cluster
+    pub(crate) fn written(&self, line: &str, is_node: impl Fn(&str) -> bool) {
+        self.dequeued();
+        let Ok(msg) = serde_json::from_str::<Written>(line) else {
+            return;
+        };
+        let mut state = self.state.lock().unwrap();
+        let kind = msg.body.kind.unwrap_or("?");
+        state
+            .sent
+            .entry(kind.to_string())
+            .or_default()
+            .add(line.len());
+        if is_node(msg.dest) {
+            state.internal_sent += 1;
+        }
+    }
+
+    pub(crate) fn read(&self, kind: &str, bytes: usize) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .received
+            .entry(kind.to_string())
+            .or_default()
+            .add(bytes);
+    }
+
+    pub(crate) fn handled(&self, kind: &str, from_client: bool, elapsed: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .handlers
+            .entry(kind.to_string())
+            .or_default()
+            .record(elapsed);
+        if from_client {
+            state.client_requests += 1;
+        }
+    }
+
+    fn snapshot(&self, node_id: &str) -> Value {
+        let mut state = self.state.lock().unwrap();
+        let depth = self.queue_depth.load(Ordering::Relaxed);
+        let max_depth = std::mem::replace(&mut state.max_queue_depth, depth);
+        let handlers: BTreeMap<&String, Value> = state
+            .handlers
+            .iter()
+            .map(|(kind, histogram)| (kind, histogram.summary()))
+            .collect();
+        let msgs_per_op = match state.client_requests {
+            0 => Value::Null,
+            requests => json!(state.internal_sent as f64 / requests as f64),
+        };
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        json!({
+            "time": time,
+            "node": node_id,
+            "uptime_ms": self.started.elapsed().as_millis() as u64,
+            "sent": state.sent,
+            "received": state.received,
+            "handlers": handlers,
+            "internal_sent": state.internal_sent,
+            "client_requests": state.client_requests,
+            "msgs_per_op": msgs_per_op,
+            "queue": { "depth": depth, "max_depth": max_depth },
+        })
+    }
+
+    // write the current numbers to `<dir>/<node id>.jsonl`, or stderr without a dir
+    pub(crate) fn dump(&self, node_id: &str) {
+        let line = format!("{}\n", self.snapshot(node_id));
+        let Some(dir) = &self.config.dir else {
+            eprint!("metrics {}", line);
+            return;
+        };
+        let mut file = self.file.lock().unwrap();
+        if file.is_none() {
+            let path = dir.join(format!("{}.jsonl", node_id));
+            match std::fs::create_dir_all(dir).and_then(|_| File::create(&path)) {
+                Ok(opened) => *file = Some(LineWriter::new(opened)),
+                Err(err) => {
+                    warn!("failed to create metrics {}: {}", path.display(), err);
+                    return;
+                }
+            }
+        }
+        if let Some(file) = file.as_mut() {
+            if let Err(err) = file.write_all(line.as_bytes()) {
+                warn!("failed to write metrics: {}", err);
+            }
+        }
+    }
+}
@@ -0,0 +1,886 @@
+use crate::config::RaftConfig;
+use crate::consensus::{Role, StateMachine};
+use crate::error::{code, RpcError};
+use crate::runtime::Runtime;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+use tracing::info;
+
+// multi-paxos, replicating a `StateMachine` just like `raft::Raft` does
+// see https://lamport.azurewebsites.net/pubs/paxos-simple.pdf, section 3
+//
+// - the log is a sequence of slots, each one decided by its own instance of paxos.
+//   every node is an acceptor and a learner, one of them is the leader (proposer)
+// - a node which doesn't hear from a leader for an election timeout runs phase 1 for
+//   all the slots it didn't apply yet at once: it prepares a ballot higher than any it
+//   saw, the acceptors promise to ignore lower ballots and return what they accepted
+//   (and their snapshot if they applied more than the candidate)
+// - with promises from a majority the candidate leads: every slot gets the value
+//   accepted in the highest ballot, holes become no-ops, and from then on the leader
+//   only runs phase 2 (accept) for these and the newly proposed commands
+// - a slot is chosen once a majority accepted it in the leader's ballot. accepts carry
+//   the slot up to which everything is chosen (empty ones are the heartbeats), nodes
+//   apply what they accepted in that ballot up to there
+// - applied slots are compacted into a snapshot like raft does, acceptors which fall
+//   behind the leader's snapshot get the snapshot instead
+//
+// unlike raft any node can win an election, the leader learns the values it misses
+// from the promises. like raft the state is kept in memory only
+
+// how often the election / heartbeat timers are checked
+const TICK: Duration = Duration::from_millis(10);
+
+// ballots are unique per node, the round decides and the node breaks ties
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Ballot {
+    pub round: u64,
+    pub node: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(bound = "C: Serialize + DeserializeOwned")]
+pub struct Slot<C> {
+    // the ballot the command was proposed in, a leader which lost its leadership uses it
+    // to tell whether the slot still holds its command
+    pub proposed: Ballot,
+    // `None` is a no-op, filling a hole a new leader found
+    pub command: Option<C>,
+}
+
+// a slot as an acceptor returns it in its promise
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(bound = "C: Serialize + DeserializeOwned")]
+pub struct Accepted<C> {
+    pub index: u64,
+    pub ballot: Ballot,
+    pub slot: Slot<C>,
+}
+
+// the requests paxos nodes send each other
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+#[serde(bound = "C: Serialize + DeserializeOwned, S: Serialize + DeserializeOwned")]
+pub enum Rpc<C, S> {
+    // phase 1 for every slot from `first` on
+    Prepare {
+        msg_id: u64,
+        ballot: Ballot,
+        first: u64,
+    },
+    // phase 2 for the slots from `first` on, everything up to `chosen` is chosen
+    Accept {
+        msg_id: u64,
+        ballot: Ballot,
+        first: u64,
+        slots: Vec<Slot<C>>,
+        chosen: u64,
+    },
+    // the leader's snapshot, for an acceptor missing slots the leader compacted
+    Catchup {
+        msg_id: u64,
+        ballot: Ballot,
+        index: u64,
+        snapshot: S,
+    },
+}
+
+// `promised` is the ballot the acceptor promised after handling the request,
+// the request was rejected if it's not the ballot of the request
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+#[serde(bound = "C: Serialize + DeserializeOwned, S: Serialize + DeserializeOwned")]
+#[allow(clippy::enum_variant_names)]
+enum Reply<C, S> {
+    PrepareOk {
+        in_reply_to: u64,
+        promised: Ballot,
+        accepted: Vec<Accepted<C>>,
+        // the acceptor's snapshot if it covers slots the candidate asked for
+        snapshot: Option<(u64, S)>,
+    },
+    AcceptOk {
+        in_reply_to: u64,
+        promised: Ballot,
+        applied: u64,
+    },
+    CatchupOk {
+        in_reply_to: u64,
+        promised: Ballot,
+    },
+}
+
+type Waiter<O> = oneshot::Sender<Result<O, RpcError>>;
+
+// what the acceptor stores for a slot
+struct Stored<C> {
+    ballot: Ballot,
+    slot: Slot<C>,
+    // this node learned the slot is chosen
+    chosen: bool,
+}
+
+// the promise of an acceptor to a candidate
+struct Promise<C, S> {
+    accepted: Vec<Accepted<C>>,
+    snapshot: Option<(u64, S)>,
+}
+
+struct State<M: StateMachine> {
+    role: Role,
+    // the highest ballot this node promised, the ballot it leads in as the leader
+    promised: Ballot,
+    leader: Option<String>,
+    election_deadline: Instant,
+    heartbeat_due: Instant,
+
+    // the slots after the snapshot this node accepted
+    slots: BTreeMap<u64, Stored<M::Command>>,
+    snapshot: Option<M::Snapshot>,
+    snapshot_index: u64,
+    applied: u64,
+    machine: M,
+
+    // candidate only
+    promises: HashMap<String, Promise<M::Command, M::Snapshot>>,
+
+    // leader only
+    // the next free slot
+    next_slot: u64,
+    // everything up to here is chosen
+    chosen: u64,
+    next_index: HashMap<String, u64>,
+    // every slot from the leader's first one up to here was accepted in its ballot
+    match_index: HashMap<String, u64>,
+    // proposals waiting for their slot to be applied, by slot
+    waiting: HashMap<u64, (Ballot, Waiter<M::Output>)>,
+}
+
+pub struct Paxos<M: StateMachine> {
+    runtime: Runtime,
+    config: RaftConfig,
+    state: Arc<Mutex<State<M>>>,
+}
+
+impl<M: StateMachine> Clone for Paxos<M> {
+    fn clone(&self) -> Self {
+        Paxos {
+            runtime: self.runtime.clone(),
+            config: self.config.clone(),
+            state: Arc::clone(&self.state),
+        }
+    }
+}
+
+impl<M: StateMachine> Paxos<M> {
+    pub fn new(runtime: Runtime, config: RaftConfig, machine: M) -> Self {
+        let now = Instant::now();
+        let state = State {
+            role: Role::Follower,
+            promised: Ballot::default(),
+            leader: None,
+            election_deadline: now,
+            heartbeat_due: now,
+            slots: BTreeMap::new(),
+            snapshot: None,
+            snapshot_index: 0,
+            applied: 0,
+            machine,
+            promises: HashMap::new(),
+            next_slot: 1,
+            chosen: 0,
+            next_index: HashMap::new(),
+            match_index: HashMap::new(),
+            waiting: HashMap::new(),
+        };
+        Paxos {
+            runtime,
+            config,
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    // start the timers, call this from `Node::init`
+    pub fn start(&self) {
+        self.state.lock().unwrap().election_deadline = Instant::now() + self.election_timeout();
+        let paxos = self.clone();
+        self.runtime.every(TICK, move || {
+            paxos.tick();
+            async { Ok(()) }
+        });
+    }
+
+    pub fn role(&self) -> Role {
+        self.state.lock().unwrap().role
+    }
+
+    // the leader of the highest ballot this node accepted, as far as it knows
+    pub fn leader(&self) -> Option<String> {
+        self.state.lock().unwrap().leader.clone()
+    }
+
+    // get `command` chosen and wait until it is applied, only works on the leader
+    //
+    // fails like `Raft::propose`: `TEMPORARILY_UNAVAILABLE` if this node is not the
+    // leader, `ABORT` if a new leader put something else into the slot and `TIMEOUT`
+    // if the outcome is unknown
+    pub async fn propose(&self, command: M::Command) -> anyhow::Result<M::Output> {
+        let (index, receiver) = {
+            let mut state = self.state.lock().unwrap();
+            if state.role != Role::Leader {
+                let text = match &state.leader {
+                    Some(leader) => format!("not the leader, {} is", leader),
+                    None => "not the leader, there is no leader right now".to_string(),
+                };
+                return Err(RpcError::new(code::TEMPORARILY_UNAVAILABLE, text).into());
+            }
+            let ballot = state.promised.clone();
+            let index = state.next_slot;
+            state.next_slot += 1;
+            let slot = Slot {
+                proposed: ballot.clone(),
+                command: Some(command),
+            };
+            state.slots.insert(
+                index,
+                Stored {
+                    ballot: ballot.clone(),
+                    slot,
+                    chosen: false,
+                },
+            );
+            let (sender, receiver) = oneshot::channel();
+            state.waiting.insert(index, (ballot, sender));
+            // a single node cluster chooses right away
+            self.advance_chosen(&mut state);
+            (index, receiver)
+        };
+        self.replicate();
+
+        match tokio::time::timeout(self.config.propose_timeout(), receiver).await {
+            Ok(Ok(result)) => Ok(result?),
+            _ => {
+                self.state.lock().unwrap().waiting.remove(&index);
+                let text = format!("slot {} was not chosen in time", index);
+                Err(RpcError::new(code::TIMEOUT, text).into())
+            }
+        }
+    }
+
+    // answer a request of another paxos node
+    pub fn handle(&self, src: &str, rpc: Rpc<M::Command, M::Snapshot>) -> anyhow::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let reply = match rpc {
+            Rpc::Prepare {
+                msg_id,
+                ballot,
+                first,
+            } => {
+                let mut accepted = Vec::new();
+                let mut snapshot = None;
+                if self.follow(&mut state, &ballot) {
+                    accepted = state
+                        .slots
+                        .range(first..)
+                        .map(|(index, stored)| Accepted {
+                            index: *index,
+                            ballot: stored.ballot.clone(),
+                            slot: stored.slot.clone(),
+                        })
+                        .collect();
+                    if state.snapshot_index >= first {
+                        snapshot = state
+                            .snapshot
+                            .clone()
+                            .map(|snapshot| (state.snapshot_index, snapshot));
+                    }
+                }
+                Reply::PrepareOk {
+                    in_reply_to: msg_id,
+                    promised: state.promised.clone(),
+                    accepted,
+                    snapshot,
+                }
+            }
+            Rpc::Accept {
+                msg_id,
+                ballot,
+                first,
+                slots,
+                chosen,
+            } => {
+                if self.follow(&mut state, &ballot) {
+                    for (index, slot) in (first..).zip(slots) {
+                        if index <= state.applied {
+                            continue;
+                        }
+                        let chosen = state.slots.get(&index).is_some_and(|stored| stored.chosen);
+                        let ballot = ballot.clone();
+                        state.slots.insert(
+                            index,
+                            Stored {
+                                ballot,
+                                slot,
+                                chosen,
+                            },
+                        );
+                    }
+                    // what we accepted in the leader's ballot is what the leader has
+                    for index in state.applied + 1..=chosen {
+                        if let Some(stored) = state.slots.get_mut(&index) {
+                            if stored.ballot == ballot {
+                                stored.chosen = true;
+                            }
+                        }
+                    }
+                    self.apply(&mut state);
+                }
+                Reply::AcceptOk {
+                    in_reply_to: msg_id,
+                    promised: state.promised.clone(),
+                    applied: state.applied,
+                }
+            }
+            Rpc::Catchup {
+                msg_id,
+                ballot,
+                index,
+                snapshot,
+            } => {
+                if self.follow(&mut state, &ballot) && index > state.applied {
+                    self.install_snapshot(&mut state, index, snapshot);
+                }
+                Reply::CatchupOk {
+                    in_reply_to: msg_id,
+                    promised: state.promised.clone(),
+                }
+            }
+        };
+        drop(state);
+        self.runtime.send(src, reply)
+    }
+
+    // promise `ballot` if it is at least the one we promised already,
+    // returns whether we did
+    fn follow(&self, state: &mut State<M>, ballot: &Ballot) -> bool {
+        if *ballot < state.promised {
+            return false;
+        }
+        if *ballot > state.promised {
+            if state.role == Role::Leader {
+                info!(round = ballot.round, leader = %ballot.node, "stepping down");
+            }
+            state.role = Role::Follower;
+            state.promised = ballot.clone();
+            state.promises.clear();
+        }
+        if state.role == Role::Follower {
+            state.leader = Some(ballot.node.clone());
+            state.election_deadline = Instant::now() + self.election_timeout();
+        }
+        true
+    }
+
+    fn install_snapshot(&self, state: &mut State<M>, index: u64, snapshot: M::Snapshot) {
+        info!(slot = index, "installing snapshot");
+        state.machine.restore(snapshot.clone());
+        state.snapshot = Some(snapshot);
+        state.snapshot_index = index;
+        state.applied = index;
+        state.slots = state.slots.split_off(&(index + 1));
+    }
+
+    fn tick(&self) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        match state.role {
+            Role::Leader => {
+                if now >= state.heartbeat_due {
+                    state.heartbeat_due = now + self.config.heartbeat();
+                    drop(state);
+                    self.replicate();
+                }
+            }
+            Role::Follower | Role::Candidate => {
+                if now >= state.election_deadline {
+                    self.start_election(&mut state);
+                }
+            }
+        }
+    }
+
+    fn start_election(&self, state: &mut State<M>) {
+        let me = self.runtime.node_id().to_string();
+        state.promised = Ballot {
+            round: state.promised.round + 1,
+            node: me.clone(),
+        };
+        state.role = Role::Candidate;
+        state.leader = None;
+        state.election_deadline = Instant::now() + self.election_timeout();
+        info!(round = state.promised.round, "preparing ballot");
+
+        let first = state.applied + 1;
+        let promise = Promise {
+            accepted: state
+                .slots
+                .range(first..)
+                .map(|(index, stored)| Accepted {
+                    index: *index,
+                    ballot: stored.ballot.clone(),
+                    slot: stored.slot.clone(),
+                })
+                .collect(),
+            snapshot: None,
+        };
+        state.promises = HashMap::from([(me, promise)]);
+        if self.is_majority(state.promises.len()) {
+            self.become_leader(state);
+            return;
+        }
+
+        let ballot = state.promised.clone();
+        for peer in self.runtime.other_node_ids() {
+            let rpc: Rpc<M::Command, M::Snapshot> = Rpc::Prepare {
+                msg_id: 0,
+                ballot: ballot.clone(),
+                first,
+            };
+            let paxos = self.clone();
+            let peer = peer.clone();
+            let ballot = ballot.clone();
+            self.runtime.spawn(async move {
+                if let Ok(reply) = paxos.call(&peer, rpc).await {
+                    paxos.on_reply(&peer, &ballot, 0, 0, reply);
+                }
+                Ok(())
+            });
+        }
+    }
+
+    // phase 1 succeeded, take over every slot after the ones we applied
+    fn become_leader(&self, state: &mut State<M>) {
+        let ballot = state.promised.clone();
+        info!(round = ballot.round, "leading ballot");
+        let promises = std::mem::take(&mut state.promises);
+
+        // some acceptor already applied (and compacted) more than we did
+        let latest = promises
+            .values()
+            .filter_map(|promise| promise.snapshot.as_ref())
+            .max_by_key(|(index, _)| *index);
+        if let Some((index, snapshot)) = latest {
+            if *index > state.applied {
+                self.install_snapshot(state, *index, snapshot.clone());
+            }
+        }
+
+        // the value accepted in the highest ballot of every slot
+        let mut highest: BTreeMap<u64, Accepted<M::Command>> = BTreeMap::new();
+        for accepted in promises.into_values().flat_map(|promise| promise.accepted) {
+            if accepted.index <= state.applied {
+                continue;
+            }
+            match highest.get(&accepted.index) {
+                Some(current) if current.ballot >= accepted.ballot => {}
+                _ => {
+                    highest.insert(accepted.index, accepted);
+                }
+            }
+        }
+        let last = highest.keys().next_back().copied().unwrap_or(state.applied);
+        for index in state.applied + 1..=last {
+            let slot = match highest.remove(&index) {
+                Some(accepted) => accepted.slot,
+                None => Slot {
+                    proposed: ballot.clone(),
+                    command: None,
+                },
+            };
+            let chosen = state.slots.get(&index).is_some_and(|stored| stored.chosen);
+            let ballot = ballot.clone();
+            state.slots.insert(
+                index,
+                Stored {
+                    ballot,
+                    slot,
+                    chosen,
+                },
+            );
+        }
+
+        state.role = Role::Leader;
+        state.leader = Some(self.runtime.node_id().to_string());
+        state.next_slot = last + 1;
+        state.chosen = state.applied;
+        let first = state.applied + 1;
+        state.next_index = self
+            .runtime
+            .other_node_ids()
+            .map(|peer| (peer.clone(), first))
+            .collect();
+        state.match_index = self
+            .runtime
+            .other_node_ids()
+            .map(|peer| (peer.clone(), 0))
+            .collect();
+        // the next tick sends the first accepts
+        state.heartbeat_due = Instant::now();
+        self.advance_chosen(state);
+    }
+
+    // send every acceptor the slots (or the snapshot) it is missing
+    fn replicate(&self) {
+        for peer in self.runtime.other_node_ids() {
+            self.replicate_to(peer);
+        }
+    }
+
+    fn replicate_to(&self, peer: &str) {
+        let state = self.state.lock().unwrap();
+        if state.role != Role::Leader {
+            return;
+        }
+        let ballot = state.promised.clone();
+        let next_index = state.next_index.get(peer).copied().unwrap_or(1);
+        let (rpc, sent) = match &state.snapshot {
+            Some(snapshot) if next_index <= state.snapshot_index => (
+                Rpc::Catchup {
+                    msg_id: 0,
+                    ballot: ballot.clone(),
+                    index: state.snapshot_index,
+                    snapshot: snapshot.clone(),
+                },
+                state.snapshot_index,
+            ),
+            _ => {
+                let slots: Vec<_> = state
+                    .slots
+                    .range(next_index..)
+                    .take(self.config.max_entries)
+                    .map(|(_, stored)| stored.slot.clone())
+                    .collect();
+                let sent = next_index + slots.len() as u64 - 1;
+                (
+                    Rpc::Accept {
+                        msg_id: 0,
+                        ballot: ballot.clone(),
+                        first: next_index,
+                        slots,
+                        chosen: state.chosen,
+                    },
+                    sent,
+                )
+            }
+        };
+        let chosen = state.chosen;
+        drop(state);
+
+        let paxos = self.clone();
+        let peer = peer.to_string();
+        self.runtime.spawn(async move {
+            if let Ok(reply) = paxos.call(&peer, rpc).await {
+                paxos.on_reply(&peer, &ballot, sent, chosen, reply);
+            }
+            Ok(())
+        });
+    }
+
+    async fn call(
+        &self,
+        peer: &str,
+        rpc: Rpc<M::Command, M::Snapshot>,
+    ) -> anyhow::Result<Reply<M::Command, M::Snapshot>> {
+        // a reply arriving after the next heartbeat is useless anyway
+        let timeout = self.config.heartbeat().max(TICK);
+        self.runtime.call_timeout(peer, rpc, timeout).await
+    }
+
+    // `ballot` is the ballot we sent the request in, `sent` the last slot it covered
+    // and `chosen` what it announced as chosen
+    fn on_reply(
+        &self,
+        peer: &str,
+        ballot: &Ballot,
+        sent: u64,
+        chosen: u64,
+        reply: Reply<M::Command, M::Snapshot>,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        let promised = match &reply {
+            Reply::PrepareOk { promised, .. }
+            | Reply::AcceptOk { promised, .. }
+            | Reply::CatchupOk { promised, .. } => promised.clone(),
+        };
+        if promised > state.promised {
+            // someone else prepared a higher ballot, wait for its accepts
+            self.follow(&mut state, &promised);
+            state.leader = None;
+            return;
+        }
+        if state.promised != *ballot || promised != *ballot {
+            return;
+        }
+
+        let more = match reply {
+            Reply::PrepareOk {
+                accepted, snapshot, ..
+            } => {
+                if state.role == Role::Candidate {
+                    let promise = Promise { accepted, snapshot };
+                    state.promises.insert(peer.to_string(), promise);
+                    if self.is_majority(state.promises.len()) {
+                        self.become_leader(&mut state);
+                    }
+                }
+                false
+            }
+            _ if state.role != Role::Leader => false,
+            reply => {
+                let matched = state.match_index.entry(peer.to_string()).or_insert(0);
+                *matched = (*matched).max(sent);
+                let mut next_index = *matched + 1;
+                // the acceptor knew everything up to `chosen` is chosen but applied less,
+                // it is missing slots it never accepted in our ballot: send them again
+                if let Reply::AcceptOk { applied, .. } = reply {
+                    if applied < chosen {
+                        next_index = next_index.min(applied + 1);
+                    }
+                }
+                state.next_index.insert(peer.to_string(), next_index);
+                self.advance_chosen(&mut state);
+                next_index < state.next_slot
+            }
+        };
+        drop(state);
+        if more {
+            self.replicate_to(peer);
+        }
+    }
+
+    // choose the slots a majority accepted in our ballot
+    fn advance_chosen(&self, state: &mut State<M>) {
+        if state.role != Role::Leader {
+            return;
+        }
+        while state.chosen + 1 < state.next_slot {
+            let index = state.chosen + 1;
+            let accepted = 1 + state
+                .match_index
+                .values()
+                .filter(|&&matched| matched >= index)
+                .count();
+            if !self.is_majority(accepted) {
+                break;
+            }
+            if let Some(stored) = state.slots.get_mut(&index) {
+                stored.chosen = true;
+            }
+            state.chosen = index;
+        }
+        self.apply(state);
+    }
+
+    fn apply(&self, state: &mut State<M>) {
+        loop {
+            let index = state.applied + 1;
+            let Some(stored) = state.slots.get(&index).filter(|stored| stored.chosen) else {
+                break;
+            };
+            let slot = stored.slot.clone();
+            let output = slot
+                .command
+                .as_ref()
+                .map(|command| state.machine.apply(command));
+            state.applied = index;
+            if let Some((ballot, waiter)) = state.waiting.remove(&index) {
+                let result = match output {
+                    Some(output) if slot.proposed == ballot => Ok(output),
+                    _ => {
+                        let text = format!("slot {} was taken over by a new leader", index);
+                        Err(RpcError::new(code::ABORT, text))
+                    }
+                };
+                let _ = waiter.send(result);
+            }
+        }
+        self.compact(state);
+    }
+
+    fn compact(&self, state: &mut State<M>) {
+        let threshold = self.config.snapshot_entries;
+        if threshold == 0 || state.applied - state.snapshot_index < threshold {
+            return;
+        }
+        state.slots = state.slots.split_off(&(state.applied + 1));
+        state.snapshot = Some(state.machine.snapshot());
+        state.snapshot_index = state.applied;
+    }
+
+    fn is_majority(&self, count: usize) -> bool {
+        count * 2 > self.runtime.node_ids().len()
+    }
+
+    // somewhere between the configured timeout and twice that,
+    // so the nodes don't all prepare at once
+    fn election_timeout(&self) -> Duration {
+        let base = self.config.election_timeout();
+        let random = self.runtime.random();
+        base + Duration::from_millis(random % (base.as_millis() as u64).max(1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::tests::Log;
+
+    fn paxos() -> Paxos<Log> {
+        let runtime = Runtime::initialized("n0", &["n0", "n1", "n2"]);
+        Paxos::new(runtime, RaftConfig::default(), Log::default())
+    }
+
+    fn ballot(round: u64, node: &str) -> Ballot {
+        Ballot {
+            round,
+            node: node.to_string(),
+        }
+    }
+
+    fn slot(proposed: &Ballot, command: Option<u64>) -> Slot<u64> {
+        Slot {
+            proposed: proposed.clone(),
+            command,
+        }
+    }
+
+    fn prepare(paxos: &Paxos<Log>, ballot: &Ballot) {
+        let rpc = Rpc::Prepare {
+            msg_id: 1,
+            ballot: ballot.clone(),
+            first: 1,
+        };
+        paxos.handle(&ballot.node, rpc).unwrap();
+    }
+
+    fn accept(paxos: &Paxos<Log>, ballot: &Ballot, commands: &[u64], chosen: u64) {
+        let rpc = Rpc::Accept {
+            msg_id: 1,
+            ballot: ballot.clone(),
+            first: 1,
+            slots: commands
+                .iter()
+                .map(|&command| slot(ballot, Some(command)))
+                .collect(),
+            chosen,
+        };
+        paxos.handle(&ballot.node, rpc).unwrap();
+    }
+
+    // the ballot and command of every slot the acceptor stores
+    fn accepted(paxos: &Paxos<Log>) -> Vec<(Ballot, Option<u64>)> {
+        let state = paxos.state.lock().unwrap();
+        let slots = state.slots.values();
+        slots
+            .map(|stored| (stored.ballot.clone(), stored.slot.command))
+            .collect()
+    }
+
+    #[test]
+    fn ballots_are_ordered_by_round_then_node() {
+        assert!(ballot(1, "n2") < ballot(2, "n0"));
+        assert!(ballot(2, "n0") < ballot(2, "n1"));
+    }
+
+    #[test]
+    fn acceptor_ignores_ballots_below_its_promise() {
+        let paxos = paxos();
+        prepare(&paxos, &ballot(2, "n1"));
+        prepare(&paxos, &ballot(1, "n2"));
+        assert_eq!(paxos.state.lock().unwrap().promised, ballot(2, "n1"));
+
+        // an accept of a lower ballot is rejected, one of the promised ballot isn't
+        accept(&paxos, &ballot(1, "n2"), &[10], 0);
+        assert_eq!(accepted(&paxos), []);
+        accept(&paxos, &ballot(2, "n1"), &[20], 0);
+        assert_eq!(accepted(&paxos), [(ballot(2, "n1"), Some(20))]);
+
+        // a higher ballot's accept counts as its prepare
+        accept(&paxos, &ballot(3, "n2"), &[30], 0);
+        assert_eq!(paxos.state.lock().unwrap().promised, ballot(3, "n2"));
+        assert_eq!(accepted(&paxos), [(ballot(3, "n2"), Some(30))]);
+        prepare(&paxos, &ballot(2, "n1"));
+        assert_eq!(paxos.state.lock().unwrap().promised, ballot(3, "n2"));
+    }
+
+    #[test]
+    fn acceptor_applies_what_it_accepted_in_the_chosen_ballot() {
+        let paxos = paxos();
+        accept(&paxos, &ballot(1, "n1"), &[1, 2], 0);
+        // a new leader re-proposes slot 2 and says everything up to 2 is chosen,
+        // slot 2 still holds the old ballot's value so it can't be applied
+        let rpc = Rpc::Accept {
+            msg_id: 1,
+            ballot: ballot(2, "n2"),
+            first: 1,
+            slots: vec![slot(&ballot(1, "n1"), Some(1))],
+            chosen: 2,
+        };
+        paxos.handle("n2", rpc).unwrap();
+        let state = paxos.state.lock().unwrap();
+        assert_eq!(state.applied, 1);
+        assert_eq!(state.machine.0, [1]);
+    }
+
+    #[test]
+    fn leader_takes_over_the_values_of_the_highest_ballots() {
+        let paxos = paxos();
+        let mut state = paxos.state.lock().unwrap();
+        let (old, older) = (ballot(2, "n1"), ballot(1, "n2"));
+        let accepted = |index, ballot: &Ballot, command| Accepted {
+            index,
+            ballot: ballot.clone(),
+            slot: slot(ballot, Some(command)),
+        };
+        state.promised = ballot(3, "n0");
+        state.role = Role::Candidate;
+        let promises = [
+            ("n0", vec![accepted(1, &older, 10)]),
+            ("n1", vec![accepted(1, &old, 11), accepted(3, &old, 31)]),
+        ];
+        for (node, accepted) in promises {
+            let promise = Promise {
+                accepted,
+                snapshot: None,
+            };
+            state.promises.insert(node.to_string(), promise);
+        }
+        paxos.become_leader(&mut state);
+
+        assert_eq!(state.role, Role::Leader);
+        assert_eq!(state.next_slot, 4);
+        let slots: Vec<_> = state
+            .slots
+            .iter()
+            .map(|(index, stored)| (*index, stored.ballot.clone(), stored.slot.command))
+            .collect();
+        // every slot is proposed again in the new ballot, the hole as a no-op
+        let leading = ballot(3, "n0");
+        assert_eq!(
+            slots,
+            [
+                (1, leading.clone(), Some(11)),
+                (2, leading.clone(), None),
+                (3, leading, Some(31)),
+            ]
+        );
+        // nothing is chosen before a majority accepted it
+        assert_eq!(state.chosen, 0);
+    }
+}
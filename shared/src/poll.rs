@@ -0,0 +1,133 @@
+use crate::config::KafkaConfig;
+
+// how much a single kafka poll response may still hold, over all keys
+//
+// clients can pass `max_messages` and `max_bytes` hints with a poll, the per key
+// limit from the config always applies on top. the first message of a response is
+// always taken, even if it doesn't fit the byte budget, so a consumer never gets stuck
+#[derive(Debug)]
+pub struct PollBudget {
+    per_key: usize,
+    messages: Option<usize>,
+    bytes: Option<usize>,
+    taken: usize,
+}
+
+impl PollBudget {
+    pub fn new(
+        config: &KafkaConfig,
+        max_messages: Option<usize>,
+        max_bytes: Option<usize>,
+    ) -> Self {
+        PollBudget {
+            per_key: config.poll_limit,
+            messages: max_messages,
+            bytes: max_bytes,
+            taken: 0,
+        }
+    }
+
+    // the most messages the next key can return
+    pub fn key_limit(&self) -> usize {
+        match self.messages {
+            Some(messages) => self.per_key.min(messages),
+            None => self.per_key,
+        }
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.key_limit() == 0 || self.bytes == Some(0)
+    }
+
+    // account for one [offset, msg] pair, false if it doesn't fit anymore
+    pub fn take(&mut self, offset: u64, msg: u64) -> bool {
+        if self.is_exhausted() {
+            return false;
+        }
+        if let Some(bytes) = self.bytes {
+            let size = entry_size(offset, msg);
+            if size > bytes && self.taken > 0 {
+                return false;
+            }
+            self.bytes = Some(bytes.saturating_sub(size));
+        }
+        if let Some(messages) = &mut self.messages {
+            *messages -= 1;
+        }
+        self.taken += 1;
+        true
+    }
+
+    // take the longest prefix of `entries` that fits
+    pub fn fill(&mut self, entries: impl IntoIterator<Item = (u64, u64)>) -> Vec<Vec<u64>> {
+        let limit = self.key_limit();
+        let mut response = Vec::new();
+        for (offset, msg) in entries.into_iter().take(limit) {
+            if !self.take(offset, msg) {
+                break;
+            }
+            response.push(vec![offset, msg]);
+        }
+        response
+    }
+}
+
+// the size of `[offset,msg],` in the json response
+fn entry_size(offset: u64, msg: u64) -> usize {
+    offset.to_string().len() + msg.to_string().len() + 4
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn budget(
+        poll_limit: usize,
+        max_messages: Option<usize>,
+        max_bytes: Option<usize>,
+    ) -> PollBudget {
+        let config = KafkaConfig {
+            poll_limit,
+            ..KafkaConfig::default()
+        };
+        PollBudget::new(&config, max_messages, max_bytes)
+    }
+
+    // offsets from `first` on, the message is the offset times 10
+    fn entries(first: u64) -> impl Iterator<Item = (u64, u64)> {
+        (first..first + 20).map(|offset| (offset, offset * 10))
+    }
+
+    #[test]
+    fn message_budget_is_shared_by_all_keys() {
+        let mut budget = budget(3, Some(5), None);
+        assert_eq!(budget.fill(entries(0)).len(), 3);
+        assert_eq!(budget.fill(entries(0)), [[0, 0], [1, 10]]);
+        assert!(budget.is_exhausted());
+        assert!(budget.fill(entries(0)).is_empty());
+    }
+
+    #[test]
+    fn per_key_limit_applies_without_hints() {
+        let mut budget = budget(3, None, None);
+        for _ in 0..10 {
+            assert_eq!(budget.fill(entries(7)).len(), 3);
+        }
+    }
+
+    #[test]
+    fn byte_budget_stops_before_the_entry_that_does_not_fit() {
+        // `[1,10],` takes 7 bytes
+        let mut budget = budget(100, None, Some(16));
+        assert_eq!(budget.fill(entries(1)), [[1, 10], [2, 20]]);
+        assert!(!budget.take(3, 30));
+        assert!(budget.fill(entries(1)).is_empty());
+    }
+
+    #[test]
+    fn first_entry_is_always_taken() {
+        let mut budget = budget(100, None, Some(1));
+        assert_eq!(budget.fill(entries(5)), [[5, 50]]);
+        assert!(budget.is_exhausted());
+    }
+}
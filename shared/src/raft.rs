@@ -0,0 +1,827 @@
+use crate::config::RaftConfig;
+use crate::consensus::{Role, StateMachine};
+use crate::error::{code, RpcError};
+use crate::runtime::Runtime;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+use tracing::info;
+
+// raft consensus, replicating a `StateMachine` over all the nodes of the cluster
+// see https://raft.github.io/raft.pdf
+//
+// - every node starts as a follower, a follower which doesn't hear from a leader for a
+//   (randomized) election timeout becomes a candidate and asks the others for votes
+// - the leader appends proposed commands to its log and replicates them with
+//   append_entries, an entry is committed once a majority stored it
+// - committed entries are applied to the state machine in log order on every node,
+//   `Raft::propose` returns the output of applying the command on the leader
+// - the applied prefix of the log is replaced by a snapshot of the state machine now
+//   and then, followers which fall behind that prefix get the snapshot instead
+//
+// the binary routes the `Rpc` messages it receives to `Raft::handle`, the replies
+// go straight back to the rpcs of the leader / candidate
+//
+// the state is kept in memory only, so a node must not come back after a crash
+// (maelstrom only crashes nodes for good, or partitions them)
+
+// how often the election / heartbeat timers are checked
+const TICK: Duration = Duration::from_millis(10);
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(bound = "C: Serialize + DeserializeOwned")]
+pub struct Entry<C> {
+    pub term: u64,
+    pub command: C,
+}
+
+// the requests raft nodes send each other
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+#[serde(bound = "C: Serialize + DeserializeOwned, S: Serialize + DeserializeOwned")]
+pub enum Rpc<C, S> {
+    RequestVote {
+        msg_id: u64,
+        term: u64,
+        candidate: String,
+        last_log_index: u64,
+        last_log_term: u64,
+    },
+    AppendEntries {
+        msg_id: u64,
+        term: u64,
+        leader: String,
+        prev_log_index: u64,
+        prev_log_term: u64,
+        entries: Vec<Entry<C>>,
+        leader_commit: u64,
+    },
+    InstallSnapshot {
+        msg_id: u64,
+        term: u64,
+        leader: String,
+        last_included_index: u64,
+        last_included_term: u64,
+        snapshot: S,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+#[allow(clippy::enum_variant_names)]
+enum Reply {
+    RequestVoteOk {
+        in_reply_to: u64,
+        term: u64,
+        vote_granted: bool,
+    },
+    AppendEntriesOk {
+        in_reply_to: u64,
+        term: u64,
+        success: bool,
+        // the last entry the follower has in common with the leader on success,
+        // where the leader should continue on a mismatch
+        match_index: u64,
+        next_index: u64,
+    },
+    InstallSnapshotOk {
+        in_reply_to: u64,
+        term: u64,
+    },
+}
+
+type Waiter<O> = oneshot::Sender<Result<O, RpcError>>;
+
+struct State<M: StateMachine> {
+    role: Role,
+    term: u64,
+    voted_for: Option<String>,
+    leader: Option<String>,
+    votes: HashSet<String>,
+    election_deadline: Instant,
+    heartbeat_due: Instant,
+
+    // entries after the snapshot, the first one has index `snapshot_index + 1`
+    log: Vec<Entry<M::Command>>,
+    snapshot: Option<M::Snapshot>,
+    snapshot_index: u64,
+    snapshot_term: u64,
+    commit_index: u64,
+    last_applied: u64,
+    machine: M,
+
+    // leader only
+    next_index: HashMap<String, u64>,
+    match_index: HashMap<String, u64>,
+    // proposals waiting for their entry to be applied, by log index
+    waiting: HashMap<u64, (u64, Waiter<M::Output>)>,
+}
+
+impl<M: StateMachine> State<M> {
+    fn last_index(&self) -> u64 {
+        self.snapshot_index + self.log.len() as u64
+    }
+
+    fn last_term(&self) -> u64 {
+        self.log
+            .last()
+            .map_or(self.snapshot_term, |entry| entry.term)
+    }
+
+    // `None` if the entry is not in the log (compacted or not there yet)
+    fn term_at(&self, index: u64) -> Option<u64> {
+        if index == self.snapshot_index {
+            return Some(self.snapshot_term);
+        }
+        self.entry(index).map(|entry| entry.term)
+    }
+
+    fn entry(&self, index: u64) -> Option<&Entry<M::Command>> {
+        if index <= self.snapshot_index {
+            return None;
+        }
+        self.log.get((index - self.snapshot_index - 1) as usize)
+    }
+
+    // the term of `candidate`'s last entry is newer, or the same but its log is at least as long
+    fn is_up_to_date(&self, last_log_index: u64, last_log_term: u64) -> bool {
+        (last_log_term, last_log_index) >= (self.last_term(), self.last_index())
+    }
+}
+
+pub struct Raft<M: StateMachine> {
+    runtime: Runtime,
+    config: RaftConfig,
+    state: Arc<Mutex<State<M>>>,
+}
+
+impl<M: StateMachine> Clone for Raft<M> {
+    fn clone(&self) -> Self {
+        Raft {
+            runtime: self.runtime.clone(),
+            config: self.config.clone(),
+            state: Arc::clone(&self.state),
+        }
+    }
+}
+
+impl<M: StateMachine> Raft<M> {
+    pub fn new(runtime: Runtime, config: RaftConfig, machine: M) -> Self {
+        let now = Instant::now();
+        let state = State {
+            role: Role::Follower,
+            term: 0,
+            voted_for: None,
+            leader: None,
+            votes: HashSet::new(),
+            election_deadline: now,
+            heartbeat_due: now,
+            log: Vec::new(),
+            snapshot: None,
+            snapshot_index: 0,
+            snapshot_term: 0,
+            commit_index: 0,
+            last_applied: 0,
+            machine,
+            next_index: HashMap::new(),
+            match_index: HashMap::new(),
+            waiting: HashMap::new(),
+        };
+        Raft {
+            runtime,
+            config,
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    // start the timers, call this from `Node::init`
+    pub fn start(&self) {
+        self.state.lock().unwrap().election_deadline = Instant::now() + self.election_timeout();
+        let raft = self.clone();
+        self.runtime.every(TICK, move || {
+            raft.tick();
+            async { Ok(()) }
+        });
+    }
+
+    pub fn role(&self) -> Role {
+        self.state.lock().unwrap().role
+    }
+
+    // the leader of the current term, as far as this node knows
+    pub fn leader(&self) -> Option<String> {
+        self.state.lock().unwrap().leader.clone()
+    }
+
+    // replicate `command` and wait until it is applied, only works on the leader
+    //
+    // fails with `TEMPORARILY_UNAVAILABLE` if this node is not the leader (the command
+    // definitely wasn't applied), `ABORT` if a new leader replaced the entry and
+    // `TIMEOUT` if the outcome is unknown
+    pub async fn propose(&self, command: M::Command) -> anyhow::Result<M::Output> {
+        let (index, receiver) = {
+            let mut state = self.state.lock().unwrap();
+            if state.role != Role::Leader {
+                let text = match &state.leader {
+                    Some(leader) => format!("not the leader, {} is", leader),
+                    None => "not the leader, there is no leader right now".to_string(),
+                };
+                return Err(RpcError::new(code::TEMPORARILY_UNAVAILABLE, text).into());
+            }
+            let term = state.term;
+            state.log.push(Entry { term, command });
+            let index = state.last_index();
+            let (sender, receiver) = oneshot::channel();
+            state.waiting.insert(index, (term, sender));
+            // a single node cluster commits right away
+            self.advance_commit(&mut state);
+            (index, receiver)
+        };
+        self.replicate();
+
+        match tokio::time::timeout(self.config.propose_timeout(), receiver).await {
+            Ok(Ok(result)) => Ok(result?),
+            _ => {
+                self.state.lock().unwrap().waiting.remove(&index);
+                let text = format!("entry {} was not committed in time", index);
+                Err(RpcError::new(code::TIMEOUT, text).into())
+            }
+        }
+    }
+
+    // answer a request of another raft node
+    pub fn handle(&self, src: &str, rpc: Rpc<M::Command, M::Snapshot>) -> anyhow::Result<()> {
+        let reply = match rpc {
+            Rpc::RequestVote {
+                msg_id,
+                term,
+                candidate,
+                last_log_index,
+                last_log_term,
+            } => {
+                let mut state = self.state.lock().unwrap();
+                if term > state.term {
+                    self.step_down(&mut state, term);
+                }
+                let vote_granted = term == state.term
+                    && state
+                        .voted_for
+                        .as_ref()
+                        .is_none_or(|voted| *voted == candidate)
+                    && state.is_up_to_date(last_log_index, last_log_term);
+                if vote_granted {
+                    state.voted_for = Some(candidate);
+                    state.election_deadline = Instant::now() + self.election_timeout();
+                }
+                Reply::RequestVoteOk {
+                    in_reply_to: msg_id,
+                    term: state.term,
+                    vote_granted,
+                }
+            }
+            Rpc::AppendEntries {
+                msg_id,
+                term,
+                leader,
+                prev_log_index,
+                prev_log_term,
+                entries,
+                leader_commit,
+            } => {
+                let mut state = self.state.lock().unwrap();
+                self.append_entries(
+                    &mut state,
+                    msg_id,
+                    term,
+                    leader,
+                    prev_log_index,
+                    prev_log_term,
+                    entries,
+                    leader_commit,
+                )
+            }
+            Rpc::InstallSnapshot {
+                msg_id,
+                term,
+                leader,
+                last_included_index,
+                last_included_term,
+                snapshot,
+            } => {
+                let mut state = self.state.lock().unwrap();
+                if term >= state.term {
+                    if term > state.term || state.role != Role::Follower {
+                        self.step_down(&mut state, term);
+                    }
+                    state.leader = Some(leader);
+                    state.election_deadline = Instant::now() + self.election_timeout();
+                    if last_included_index > state.commit_index {
+                        self.install_snapshot(
+                            &mut state,
+                            last_included_index,
+                            last_included_term,
+                            snapshot,
+                        );
+                    }
+                }
+                Reply::InstallSnapshotOk {
+                    in_reply_to: msg_id,
+                    term: state.term,
+                }
+            }
+        };
+        self.runtime.send(src, reply)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn append_entries(
+        &self,
+        state: &mut State<M>,
+        msg_id: u64,
+        term: u64,
+        leader: String,
+        mut prev_log_index: u64,
+        mut prev_log_term: u64,
+        mut entries: Vec<Entry<M::Command>>,
+        leader_commit: u64,
+    ) -> Reply {
+        let reject = |state: &State<M>, next_index: u64| Reply::AppendEntriesOk {
+            in_reply_to: msg_id,
+            term: state.term,
+            success: false,
+            match_index: 0,
+            next_index,
+        };
+        if term < state.term {
+            return reject(state, state.last_index() + 1);
+        }
+        if term > state.term || state.role != Role::Follower {
+            self.step_down(state, term);
+        }
+        state.leader = Some(leader);
+        state.election_deadline = Instant::now() + self.election_timeout();
+
+        // the entries the snapshot covers are committed, they can only match
+        if prev_log_index < state.snapshot_index {
+            let covered = (state.snapshot_index - prev_log_index) as usize;
+            entries.drain(..covered.min(entries.len()));
+            prev_log_index = state.snapshot_index;
+            prev_log_term = state.snapshot_term;
+        }
+        match state.term_at(prev_log_index) {
+            None => return reject(state, state.last_index() + 1),
+            Some(conflict) if conflict != prev_log_term => {
+                // skip all the entries of the conflicting term at once
+                let mut next_index = prev_log_index;
+                while next_index > state.snapshot_index + 1
+                    && state.term_at(next_index - 1) == Some(conflict)
+                {
+                    next_index -= 1;
+                }
+                return reject(state, next_index);
+            }
+            Some(_) => {}
+        }
+
+        let match_index = prev_log_index + entries.len() as u64;
+        for (index, entry) in (prev_log_index + 1..).zip(entries) {
+            match state.term_at(index) {
+                Some(term) if term == entry.term => continue,
+                Some(_) => {
+                    // everything from here on was never committed, a new leader replaced it
+                    let keep = (index - state.snapshot_index - 1) as usize;
+                    state.log.truncate(keep);
+                    state.log.push(entry);
+                }
+                None => state.log.push(entry),
+            }
+        }
+        if leader_commit > state.commit_index {
+            state.commit_index = leader_commit.min(match_index).max(state.commit_index);
+            self.apply(state);
+        }
+        Reply::AppendEntriesOk {
+            in_reply_to: msg_id,
+            term: state.term,
+            success: true,
+            match_index,
+            next_index: match_index + 1,
+        }
+    }
+
+    fn install_snapshot(
+        &self,
+        state: &mut State<M>,
+        last_included_index: u64,
+        last_included_term: u64,
+        snapshot: M::Snapshot,
+    ) {
+        // keep the entries after the snapshot if the log agrees with it
+        if state.term_at(last_included_index) == Some(last_included_term) {
+            let covered = (last_included_index - state.snapshot_index) as usize;
+            state.log.drain(..covered);
+        } else {
+            state.log.clear();
+        }
+        info!(index = last_included_index, "installing snapshot");
+        state.machine.restore(snapshot.clone());
+        state.snapshot = Some(snapshot);
+        state.snapshot_index = last_included_index;
+        state.snapshot_term = last_included_term;
+        state.commit_index = last_included_index;
+        state.last_applied = last_included_index;
+    }
+
+    fn tick(&self) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        match state.role {
+            Role::Leader => {
+                if now >= state.heartbeat_due {
+                    state.heartbeat_due = now + self.config.heartbeat();
+                    drop(state);
+                    self.replicate();
+                }
+            }
+            Role::Follower | Role::Candidate => {
+                if now >= state.election_deadline {
+                    self.start_election(&mut state);
+                }
+            }
+        }
+    }
+
+    fn start_election(&self, state: &mut State<M>) {
+        state.term += 1;
+        state.role = Role::Candidate;
+        state.leader = None;
+        state.voted_for = Some(self.runtime.node_id().to_string());
+        state.votes = HashSet::from([self.runtime.node_id().to_string()]);
+        state.election_deadline = Instant::now() + self.election_timeout();
+        info!(term = state.term, "starting election");
+        if self.is_majority(state.votes.len()) {
+            self.become_leader(state);
+            return;
+        }
+
+        let term = state.term;
+        for peer in self.runtime.other_node_ids() {
+            let rpc: Rpc<M::Command, M::Snapshot> = Rpc::RequestVote {
+                msg_id: 0,
+                term,
+                candidate: self.runtime.node_id().to_string(),
+                last_log_index: state.last_index(),
+                last_log_term: state.last_term(),
+            };
+            let raft = self.clone();
+            let peer = peer.clone();
+            self.runtime.spawn(async move {
+                if let Ok(reply) = raft.call(&peer, rpc).await {
+                    raft.on_reply(&peer, term, 0, reply);
+                }
+                Ok(())
+            });
+        }
+    }
+
+    fn become_leader(&self, state: &mut State<M>) {
+        info!(term = state.term, "became leader");
+        state.role = Role::Leader;
+        state.leader = Some(self.runtime.node_id().to_string());
+        let next_index = state.last_index() + 1;
+        state.next_index = self
+            .runtime
+            .other_node_ids()
+            .map(|peer| (peer.clone(), next_index))
+            .collect();
+        state.match_index = self
+            .runtime
+            .other_node_ids()
+            .map(|peer| (peer.clone(), 0))
+            .collect();
+        // the next tick sends the first heartbeats
+        state.heartbeat_due = Instant::now();
+    }
+
+    // a newer term showed up (or another node won the election of our term)
+    fn step_down(&self, state: &mut State<M>, term: u64) {
+        if term > state.term {
+            state.term = term;
+            state.voted_for = None;
+            state.leader = None;
+        }
+        if state.role == Role::Leader {
+            info!(term = state.term, "stepping down");
+        }
+        state.role = Role::Follower;
+        state.election_deadline = Instant::now() + self.election_timeout();
+    }
+
+    // send every follower the entries (or the snapshot) it is missing
+    fn replicate(&self) {
+        for peer in self.runtime.other_node_ids() {
+            self.replicate_to(peer);
+        }
+    }
+
+    fn replicate_to(&self, peer: &str) {
+        let state = self.state.lock().unwrap();
+        if state.role != Role::Leader {
+            return;
+        }
+        let term = state.term;
+        let leader = self.runtime.node_id().to_string();
+        let next_index = state.next_index.get(peer).copied().unwrap_or(1);
+        let (rpc, sent_up_to) = match &state.snapshot {
+            Some(snapshot) if next_index <= state.snapshot_index => (
+                Rpc::InstallSnapshot {
+                    msg_id: 0,
+                    term,
+                    leader,
+                    last_included_index: state.snapshot_index,
+                    last_included_term: state.snapshot_term,
+                    snapshot: snapshot.clone(),
+                },
+                state.snapshot_index,
+            ),
+            _ => {
+                let prev_log_index = next_index - 1;
+                let first = (next_index - state.snapshot_index - 1) as usize;
+                let entries: Vec<_> = state.log[first.min(state.log.len())..]
+                    .iter()
+                    .take(self.config.max_entries)
+                    .cloned()
+                    .collect();
+                (
+                    Rpc::AppendEntries {
+                        msg_id: 0,
+                        term,
+                        leader,
+                        prev_log_index,
+                        prev_log_term: state.term_at(prev_log_index).unwrap_or(0),
+                        entries,
+                        leader_commit: state.commit_index,
+                    },
+                    0,
+                )
+            }
+        };
+        drop(state);
+
+        let raft = self.clone();
+        let peer = peer.to_string();
+        self.runtime.spawn(async move {
+            if let Ok(reply) = raft.call(&peer, rpc).await {
+                raft.on_reply(&peer, term, sent_up_to, reply);
+            }
+            Ok(())
+        });
+    }
+
+    async fn call(&self, peer: &str, rpc: Rpc<M::Command, M::Snapshot>) -> anyhow::Result<Reply> {
+        // a reply arriving after the next heartbeat is useless anyway
+        let timeout = self.config.heartbeat().max(TICK);
+        self.runtime.call_timeout(peer, rpc, timeout).await
+    }
+
+    // `term` is the term we sent the request in, `snapshot_index` the last index
+    // of the snapshot if it was an install_snapshot
+    fn on_reply(&self, peer: &str, term: u64, snapshot_index: u64, reply: Reply) {
+        let mut state = self.state.lock().unwrap();
+        let reply_term = match &reply {
+            Reply::RequestVoteOk { term, .. }
+            | Reply::AppendEntriesOk { term, .. }
+            | Reply::InstallSnapshotOk { term, .. } => *term,
+        };
+        if reply_term > state.term {
+            self.step_down(&mut state, reply_term);
+            return;
+        }
+        if state.term != term {
+            return;
+        }
+
+        let more = match reply {
+            Reply::RequestVoteOk { vote_granted, .. } => {
+                if state.role == Role::Candidate && vote_granted {
+                    state.votes.insert(peer.to_string());
+                    if self.is_majority(state.votes.len()) {
+                        self.become_leader(&mut state);
+                    }
+                }
+                false
+            }
+            _ if state.role != Role::Leader => false,
+            Reply::AppendEntriesOk {
+                success: true,
+                match_index,
+                ..
+            } => self.on_match(&mut state, peer, match_index),
+            Reply::AppendEntriesOk { next_index, .. } => {
+                state.next_index.insert(peer.to_string(), next_index.max(1));
+                true
+            }
+            Reply::InstallSnapshotOk { .. } => self.on_match(&mut state, peer, snapshot_index),
+        };
+        drop(state);
+        if more {
+            self.replicate_to(peer);
+        }
+    }
+
+    // `peer` stores everything up to `match_index`, returns whether it is still missing entries
+    fn on_match(&self, state: &mut State<M>, peer: &str, match_index: u64) -> bool {
+        let matched = state.match_index.entry(peer.to_string()).or_insert(0);
+        *matched = (*matched).max(match_index);
+        let next_index = *matched + 1;
+        state.next_index.insert(peer.to_string(), next_index);
+        self.advance_commit(state);
+        next_index <= state.last_index()
+    }
+
+    // commit the latest entry of the current term a majority has stored
+    //
+    // entries of older terms are only committed along with it, see section 5.4.2
+    fn advance_commit(&self, state: &mut State<M>) {
+        if state.role != Role::Leader {
+            return;
+        }
+        for index in (state.commit_index + 1..=state.last_index()).rev() {
+            if state.term_at(index) != Some(state.term) {
+                break;
+            }
+            let stored = 1 + state
+                .match_index
+                .values()
+                .filter(|&&matched| matched >= index)
+                .count();
+            if self.is_majority(stored) {
+                state.commit_index = index;
+                break;
+            }
+        }
+        self.apply(state);
+    }
+
+    fn apply(&self, state: &mut State<M>) {
+        while state.last_applied < state.commit_index {
+            let index = state.last_applied + 1;
+            let Some(entry) = state.entry(index).cloned() else {
+                break;
+            };
+            let output = state.machine.apply(&entry.command);
+            state.last_applied = index;
+            if let Some((term, waiter)) = state.waiting.remove(&index) {
+                let result = if term == entry.term {
+                    Ok(output)
+                } else {
+                    let text = format!("entry {} was replaced by a new leader", index);
+                    Err(RpcError::new(code::ABORT, text))
+                };
+                let _ = waiter.send(result);
+            }
+        }
+        self.compact(state);
+    }
+
+    fn compact(&self, state: &mut State<M>) {
+        let threshold = self.config.snapshot_entries;
+        if threshold == 0 || state.last_applied - state.snapshot_index < threshold {
+            return;
+        }
+        let Some(term) = state.term_at(state.last_applied) else {
+            return;
+        };
+        let covered = (state.last_applied - state.snapshot_index) as usize;
+        state.log.drain(..covered);
+        state.snapshot = Some(state.machine.snapshot());
+        state.snapshot_index = state.last_applied;
+        state.snapshot_term = term;
+    }
+
+    fn is_majority(&self, count: usize) -> bool {
+        count * 2 > self.runtime.node_ids().len()
+    }
+
+    // somewhere between the configured timeout and twice that,
+    // so the nodes don't all start their elections at once
+    fn election_timeout(&self) -> Duration {
+        let base = self.config.election_timeout();
+        let random = self.runtime.random();
+        base + Duration::from_millis(random % (base.as_millis() as u64).max(1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::tests::Log;
+
+    fn raft() -> Raft<Log> {
+        let runtime = Runtime::initialized("n0", &["n0", "n1", "n2"]);
+        Raft::new(runtime, RaftConfig::default(), Log::default())
+    }
+
+    // the command of every entry is its index
+    fn entries(first: u64, terms: &[u64]) -> Vec<Entry<u64>> {
+        (first..)
+            .zip(terms)
+            .map(|(command, &term)| Entry { term, command })
+            .collect()
+    }
+
+    fn terms(state: &State<Log>) -> Vec<u64> {
+        state.log.iter().map(|entry| entry.term).collect()
+    }
+
+    // returns (success, match_index, next_index)
+    fn append(
+        raft: &Raft<Log>,
+        term: u64,
+        prev: (u64, u64),
+        new: &[u64],
+        commit: u64,
+    ) -> (bool, u64, u64) {
+        let mut state = raft.state.lock().unwrap();
+        let entries = entries(prev.0 + 1, new);
+        let reply = raft.append_entries(
+            &mut state,
+            1,
+            term,
+            "n1".to_string(),
+            prev.0,
+            prev.1,
+            entries,
+            commit,
+        );
+        match reply {
+            Reply::AppendEntriesOk {
+                success,
+                match_index,
+                next_index,
+                ..
+            } => (success, match_index, next_index),
+            reply => panic!("unexpected reply {:?}", reply),
+        }
+    }
+
+    #[test]
+    fn append_entries_checks_the_previous_entry() {
+        let raft = raft();
+        assert_eq!(append(&raft, 1, (0, 0), &[1, 1], 0), (true, 2, 3));
+        // a gap, the leader has to go back to the end of our log
+        assert_eq!(append(&raft, 1, (5, 1), &[1], 0), (false, 0, 3));
+        assert_eq!(append(&raft, 2, (2, 1), &[2, 2], 0), (true, 4, 5));
+        // index 4 holds term 2, the leader skips back to the first entry of that term
+        assert_eq!(append(&raft, 3, (4, 3), &[3], 0), (false, 0, 3));
+        // a request of an older term is rejected
+        assert!(!append(&raft, 2, (4, 2), &[2], 0).0);
+        assert_eq!(terms(&raft.state.lock().unwrap()), [1, 1, 2, 2]);
+    }
+
+    #[test]
+    fn append_entries_truncates_conflicts_only() {
+        let raft = raft();
+        append(&raft, 2, (0, 0), &[1, 1, 2, 2], 0);
+        // a stale (shorter) append of entries we have doesn't drop the later ones
+        assert_eq!(append(&raft, 2, (0, 0), &[1, 1], 0), (true, 2, 3));
+        assert_eq!(terms(&raft.state.lock().unwrap()), [1, 1, 2, 2]);
+
+        // a new leader replaced everything from index 3 on
+        assert_eq!(append(&raft, 3, (2, 1), &[3], 3), (true, 3, 4));
+        let state = raft.state.lock().unwrap();
+        assert_eq!(terms(&state), [1, 1, 3]);
+        // committed up to the entries the leader sent, not beyond
+        assert_eq!(state.commit_index, 3);
+        assert_eq!(state.machine.0, [1, 2, 3]);
+    }
+
+    #[test]
+    fn leader_commits_older_terms_only_with_its_own() {
+        let raft = raft();
+        let mut state = raft.state.lock().unwrap();
+        state.log = entries(1, &[1, 2]);
+        state.role = Role::Leader;
+        state.term = 3;
+
+        // a majority stores the entries of earlier terms, they still aren't committed
+        state.match_index.insert("n1".to_string(), 2);
+        raft.advance_commit(&mut state);
+        assert_eq!(state.commit_index, 0);
+
+        // once an entry of the current term is stored on a majority everything up to it is
+        state.log.push(Entry {
+            term: 3,
+            command: 3,
+        });
+        raft.on_match(&mut state, "n2", 3);
+        assert_eq!(state.commit_index, 3);
+        assert_eq!(state.machine.0, [1, 2, 3]);
+    }
+}
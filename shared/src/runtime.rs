@@ -0,0 +1,677 @@
+use crate::config::MetricsConfig;
+use crate::error::{code, RpcError};
+use crate::message::Message;
+use crate::metrics::Metrics;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{LineWriter, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinSet;
+use tokio::time::{Instant, MissedTickBehavior};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn, Instrument, Span};
+
+// async runtime shared by all the challenge binaries
+//
+// - stdin is read by a single task, every message is processed in its own task
+// - everything written to stdout goes through a single output task,
+//   so lines from concurrent handlers / background tasks never interleave
+// - `init` is answered by the runtime itself, nodes only see the workload messages
+// - periodic work (gossip etc.) runs on tokio timers via `Runtime::every`
+// - `Runtime::call` sends an rpc and waits for the message replying to it, replies
+//   arriving after the call timed out are dropped without reaching the node (for
+//   the last `MAX_EXPIRED` timeouts)
+// - on stdin EOF or SIGTERM/SIGINT the runtime shuts down: background tasks and
+//   pending rpcs are cancelled, `Node::shutdown` gets a last chance to send what is
+//   still queued, and the output task drains before `run` returns
+// - `run_with` does the same on any reader / writer, which is how `sim` runs
+//   several nodes in one process
+// - with `with_trace` every line read and written is also recorded in a trace file,
+//   which `trace-replay` can feed back into the node later
+// - with `with_metrics` the runtime counts what goes through it and dumps the
+//   numbers periodically, see `metrics`
+// - handlers and background tasks run in a `node` span and every message in a `msg`
+//   span below it, so whatever they log names the node and message, see `log`
+
+// how long `call` waits for a reply
+const RPC_TIMEOUT: Duration = Duration::from_secs(1);
+// how many timed out rpcs are remembered, a reply to an older one reaches the node
+const MAX_EXPIRED: usize = 10_000;
+
+#[async_trait]
+pub trait Node: Send + Sync + 'static {
+    // messages this node understands, usually an enum tagged by `type`
+    type Body: DeserializeOwned + Send + 'static;
+
+    // called once the init message was processed, before any other message
+    // spawn background tasks from here
+    async fn init(&self, _runtime: Runtime) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    // called concurrently for every incoming message
+    // an error is sent back to the sender as an `error` message
+    async fn process(&self, runtime: Runtime, req: Message<Self::Body>) -> anyhow::Result<()>;
+
+    // called once after the in-flight messages were handled and the background tasks
+    // stopped, flush anything that is still queued for other nodes from here
+    async fn shutdown(&self, _runtime: Runtime) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct Runtime {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    node_id: OnceLock<String>,
+    node_ids: OnceLock<Vec<String>>,
+    next_msg_id: AtomicU64,
+    // state of `random`, seeded by the simulator so runs can be replayed
+    rng: AtomicU64,
+    // taken on shutdown, so the output task ends once the queue is drained
+    output: Mutex<Option<mpsc::UnboundedSender<String>>>,
+    output_receiver: Mutex<Option<mpsc::UnboundedReceiver<String>>>,
+    // rpcs waiting for their reply, by msg_id
+    pending: Mutex<HashMap<u64, oneshot::Sender<Value>>>,
+    // rpcs which timed out, their replies may still come in. msg ids only grow, so
+    // the first ones are the oldest and go first once there are `MAX_EXPIRED`
+    expired: Mutex<BTreeSet<u64>>,
+    // background tasks started with `spawn` / `every`
+    tasks: Mutex<JoinSet<anyhow::Result<()>>>,
+    trace: Mutex<Option<Trace>>,
+    metrics: OnceLock<Metrics>,
+    // parent of everything the node logs, `id` is filled in by init
+    span: Span,
+    // cancelled on stdin EOF / SIGTERM
+    shutdown: CancellationToken,
+}
+
+impl Default for Runtime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Runtime {
+    pub fn new() -> Self {
+        Self::seeded(RandomState::new().build_hasher().finish())
+    }
+
+    // a runtime whose `random` numbers only depend on `seed`
+    pub fn seeded(seed: u64) -> Self {
+        let (output, output_receiver) = mpsc::unbounded_channel();
+        Runtime {
+            inner: Arc::new(Inner {
+                node_id: OnceLock::new(),
+                node_ids: OnceLock::new(),
+                next_msg_id: AtomicU64::new(1),
+                rng: AtomicU64::new(seed),
+                output: Mutex::new(Some(output)),
+                output_receiver: Mutex::new(Some(output_receiver)),
+                pending: Mutex::new(HashMap::new()),
+                expired: Mutex::new(BTreeSet::new()),
+                tasks: Mutex::new(JoinSet::new()),
+                trace: Mutex::new(None),
+                metrics: OnceLock::new(),
+                span: tracing::info_span!("node", id = tracing::field::Empty),
+                shutdown: CancellationToken::new(),
+            }),
+        }
+    }
+
+    // a runtime which already got its init message, for unit tests
+    #[cfg(test)]
+    pub(crate) fn initialized(node_id: &str, node_ids: &[&str]) -> Self {
+        let runtime = Self::seeded(0);
+        let _ = runtime.inner.node_id.set(node_id.to_string());
+        let node_ids = node_ids.iter().map(|id| id.to_string()).collect();
+        let _ = runtime.inner.node_ids.set(node_ids);
+        runtime
+    }
+
+    // record every message in and out in `<dir>/<node id>.jsonl`, nothing if `dir` is
+    // None. each line holds the unix time in milliseconds, `in` or `out` and the message:
+    //   {"time":1700000000000.123,"dir":"in","msg":{"src":"c1","dest":"n0",...}}
+    pub fn with_trace(self, dir: Option<PathBuf>) -> Self {
+        *self.inner.trace.lock().unwrap() = dir.map(|dir| Trace {
+            dir,
+            file: None,
+            buffered: Vec::new(),
+        });
+        self
+    }
+
+    // count messages, bytes, handler latencies and the output queue depth,
+    // nothing if `config` doesn't set an interval
+    pub fn with_metrics(self, config: MetricsConfig) -> Self {
+        if config.enabled() {
+            let _ = self.inner.metrics.set(Metrics::new(config));
+        }
+        self
+    }
+
+    // this node's id, empty until the init message arrived
+    pub fn node_id(&self) -> &str {
+        self.inner.node_id.get().map(String::as_str).unwrap_or("")
+    }
+
+    // all nodes in the cluster (including this one)
+    pub fn node_ids(&self) -> &[String] {
+        self.inner.node_ids.get().map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    // all nodes in the cluster except this one
+    pub fn other_node_ids(&self) -> impl Iterator<Item = &String> {
+        let this_node = self.node_id();
+        self.node_ids()
+            .iter()
+            .filter(move |node| *node != this_node)
+    }
+
+    pub fn next_msg_id(&self) -> u64 {
+        self.inner.next_msg_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    // a pseudo random number, use this instead of other sources of randomness
+    // so the simulator can replay a run
+    pub fn random(&self) -> u64 {
+        splitmix(&self.inner.rng)
+    }
+
+    // queue a message for the output task
+    pub fn send<T: Serialize>(&self, dest: &str, body: T) -> anyhow::Result<()> {
+        let msg = Message {
+            src: self.node_id().to_string(),
+            dest: dest.to_string(),
+            body,
+        };
+        let serialized_output = serde_json::to_string(&msg)?;
+        let metrics = self.inner.metrics.get();
+        // counted before it's queued, the output task may write it right away
+        if let Some(metrics) = metrics {
+            metrics.queued();
+        }
+        let output = self.inner.output.lock().unwrap();
+        let queued = output
+            .as_ref()
+            .ok_or_else(|| anyhow!("runtime is shut down"))
+            .and_then(|output| {
+                output
+                    .send(serialized_output)
+                    .map_err(|_| anyhow!("output task is gone"))
+            });
+        if let (Err(_), Some(metrics)) = (&queued, metrics) {
+            metrics.dequeued();
+        }
+        queued
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.inner.shutdown.is_cancelled()
+    }
+
+    // resolves once the runtime starts shutting down
+    pub async fn cancelled(&self) {
+        self.inner.shutdown.cancelled().await
+    }
+
+    // send `body` (a json object) with a fresh msg_id and wait for the reply
+    // an `error` reply is returned as an `RpcError`
+    pub async fn call<T, R>(&self, dest: &str, body: T) -> anyhow::Result<R>
+    where
+        T: Serialize,
+        R: DeserializeOwned,
+    {
+        self.call_timeout(dest, body, RPC_TIMEOUT).await
+    }
+
+    pub async fn call_timeout<T, R>(
+        &self,
+        dest: &str,
+        body: T,
+        timeout: Duration,
+    ) -> anyhow::Result<R>
+    where
+        T: Serialize,
+        R: DeserializeOwned,
+    {
+        let msg_id = self.next_msg_id();
+        let mut body = serde_json::to_value(body)?;
+        match body.as_object_mut() {
+            Some(fields) => fields.insert("msg_id".to_string(), msg_id.into()),
+            None => return Err(anyhow!("rpc body must be a json object")),
+        };
+
+        let (sender, receiver) = oneshot::channel();
+        self.inner.pending.lock().unwrap().insert(msg_id, sender);
+        self.send(dest, body)?;
+
+        // nobody is reading replies anymore once the runtime shuts down
+        let reply = tokio::select! {
+            // fixed order, so simulated runs don't depend on tokio's branch shuffling
+            biased;
+            reply = tokio::time::timeout(timeout, receiver) => reply,
+            _ = self.inner.shutdown.cancelled() => {
+                self.inner.pending.lock().unwrap().remove(&msg_id);
+                let text = format!("shutting down, dropped msg {} to {}", msg_id, dest);
+                return Err(RpcError::new(code::TEMPORARILY_UNAVAILABLE, text).into());
+            }
+        };
+        let reply = match reply {
+            Ok(Ok(reply)) => reply,
+            _ => {
+                self.inner.pending.lock().unwrap().remove(&msg_id);
+                self.expire(msg_id);
+                let text = format!("no reply from {} for msg {}", dest, msg_id);
+                return Err(RpcError::new(code::TIMEOUT, text).into());
+            }
+        };
+        if reply.get("type").and_then(Value::as_str) == Some("error") {
+            let err: RpcError = serde_json::from_value(reply)?;
+            return Err(err.into());
+        }
+        Ok(serde_json::from_value(reply)?)
+    }
+
+    // remember that the rpc `msg_id` timed out, so its reply is dropped
+    fn expire(&self, msg_id: u64) {
+        let mut expired = self.inner.expired.lock().unwrap();
+        expired.insert(msg_id);
+        if expired.len() > MAX_EXPIRED {
+            expired.pop_first();
+        }
+    }
+
+    // run a background task next to the message handlers
+    // the task is dropped at its next await point once the runtime shuts down
+    pub fn spawn<F>(&self, task: F)
+    where
+        F: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        let shutdown = self.inner.shutdown.clone();
+        let task = async move {
+            tokio::select! {
+                biased;
+                result = task => result,
+                _ = shutdown.cancelled() => Ok(()),
+            }
+        };
+        let span = self.inner.span.clone();
+        let mut tasks = self.inner.tasks.lock().unwrap();
+        tasks.spawn(task.instrument(span));
+        // forget about the tasks that are already done, some nodes start one per rpc
+        while let Some(result) = tasks.try_join_next() {
+            if let Ok(Err(err)) = result {
+                error!("background task failed: {:#}", err);
+            }
+        }
+    }
+
+    // run `f` every `period`, errors are logged and the next tick still happens
+    pub fn every<F, Fut>(&self, period: Duration, mut f: F)
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        self.spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                if let Err(err) = f().await {
+                    warn!("periodic task failed: {:#}", err);
+                }
+            }
+        });
+    }
+
+    // read messages from stdin until it is closed (or we get SIGTERM), handing them to `node`
+    pub async fn run<N: Node>(self, node: N) -> anyhow::Result<()> {
+        let input = BufReader::new(tokio::io::stdin());
+        self.run_with(node, input, tokio::io::stdout(), terminated())
+            .await
+    }
+
+    // `run` with messages read from `input` and written to `output`,
+    // stopping early once `terminated` resolves
+    pub async fn run_with<N, R, W, T>(
+        self,
+        node: N,
+        input: R,
+        output: W,
+        terminated: T,
+    ) -> anyhow::Result<()>
+    where
+        N: Node,
+        R: AsyncBufRead + Unpin,
+        W: AsyncWrite + Unpin + Send + 'static,
+        T: Future<Output = &'static str>,
+    {
+        let span = self.inner.span.clone();
+        self.serve(node, input, output, terminated)
+            .instrument(span)
+            .await
+    }
+
+    async fn serve<N, R, W, T>(
+        self,
+        node: N,
+        input: R,
+        output: W,
+        terminated: T,
+    ) -> anyhow::Result<()>
+    where
+        N: Node,
+        R: AsyncBufRead + Unpin,
+        W: AsyncWrite + Unpin + Send + 'static,
+        T: Future<Output = &'static str>,
+    {
+        let node = Arc::new(node);
+        let output_receiver = self
+            .inner
+            .output_receiver
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or_else(|| anyhow!("runtime is already running"))?;
+        let output = write_output(self.clone(), output_receiver, output);
+        let output = tokio::spawn(output.instrument(Span::current()));
+
+        let mut handlers = JoinSet::new();
+        let mut lines = input.lines();
+        tokio::pin!(terminated);
+        loop {
+            let line = tokio::select! {
+                biased;
+                line = lines.next_line() => match line? {
+                    Some(line) => line,
+                    None => break,
+                },
+                signal = &mut terminated => {
+                    info!("received {}, shutting down", signal);
+                    break;
+                }
+            };
+            let msg: Message<Value> = match serde_json::from_str(&line) {
+                Ok(msg) => msg,
+                Err(err) => {
+                    warn!("malformed message {}: {}", line, err);
+                    continue;
+                }
+            };
+            debug!(msg = %line, "read");
+            self.trace("in", &line);
+            if let Some(metrics) = self.inner.metrics.get() {
+                let kind = msg.body.get("type").and_then(Value::as_str);
+                metrics.read(kind.unwrap_or("?"), line.len());
+            }
+
+            // replies to our own rpcs go straight to the waiting `call`
+            if let Some(in_reply_to) = msg.body.get("in_reply_to").and_then(Value::as_u64) {
+                let sender = self.inner.pending.lock().unwrap().remove(&in_reply_to);
+                if let Some(sender) = sender {
+                    let _ = sender.send(msg.body);
+                    continue;
+                }
+                if self.inner.expired.lock().unwrap().remove(&in_reply_to) {
+                    debug!(in_reply_to, "dropped a reply which came too late");
+                    continue;
+                }
+            }
+
+            if msg.body.get("type").and_then(Value::as_str) == Some("init") {
+                self.init(msg)?;
+                node.init(self.clone()).await?;
+                continue;
+            }
+
+            let runtime = self.clone();
+            let node = Arc::clone(&node);
+            let span = self.message_span(&msg);
+            handlers.spawn(runtime.handle(node, msg).instrument(span));
+            // forget about the handlers that are already done
+            while handlers.try_join_next().is_some() {}
+        }
+
+        self.inner.shutdown.cancel();
+        while handlers.join_next().await.is_some() {}
+        let mut tasks = std::mem::take(&mut *self.inner.tasks.lock().unwrap());
+        while let Some(result) = tasks.join_next().await {
+            if let Ok(Err(err)) = result {
+                error!("background task failed: {:#}", err);
+            }
+        }
+        if let Err(err) = node.shutdown(self.clone()).await {
+            error!("shutdown failed: {:#}", err);
+        }
+
+        // the node (or tasks it leaked) may still hold runtime clones,
+        // so close the output queue explicitly instead of waiting for the senders to drop
+        self.inner.output.lock().unwrap().take();
+        let written = output.await?;
+        // the last dump includes what was flushed on shutdown
+        self.dump_metrics();
+        written
+    }
+
+    fn init(&self, msg: Message<Value>) -> anyhow::Result<()> {
+        #[derive(serde::Deserialize)]
+        struct Init {
+            msg_id: u64,
+            node_id: String,
+            node_ids: Vec<String>,
+        }
+        let init: Init = serde_json::from_value(msg.body)?;
+        self.inner
+            .span
+            .record("id", tracing::field::display(&init.node_id));
+        self.inner
+            .node_id
+            .set(init.node_id)
+            .map_err(|_| anyhow!("init message sent twice"))?;
+        let _ = self.inner.node_ids.set(init.node_ids);
+        self.open_trace();
+        if let Some(metrics) = self.inner.metrics.get() {
+            let runtime = self.clone();
+            self.every(metrics.interval(), move || {
+                runtime.dump_metrics();
+                async { Ok(()) }
+            });
+        }
+        self.send(
+            &msg.src,
+            serde_json::json!({ "type": "init_ok", "in_reply_to": init.msg_id }),
+        )
+    }
+
+    // the trace file is named after the node, so it is opened once init told us its id
+    fn open_trace(&self) {
+        let mut trace = self.inner.trace.lock().unwrap();
+        let Some(trace) = trace.as_mut() else {
+            return;
+        };
+        let path = trace.dir.join(format!("{}.jsonl", self.node_id()));
+        let opened = std::fs::create_dir_all(&trace.dir).and_then(|_| File::create(&path));
+        match opened {
+            Ok(file) => {
+                let mut file = LineWriter::new(file);
+                for line in trace.buffered.drain(..) {
+                    let _ = file.write_all(line.as_bytes());
+                }
+                trace.file = Some(file);
+            }
+            Err(err) => warn!("failed to create trace {}: {}", path.display(), err),
+        }
+    }
+
+    fn dump_metrics(&self) {
+        if let Some(metrics) = self.inner.metrics.get() {
+            metrics.dump(self.node_id());
+        }
+    }
+
+    // `line` is a json message
+    fn trace(&self, direction: &str, line: &str) {
+        let mut trace = self.inner.trace.lock().unwrap();
+        let Some(trace) = trace.as_mut() else {
+            return;
+        };
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64()
+            * 1000.0;
+        let record = format!(
+            "{{\"time\":{:.3},\"dir\":\"{}\",\"msg\":{}}}\n",
+            time, direction, line
+        );
+        match &mut trace.file {
+            Some(file) => {
+                if let Err(err) = file.write_all(record.as_bytes()) {
+                    warn!("failed to write trace: {}", err);
+                }
+            }
+            None => trace.buffered.push(record),
+        }
+    }
+
+    // `msg{src=.. msg_id=.. type=..}`, inside the node span
+    fn message_span(&self, msg: &Message<Value>) -> Span {
+        let msg_id = msg.body.get("msg_id").and_then(Value::as_u64);
+        let kind = msg.body.get("type").and_then(Value::as_str);
+        tracing::info_span!(
+            parent: &self.inner.span,
+            "msg",
+            src = %msg.src,
+            msg_id,
+            r#type = %kind.unwrap_or("?")
+        )
+    }
+
+    async fn handle<N: Node>(self, node: Arc<N>, msg: Message<Value>) {
+        let msg_id = msg.body.get("msg_id").and_then(Value::as_u64);
+        let src = msg.src.clone();
+        let kind = msg.body.get("type").and_then(Value::as_str);
+        let kind = kind.unwrap_or("?").to_string();
+        let started = Instant::now();
+        let result = match serde_json::from_value::<N::Body>(msg.body) {
+            Ok(body) => {
+                let req = Message {
+                    src: msg.src,
+                    dest: msg.dest,
+                    body,
+                };
+                node.process(self.clone(), req).await
+            }
+            Err(err) => Err(RpcError::new(code::NOT_SUPPORTED, err.to_string()).into()),
+        };
+        if let Some(metrics) = self.inner.metrics.get() {
+            // requests from outside the cluster, replies and kv services don't send any
+            let from_client = msg_id.is_some() && !self.node_ids().contains(&src);
+            metrics.handled(&kind, from_client, started.elapsed());
+        }
+
+        if let Err(err) = result {
+            warn!("failed to process message: {:#}", err);
+            // only requests (with a msg_id) expect a reply
+            if let Some(msg_id) = msg_id {
+                let err = match err.downcast::<RpcError>() {
+                    Ok(err) => err,
+                    Err(err) => RpcError::new(code::CRASH, format!("{:#}", err)),
+                };
+                let _ = self.send(&src, err.reply_body(msg_id));
+            }
+        }
+    }
+}
+
+// resolves with the name of the first termination signal we receive
+async fn terminated() -> &'static str {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => tokio::select! {
+                _ = sigterm.recv() => "SIGTERM",
+                _ = tokio::signal::ctrl_c() => "SIGINT",
+            },
+            Err(err) => {
+                warn!("failed to listen for SIGTERM: {}", err);
+                let _ = tokio::signal::ctrl_c().await;
+                "SIGINT"
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        "ctrl-c"
+    }
+}
+
+// lines read / written before the node knows its id wait in `buffered`
+struct Trace {
+    dir: PathBuf,
+    file: Option<LineWriter<File>>,
+    buffered: Vec<String>,
+}
+
+async fn write_output<W: AsyncWrite + Unpin>(
+    runtime: Runtime,
+    mut receiver: mpsc::UnboundedReceiver<String>,
+    mut output: W,
+) -> anyhow::Result<()> {
+    while let Some(line) = receiver.recv().await {
+        debug!(msg = %line, "wrote");
+        runtime.trace("out", &line);
+        if let Some(metrics) = runtime.inner.metrics.get() {
+            metrics.written(&line, |dest| runtime.node_ids().iter().any(|id| id == dest));
+        }
+        output.write_all(line.as_bytes()).await?;
+        output.write_all(b"\n").await?;
+        output.flush().await?;
+    }
+    Ok(())
+}
+
+// splitmix64, advancing `state`
+pub(crate) fn splitmix(state: &AtomicU64) -> u64 {
+    let mut z = state
+        .fetch_add(0x9e3779b97f4a7c15, Ordering::Relaxed)
+        .wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_latest_expired_rpcs_are_remembered() {
+        let runtime = Runtime::initialized("n0", &["n0"]);
+        for msg_id in 1..=MAX_EXPIRED as u64 + 5 {
+            runtime.expire(msg_id);
+        }
+        let expired = runtime.inner.expired.lock().unwrap();
+        assert_eq!(expired.len(), MAX_EXPIRED);
+        assert_eq!(expired.first(), Some(&6));
+    }
+}
@@ -0,0 +1,583 @@
+use crate::config::SimConfig;
+use crate::error::{code, RpcError};
+use crate::message::Message;
+use crate::runtime::{splitmix, Node, Runtime};
+use anyhow::{anyhow, Context};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::hash::{BuildHasher, Hasher};
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Once};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream};
+use tokio::sync::oneshot;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+// deterministic simulation of a whole cluster, without maelstrom
+//
+// - `nodes` copies of the binary's `Node` run in this process on a single threaded
+//   tokio runtime with a paused clock. time only moves when every task waits for a
+//   timer, so timeouts cost nothing and the order of events only depends on the run
+// - the simulator is the network: every message gets a random delay and messages
+//   between nodes may be dropped. lin-kv / seq-kv / lww-kv are answered from an
+//   in-memory store (linearizable, whichever one is asked)
+// - with `partition_ms` set, a random node is cut off from the other nodes (not from
+//   the clients and services) for `partition_ms` after every `partition_ms`, until the
+//   clients are done
+// - `clients` clients send the bodies of the requests file in order, each to a random
+//   node after a random pause, waiting for the reply (or `timeout`) before taking the
+//   next one
+// - every random decision derives from the seed, the nodes get theirs through
+//   `Runtime::random`. a node sending messages in `HashMap` iteration order is the one
+//   thing the seed doesn't pin down, that order differs between processes
+//
+// - every request and its outcome is also recorded as an operation in the format of
+//   `linearizability-checker --json`: `{"process":"c1","type":"invoke","f":"cas",
+//   "key":1,"value":[2,3]}` and then `"type":"ok"` / `"fail"` / `"info"` (timeouts and
+//   crashes). sends are `append`s whose value is the offset
+//
+// a run fails when a node panics, stops with an error, writes something that isn't a
+// message, answers a client with a crash (code 13) or when the operations don't pass
+// the check given to `simulate_checked`. running again with the seed of a failing run
+// replays it
+
+// the kv services answered by the simulator itself
+const SERVICES: [&str; 3] = ["lin-kv", "seq-kv", "lww-kv"];
+// how long nodes get to shut down once their input is closed
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+// panics anywhere in the process, counted by the hook `simulate` installs
+static PANICS: AtomicU64 = AtomicU64::new(0);
+
+// a message as the history file shows it, times are virtual milliseconds since the
+// start of the run and `received` is missing for dropped messages
+#[derive(Serialize, Debug)]
+struct Delivery {
+    sent: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    received: Option<f64>,
+    src: String,
+    dest: String,
+    body: Value,
+}
+
+#[derive(Debug, Default)]
+struct Stats {
+    messages: u64,
+    dropped: u64,
+    ok: u64,
+    errors: u64,
+    crashes: u64,
+    timeouts: u64,
+    malformed: u64,
+}
+
+struct Network {
+    config: SimConfig,
+    rng: AtomicU64,
+    start: Instant,
+    // what the nodes read from, taken to shut them down
+    inputs: BTreeMap<String, tokio::sync::Mutex<Option<DuplexStream>>>,
+    requests: Mutex<VecDeque<Value>>,
+    // clients waiting for a reply, by client and msg_id
+    waiters: Mutex<HashMap<(String, u64), oneshot::Sender<Value>>>,
+    // by service and key (as json)
+    store: Mutex<BTreeMap<(String, String), Value>>,
+    // the node currently cut off from the others
+    isolated: Mutex<Option<String>>,
+    history: Mutex<Vec<Delivery>>,
+    ops: Mutex<Vec<Value>>,
+    stats: Mutex<Stats>,
+}
+
+impl Network {
+    fn random(&self) -> u64 {
+        splitmix(&self.rng)
+    }
+
+    // virtual milliseconds since the start of the run
+    fn now(&self) -> f64 {
+        (Instant::now() - self.start).as_secs_f64() * 1000.0
+    }
+
+    fn is_node(&self, id: &str) -> bool {
+        self.inputs.contains_key(id)
+    }
+
+    // put `msg` on the wire
+    fn send(self: &Arc<Self>, msg: Message<Value>) {
+        let sent = self.now();
+        let roll = self.random() as f64 / u64::MAX as f64;
+        let delay = Duration::from_micros(self.random() % (self.config.latency_ms * 1000 + 1));
+        self.stats.lock().unwrap().messages += 1;
+        if self.is_node(&msg.src)
+            && self.is_node(&msg.dest)
+            && (roll < self.config.loss || self.is_isolated(&msg))
+        {
+            self.stats.lock().unwrap().dropped += 1;
+            self.record(sent, None, msg);
+            return;
+        }
+        let network = Arc::clone(self);
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            network.record(sent, Some(network.now()), msg.clone());
+            network.deliver(msg).await;
+        });
+    }
+
+    // whether `msg` would cross the partition
+    fn is_isolated(&self, msg: &Message<Value>) -> bool {
+        let isolated = self.isolated.lock().unwrap();
+        isolated
+            .as_ref()
+            .is_some_and(|node| *node == msg.src || *node == msg.dest)
+    }
+
+    // cut a random node off every `partition` and heal the network after as long
+    async fn partitions(self: Arc<Self>) {
+        let nodes: Vec<String> = self.inputs.keys().cloned().collect();
+        loop {
+            tokio::time::sleep(self.config.partition()).await;
+            let node = nodes[(self.random() % nodes.len() as u64) as usize].clone();
+            info!(%node, "partitioned from the other nodes");
+            *self.isolated.lock().unwrap() = Some(node);
+            tokio::time::sleep(self.config.partition()).await;
+            *self.isolated.lock().unwrap() = None;
+        }
+    }
+
+    fn record(&self, sent: f64, received: Option<f64>, msg: Message<Value>) {
+        self.history.lock().unwrap().push(Delivery {
+            sent,
+            received,
+            src: msg.src,
+            dest: msg.dest,
+            body: msg.body,
+        });
+    }
+
+    async fn deliver(self: &Arc<Self>, msg: Message<Value>) {
+        if let Some(input) = self.inputs.get(&msg.dest) {
+            let mut line = serde_json::to_string(&msg).unwrap_or_default();
+            line.push('\n');
+            // the node is gone once its input was taken
+            if let Some(input) = input.lock().await.as_mut() {
+                let _ = input.write_all(line.as_bytes()).await;
+            }
+        } else if SERVICES.contains(&msg.dest.as_str()) {
+            let reply = self.serve(&msg.dest, &msg.body);
+            self.send(Message {
+                src: msg.dest,
+                dest: msg.src,
+                body: reply,
+            });
+        } else if let Some(in_reply_to) = msg.body.get("in_reply_to").and_then(Value::as_u64) {
+            let waiter = self
+                .waiters
+                .lock()
+                .unwrap()
+                .remove(&(msg.dest, in_reply_to));
+            if let Some(waiter) = waiter {
+                let _ = waiter.send(msg.body);
+            }
+        }
+    }
+
+    // answer a kv service request
+    fn serve(&self, service: &str, body: &Value) -> Value {
+        let msg_id = body.get("msg_id").and_then(Value::as_u64).unwrap_or(0);
+        let key = (service.to_string(), body["key"].to_string());
+        let mut store = self.store.lock().unwrap();
+        let current = store.get(&key).cloned();
+        let result = match body["type"].as_str() {
+            Some("read") => match current {
+                Some(value) => Ok(json!({ "type": "read_ok", "value": value })),
+                None => Err(code::KEY_DOES_NOT_EXIST),
+            },
+            Some("write") => {
+                store.insert(key, body["value"].clone());
+                Ok(json!({ "type": "write_ok" }))
+            }
+            Some("cas") => match current {
+                Some(value) if value != body["from"] => Err(code::PRECONDITION_FAILED),
+                None if body["create_if_not_exists"] != true => Err(code::KEY_DOES_NOT_EXIST),
+                _ => {
+                    store.insert(key, body["to"].clone());
+                    Ok(json!({ "type": "cas_ok" }))
+                }
+            },
+            _ => Err(code::NOT_SUPPORTED),
+        };
+        match result {
+            Ok(mut reply) => {
+                reply["in_reply_to"] = msg_id.into();
+                reply
+            }
+            Err(code) => RpcError::new(code, format!("{} says no", service)).reply_body(msg_id),
+        }
+    }
+
+    fn record_op(&self, mut op: Value) {
+        op["time"] = self.now().into();
+        self.ops.lock().unwrap().push(op);
+    }
+
+    // put everything `node` writes on the wire
+    async fn route(self: Arc<Self>, node: String, output: DuplexStream) {
+        let mut lines = BufReader::new(output).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            match serde_json::from_str::<Message<Value>>(&line) {
+                Ok(msg) if msg.src == node => self.send(msg),
+                _ => {
+                    warn!(%node, "wrote something that isn't a message: {}", line);
+                    self.stats.lock().unwrap().malformed += 1;
+                }
+            }
+        }
+    }
+
+    async fn client(self: Arc<Self>, id: String) {
+        let nodes: Vec<String> = self.inputs.keys().cloned().collect();
+        let mut msg_id = 0;
+        loop {
+            let pause = self.random() % (self.config.interval_ms * 2000 + 1);
+            tokio::time::sleep(Duration::from_micros(pause)).await;
+            let Some(mut body) = self.requests.lock().unwrap().pop_front() else {
+                return;
+            };
+            msg_id += 1;
+            body["msg_id"] = msg_id.into();
+            let dest = nodes[(self.random() % nodes.len() as u64) as usize].clone();
+            let invoke = invocation(&id, &body);
+            self.record_op(invoke.clone());
+            let (sender, receiver) = oneshot::channel();
+            self.waiters
+                .lock()
+                .unwrap()
+                .insert((id.clone(), msg_id), sender);
+            self.send(Message {
+                src: id.clone(),
+                dest,
+                body,
+            });
+
+            let reply = match tokio::time::timeout(self.config.timeout(), receiver).await {
+                Ok(Ok(reply)) => Some(reply),
+                _ => {
+                    self.waiters.lock().unwrap().remove(&(id.clone(), msg_id));
+                    None
+                }
+            };
+            self.record_op(completion(&invoke, reply.as_ref()));
+            let mut stats = self.stats.lock().unwrap();
+            match reply {
+                Some(reply) if reply["type"] == "error" => {
+                    if reply["code"] == code::CRASH {
+                        stats.crashes += 1;
+                    } else {
+                        stats.errors += 1;
+                    }
+                }
+                Some(_) => stats.ok += 1,
+                None => stats.timeouts += 1,
+            }
+        }
+    }
+}
+
+// the operation `process` starts by sending `body`
+fn invocation(process: &str, body: &Value) -> Value {
+    let kind = body["type"].as_str().unwrap_or_default();
+    let (f, value) = match kind {
+        "write" => ("write", body["value"].clone()),
+        "cas" => ("cas", json!([body["from"], body["to"]])),
+        "send" => ("append", Value::Null),
+        _ => (kind, Value::Null),
+    };
+    let mut op = json!({ "process": process, "type": "invoke", "f": f, "value": value });
+    if let Some(key) = body.get("key") {
+        op["key"] = key.clone();
+    }
+    op
+}
+
+// how `invoke` ended, `reply` is `None` after a timeout
+fn completion(invoke: &Value, reply: Option<&Value>) -> Value {
+    let mut op = invoke.clone();
+    let (kind, value) = match reply {
+        Some(reply) if reply["type"] == "error" => {
+            let definite = reply["code"].as_u64().is_some_and(code::is_definite);
+            (if definite { "fail" } else { "info" }, None)
+        }
+        Some(reply) => match invoke["f"].as_str() {
+            Some("read") => ("ok", Some(reply["value"].clone())),
+            Some("append") => ("ok", Some(reply["offset"].clone())),
+            _ => ("ok", None),
+        },
+        None => ("info", None),
+    };
+    op["type"] = kind.into();
+    if let Some(value) = value {
+        op["value"] = value;
+    }
+    op
+}
+
+// what a single run left behind
+struct Outcome {
+    stats: Stats,
+    // why the run failed, if it did
+    failures: Vec<String>,
+    history: Vec<Delivery>,
+    ops: Vec<Value>,
+    elapsed: f64,
+}
+
+async fn run_once<N, F>(
+    config: &SimConfig,
+    seed: u64,
+    requests: &[Value],
+    make_node: &F,
+) -> anyhow::Result<Outcome>
+where
+    N: Node,
+    F: Fn(Runtime) -> N,
+{
+    let node_ids: Vec<String> = (0..config.nodes).map(|i| format!("n{}", i)).collect();
+    let mut inputs = BTreeMap::new();
+    let mut pipes = Vec::new();
+    for id in &node_ids {
+        let (input, node_input) = tokio::io::duplex(1 << 16);
+        let (node_output, output) = tokio::io::duplex(1 << 16);
+        inputs.insert(id.clone(), tokio::sync::Mutex::new(Some(input)));
+        pipes.push((id.clone(), node_input, node_output, output));
+    }
+    let network = Arc::new(Network {
+        config: config.clone(),
+        rng: AtomicU64::new(seed),
+        start: Instant::now(),
+        inputs,
+        requests: Mutex::new(requests.iter().cloned().collect()),
+        waiters: Mutex::new(HashMap::new()),
+        store: Mutex::new(BTreeMap::new()),
+        isolated: Mutex::new(None),
+        history: Mutex::new(Vec::new()),
+        ops: Mutex::new(Vec::new()),
+        stats: Mutex::new(Stats::default()),
+    });
+    let panics = PANICS.load(Ordering::Relaxed);
+
+    let mut nodes = JoinSet::new();
+    let mut routers = JoinSet::new();
+    for (id, node_input, node_output, output) in pipes {
+        let runtime = Runtime::seeded(network.random());
+        let node = make_node(runtime.clone());
+        let input = BufReader::new(node_input);
+        let terminated = std::future::pending();
+        let name = id.clone();
+        nodes.spawn(async move {
+            let result = runtime.run_with(node, input, node_output, terminated).await;
+            result.with_context(|| format!("{} stopped", name))
+        });
+        routers.spawn(Arc::clone(&network).route(id, output));
+    }
+    for id in &node_ids {
+        let body = json!({ "type": "init", "msg_id": 0, "node_id": id, "node_ids": node_ids });
+        let init = Message {
+            src: "c0".to_string(),
+            dest: id.clone(),
+            body,
+        };
+        network.deliver(init).await;
+    }
+
+    let mut clients = JoinSet::new();
+    for i in 1..=config.clients {
+        clients.spawn(Arc::clone(&network).client(format!("c{}", i)));
+    }
+    let mut nemesis = JoinSet::new();
+    if config.partition_ms > 0 {
+        nemesis.spawn(Arc::clone(&network).partitions());
+    }
+    while clients.join_next().await.is_some() {}
+    nemesis.abort_all();
+    *network.isolated.lock().unwrap() = None;
+    tokio::time::sleep(config.settle()).await;
+
+    // closing their input shuts the nodes down
+    for input in network.inputs.values() {
+        input.lock().await.take();
+    }
+    let mut failures = Vec::new();
+    let stopped = tokio::time::timeout(SHUTDOWN_TIMEOUT, async {
+        while let Some(result) = nodes.join_next().await {
+            match result {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => failures.push(format!("{:#}", err)),
+                Err(err) => failures.push(format!("node task failed: {}", err)),
+            }
+        }
+    });
+    if stopped.await.is_err() {
+        failures.push("nodes didn't shut down".to_string());
+    }
+    routers.abort_all();
+
+    let elapsed = network.now();
+    let stats = std::mem::take(&mut *network.stats.lock().unwrap());
+    let mut history = std::mem::take(&mut *network.history.lock().unwrap());
+    history.sort_by(|a, b| a.sent.total_cmp(&b.sent));
+    let ops = std::mem::take(&mut *network.ops.lock().unwrap());
+    let panicked = PANICS.load(Ordering::Relaxed) - panics;
+    if panicked > 0 {
+        failures.push(format!("{} panics", panicked));
+    }
+    if stats.crashes > 0 {
+        failures.push(format!("{} requests crashed a node", stats.crashes));
+    }
+    if stats.malformed > 0 {
+        failures.push(format!("{} malformed messages", stats.malformed));
+    }
+    Ok(Outcome {
+        stats,
+        failures,
+        history,
+        ops,
+        elapsed,
+    })
+}
+
+fn load_requests(config: &SimConfig) -> anyhow::Result<Vec<Value>> {
+    let path = config
+        .requests
+        .as_ref()
+        .ok_or_else(|| anyhow!("no requests file to simulate"))?;
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read requests {}", path.display()))?;
+    let mut requests = Vec::new();
+    for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+        let request: Value =
+            serde_json::from_str(line).with_context(|| format!("malformed request {}", line))?;
+        if !request.is_object() {
+            return Err(anyhow!("request {} is not a json object", line));
+        }
+        requests.push(request);
+    }
+    Ok(requests)
+}
+
+// the messages and operations of `outcome` to the files of `config`, if set
+fn write_history(config: &SimConfig, outcome: &Outcome) -> anyhow::Result<()> {
+    if let Some(path) = &config.history {
+        write_lines(path, &outcome.history)?;
+    }
+    if let Some(path) = &config.ops {
+        write_lines(path, &outcome.ops)?;
+    }
+    Ok(())
+}
+
+fn write_lines<T: Serialize>(path: &Path, lines: &[T]) -> anyhow::Result<()> {
+    let file = std::fs::File::create(path)
+        .with_context(|| format!("failed to create {}", path.display()))?;
+    let mut file = std::io::BufWriter::new(file);
+    for line in lines {
+        serde_json::to_writer(&mut file, line)?;
+        file.write_all(b"\n")?;
+    }
+    file.flush()?;
+    Ok(())
+}
+
+fn simulate_blocking<N, F, C>(config: SimConfig, make_node: F, check: C) -> anyhow::Result<()>
+where
+    N: Node,
+    F: Fn(Runtime) -> N,
+    C: Fn(&[Value]) -> anyhow::Result<()>,
+{
+    let requests = load_requests(&config)?;
+    static HOOK: Once = Once::new();
+    HOOK.call_once(|| {
+        let default = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            PANICS.fetch_add(1, Ordering::Relaxed);
+            default(info);
+        }));
+    });
+
+    let first = config
+        .seed
+        .unwrap_or_else(|| RandomState::new().build_hasher().finish());
+    let end = first.saturating_add(config.runs.max(1));
+    for seed in first..end {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .start_paused(true)
+            .build()?;
+        let mut outcome = runtime.block_on(run_once(&config, seed, &requests, &make_node))?;
+        drop(runtime);
+        if let Err(err) = check(&outcome.ops) {
+            outcome.failures.push(format!("{:#}", err));
+        }
+
+        let stats = &outcome.stats;
+        println!(
+            "seed {}: {} ok, {} errors, {} timeouts, {} messages ({} dropped), {:.0}ms",
+            seed,
+            stats.ok,
+            stats.errors,
+            stats.timeouts,
+            stats.messages,
+            stats.dropped,
+            outcome.elapsed
+        );
+        if !outcome.failures.is_empty() {
+            write_history(&config, &outcome)?;
+            return Err(anyhow!(
+                "seed {} failed: {}, replay it with --sim-seed {}",
+                seed,
+                outcome.failures.join(", "),
+                seed
+            ));
+        }
+        if seed + 1 == end {
+            write_history(&config, &outcome)?;
+        }
+    }
+    Ok(())
+}
+
+// run the nodes `make_node` returns under the simulator instead of maelstrom,
+// on a thread of its own so the caller's tokio runtime stays out of it
+pub async fn simulate<N, F>(config: SimConfig, make_node: F) -> anyhow::Result<()>
+where
+    N: Node,
+    F: Fn(Runtime) -> N + Send + 'static,
+{
+    simulate_checked(config, make_node, |_| Ok(())).await
+}
+
+// `simulate`, failing every run whose client operations `check` rejects
+pub async fn simulate_checked<N, F, C>(
+    config: SimConfig,
+    make_node: F,
+    check: C,
+) -> anyhow::Result<()>
+where
+    N: Node,
+    F: Fn(Runtime) -> N + Send + 'static,
+    C: Fn(&[Value]) -> anyhow::Result<()> + Send + 'static,
+{
+    let (sender, receiver) = oneshot::channel();
+    std::thread::spawn(move || {
+        let _ = sender.send(simulate_blocking(config, make_node, check));
+    });
+    receiver.await?
+}
@@ -1,41 +1,17 @@
-use crate::Body::{BroadcastOk, InitOk, ReadOk, TopologyOk};
+use crate::Body::{BroadcastOk, ReadOk, TopologyOk};
+use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
-use std::io;
-use std::io::{BufRead, Write};
-
-// generic type for json received for all problems
-// use this as a template
-#[derive(Serialize, Deserialize, Debug)]
-struct Message {
-    src: String,
-    dest: String,
-    body: Body,
-}
-
-#[derive(Serialize, Deserialize)]
-struct InnerTopology {
-    #[serde(rename = "n1")]
-    n1: Vec<String>,
-    #[serde(rename = "n2")]
-    n2: Vec<String>,
-    #[serde(rename = "n3")]
-    n3: Vec<String>,
-}
+use shared::config::Config;
+use shared::crdt::GSet;
+use shared::log;
+use shared::sim::simulate;
+use shared::{Message, Node, Runtime};
+use std::sync::Mutex;
+use tracing::warn;
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "type")]
 enum Body {
-    // this type = "init" is received at the start
-    #[serde(rename = "init")]
-    Init {
-        msg_id: u64,
-        node_id: String,
-        node_ids: Vec<String>,
-    },
-    // response for the type = "init"
-    #[serde(rename = "init_ok")]
-    InitOk { in_reply_to: u64 },
-
     // type = "broadcast" for this problem
     #[serde(rename = "broadcast")]
     Broadcast { message: u64, msg_id: u64 },
@@ -53,13 +29,12 @@ enum Body {
     ReadOk {
         msg_id: u64,
         in_reply_to: u64,
-        messages: Vec<u64>,
+        messages: GSet<u64>,
     },
 
     // type = "topology" for this problem
     #[serde(rename = "topology")]
     Topology {
-        // topology: InnerTopology,
         topology: std::collections::HashMap<String, Vec<String>>,
         msg_id: u64,
     },
@@ -77,77 +52,69 @@ enum Body {
     },
 }
 
-fn main() -> anyhow::Result<()> {
-    let stdin = io::stdin();
-    let mut stdout = io::stdout();
-
-    let mut print_and_flush = |output: Message| -> anyhow::Result<()> {
-        let serialized_output = serde_json::to_string(&output)?;
-        writeln!(stdout, "{}", serialized_output)?;
-        stdout.flush()?;
-        Ok(())
-    };
+// the init message is answered by the runtime
+#[derive(Default)]
+struct Handler {
+    messages: Mutex<GSet<u64>>,
+}
 
-    let mut messages: Vec<u64> = Vec::new();
+#[async_trait]
+impl Node for Handler {
+    type Body = Body;
 
-    for line in stdin.lock().lines() {
-        let input: Message = serde_json::from_str(&line?)?;
+    async fn process(&self, runtime: Runtime, input: Message<Body>) -> anyhow::Result<()> {
         match input.body {
-            Body::Init { msg_id, .. } => {
-                let output: Message = Message {
-                    src: input.dest,
-                    dest: input.src,
-                    body: InitOk {
-                        in_reply_to: msg_id,
-                    },
-                };
-                print_and_flush(output)?;
-            }
             Body::Read { msg_id } => {
-                let output: Message = Message {
-                    src: input.dest,
-                    dest: input.src,
-                    body: ReadOk {
+                let messages = self.messages.lock().unwrap().clone();
+                runtime.send(
+                    &input.src,
+                    ReadOk {
                         msg_id,
                         in_reply_to: msg_id,
-                        messages: messages.clone(),
+                        messages,
                     },
-                };
-                print_and_flush(output)?;
+                )?;
             }
             Body::Broadcast { msg_id, message } => {
-                messages.push(message);
-                let output: Message = Message {
-                    src: input.dest,
-                    dest: input.src,
-                    body: BroadcastOk {
+                self.messages.lock().unwrap().insert(message);
+                runtime.send(
+                    &input.src,
+                    BroadcastOk {
                         msg_id,
                         in_reply_to: msg_id,
                     },
-                };
-                print_and_flush(output)?;
+                )?;
             }
             Body::Topology { msg_id, .. } => {
-                let output: Message = Message {
-                    src: input.dest,
-                    dest: input.src,
-                    body: TopologyOk {
+                runtime.send(
+                    &input.src,
+                    TopologyOk {
                         msg_id,
                         in_reply_to: msg_id,
                     },
-                };
-                print_and_flush(output)?;
+                )?;
             }
-            InitOk { .. }
-            | ReadOk { .. }
-            | BroadcastOk { .. }
-            | TopologyOk { .. } => {
-                eprintln!("{}", "Impossible input");
+            ReadOk { .. } | BroadcastOk { .. } | TopologyOk { .. } => {
+                warn!("impossible input");
             }
             Body::Error { text, .. } => {
-                eprintln!("{}", text);
+                warn!("{}", text);
             }
         }
+        Ok(())
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let config = Config::load(Config::default())?;
+    log::init(&config.log);
+    if config.sim.enabled() {
+        return simulate(config.sim, |_| Handler::default()).await;
     }
-    Ok(())
+    Runtime::new()
+        .with_trace(config.trace.dir)
+        .with_metrics(config.metrics)
+        .run(Handler::default())
+        .await
 }
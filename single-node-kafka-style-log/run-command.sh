@@ -2,3 +2,7 @@
 cargo build --release
 
 ~/maelstrom/maelstrom/maelstrom test -w kafka --bin ~/distributed-systems-challenges/single-node-kafka-style-log/target/release/single-node-kafka-style-log --node-count 1 --concurrency 2n --time-limit 20 --rate 1000
+
+# same workload with the log persisted below /tmp/kafka-log (segments per key, fsync every 100 ms)
+rm -rf /tmp/kafka-log
+DSC_KAFKA_DATA_DIR=/tmp/kafka-log ~/maelstrom/maelstrom/maelstrom test -w kafka --bin ~/distributed-systems-challenges/single-node-kafka-style-log/target/release/single-node-kafka-style-log --node-count 1 --concurrency 2n --time-limit 20 --rate 1000
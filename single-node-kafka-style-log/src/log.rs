@@ -0,0 +1,212 @@
+use shared::config::KafkaConfig;
+use std::collections::{HashMap, VecDeque};
+use tokio::time::Instant;
+
+// the messages of a single key
+//
+// offsets are dense when the log is written, but retention drops prefixes
+// (everything below `start`) and compaction drops single messages in between,
+// so every entry carries its own offset
+#[derive(Debug, Default)]
+pub struct Log {
+    entries: VecDeque<Entry>,
+    // everything below this offset is gone
+    start: u64,
+    next_offset: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    offset: u64,
+    msg: u64,
+    appended: Instant,
+}
+
+// the requested offset is below the start of the log
+#[derive(Debug)]
+pub struct Truncated {
+    pub start: u64,
+}
+
+impl Log {
+    // a log read back from disk, `values` are the messages starting at `start`
+    pub fn recovered(start: u64, values: Vec<u64>) -> Self {
+        let now = Instant::now();
+        let next_offset = start + values.len() as u64;
+        let entries = values
+            .into_iter()
+            .enumerate()
+            .map(|(i, msg)| Entry {
+                offset: start + i as u64,
+                msg,
+                appended: now,
+            })
+            .collect();
+        Log {
+            entries,
+            start,
+            next_offset,
+        }
+    }
+
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    pub fn next_offset(&self) -> u64 {
+        self.next_offset
+    }
+
+    pub fn append(&mut self, msg: u64) -> u64 {
+        let offset = self.next_offset;
+        self.entries.push_back(Entry {
+            offset,
+            msg,
+            appended: Instant::now(),
+        });
+        self.next_offset += 1;
+        offset
+    }
+
+    // the (offset, msg) pairs starting at `offset`, up to the end of the log
+    pub fn read(&self, offset: u64) -> Result<impl Iterator<Item = (u64, u64)> + '_, Truncated> {
+        if offset < self.start {
+            return Err(Truncated { start: self.start });
+        }
+        let first = self.entries.partition_point(|entry| entry.offset < offset);
+        Ok(self
+            .entries
+            .range(first..)
+            .map(|entry| (entry.offset, entry.msg)))
+    }
+
+    // drop everything below `offset`, returns the number of dropped messages
+    pub fn truncate(&mut self, offset: u64) -> usize {
+        let offset = offset.min(self.next_offset);
+        if offset <= self.start {
+            return 0;
+        }
+        let dropped = self.entries.partition_point(|entry| entry.offset < offset);
+        self.entries.drain(..dropped);
+        self.start = offset;
+        dropped
+    }
+
+    // keep only the latest entry of every message value
+    pub fn compact(&mut self) -> usize {
+        let mut latest = HashMap::new();
+        for entry in &self.entries {
+            latest.insert(entry.msg, entry.offset);
+        }
+        let before = self.entries.len();
+        self.entries
+            .retain(|entry| latest.get(&entry.msg) == Some(&entry.offset));
+        before - self.entries.len()
+    }
+
+    // the offset retention allows to truncate to, given the committed offset of this key
+    pub fn retention_point(
+        &self,
+        config: &KafkaConfig,
+        committed: Option<u64>,
+        now: Instant,
+    ) -> u64 {
+        let mut point = self.start;
+        // nothing can be older than the retention right after the start of the process
+        if let Some(cutoff) = config
+            .retention()
+            .and_then(|retention| now.checked_sub(retention))
+        {
+            let expired = self
+                .entries
+                .partition_point(|entry| entry.appended < cutoff);
+            let offset = self
+                .entries
+                .get(expired)
+                .map_or(self.next_offset, |entry| entry.offset);
+            point = point.max(offset);
+        }
+        if let Some(keep) = config.retention_messages {
+            let len = self.entries.len() as u64;
+            if len > keep {
+                let offset = self.entries[(len - keep) as usize].offset;
+                point = point.max(offset);
+            }
+        }
+        if config.retention_committed {
+            // the committed offset itself was processed already
+            if let Some(committed) = committed {
+                point = point.max(committed + 1);
+            }
+        }
+        point.min(self.next_offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn log(msgs: &[u64]) -> Log {
+        let mut log = Log::default();
+        for msg in msgs {
+            log.append(*msg);
+        }
+        log
+    }
+
+    fn read(log: &Log, offset: u64) -> Vec<(u64, u64)> {
+        log.read(offset).unwrap().collect()
+    }
+
+    #[test]
+    fn truncate_drops_a_prefix() {
+        let mut log = log(&[10, 11, 12, 13, 14]);
+        assert_eq!(log.truncate(2), 2);
+        assert_eq!(log.truncate(1), 0);
+        assert_eq!(log.read(1).err().map(|truncated| truncated.start), Some(2));
+        assert_eq!(read(&log, 3), [(3, 13), (4, 14)]);
+        // never past the end, the next append keeps its offset
+        log.truncate(10);
+        assert_eq!((log.start(), log.next_offset()), (5, 5));
+        assert_eq!(log.append(15), 5);
+    }
+
+    #[test]
+    fn compact_keeps_the_latest_of_every_message() {
+        let mut log = log(&[1, 2, 1, 3, 2]);
+        assert_eq!(log.compact(), 2);
+        assert_eq!(read(&log, 0), [(2, 1), (3, 3), (4, 2)]);
+        assert_eq!(read(&log, 3), [(3, 3), (4, 2)]);
+        assert_eq!(log.append(1), 5);
+    }
+
+    #[test]
+    fn recovered_log_continues_after_its_values() {
+        let log = Log::recovered(4, vec![7, 8]);
+        assert_eq!((log.start(), log.next_offset()), (4, 6));
+        assert_eq!(read(&log, 4), [(4, 7), (5, 8)]);
+    }
+
+    #[test]
+    fn retention_point_takes_the_furthest_rule() {
+        let log = log(&[0, 1, 2, 3, 4, 5]);
+        let now = Instant::now();
+        let mut config = KafkaConfig::default();
+        assert_eq!(log.retention_point(&config, Some(3), now), 0);
+
+        config.retention_messages = Some(4);
+        assert_eq!(log.retention_point(&config, Some(3), now), 2);
+        config.retention_committed = true;
+        assert_eq!(log.retention_point(&config, Some(3), now), 4);
+        assert_eq!(log.retention_point(&config, None, now), 2);
+        // the committed offset can be ahead of the log
+        assert_eq!(log.retention_point(&config, Some(10), now), 6);
+
+        config.retention_ms = Some(1000);
+        assert_eq!(log.retention_point(&config, None, now), 2);
+        let later = now + Duration::from_secs(2);
+        assert_eq!(log.retention_point(&config, None, later), 6);
+    }
+}
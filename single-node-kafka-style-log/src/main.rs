@@ -1,29 +1,24 @@
-use crate::Body::InitOk;
+use crate::log::Log;
+use crate::wal::Wal;
+use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
-use std::io::{BufRead, Write};
-use std::{collections::HashMap, io};
+use shared::config::{Config, FsyncPolicy, KafkaConfig, TruncatedPoll};
+use shared::error::{code, RpcError};
+use shared::poll::PollBudget;
+use shared::sim::simulate;
+use shared::{Message, Node, Runtime};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::time::Instant;
+use tracing::{info, trace, warn};
 
-// generic type for json received for all problems
-// use this as a template
-#[derive(Serialize, Deserialize, Debug)]
-struct Message {
-    src: String,
-    dest: String,
-    body: Body,
-}
+mod log;
+mod wal;
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
 enum Body {
-    Init {
-        msg_id: u64,
-        node_id: String,
-        node_ids: Vec<String>,
-    },
-    InitOk {
-        in_reply_to: u64,
-    },
     Send {
         msg_id: u64,
         key: String,
@@ -33,17 +28,25 @@ enum Body {
         offset: usize, // usize for now
         in_reply_to: u64,
     },
+    // `max_messages` and `max_bytes` limit the whole response, see `PollBudget`
     Poll {
         msg_id: u64,
         offsets: HashMap<String, usize>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max_messages: Option<usize>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max_bytes: Option<usize>,
     },
     PollOk {
         msgs: HashMap<String, Vec<Vec<u64>>>,
         in_reply_to: u64,
     },
+    // the maelstrom workload doesn't send a consumer group, those commits go to the default group
     CommitOffsets {
         msg_id: u64,
         offsets: HashMap<String, u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        group: Option<String>,
     },
     CommitOffsetsOk {
         in_reply_to: u64,
@@ -51,11 +54,31 @@ enum Body {
     ListCommittedOffsets {
         msg_id: u64,
         keys: Vec<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        group: Option<String>,
     },
     ListCommittedOffsetsOk {
         offsets: HashMap<String, u64>,
         in_reply_to: u64,
     },
+    // all consumer groups which committed anything (without the default group)
+    ListGroups {
+        msg_id: u64,
+    },
+    ListGroupsOk {
+        groups: Vec<String>,
+        in_reply_to: u64,
+    },
+    // unlike a commit, this can also move a group back to re-read messages
+    ResetOffsets {
+        msg_id: u64,
+        offsets: HashMap<String, u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        group: Option<String>,
+    },
+    ResetOffsetsOk {
+        in_reply_to: u64,
+    },
     Error {
         in_reply_to: u64,
         code: u64,
@@ -63,132 +86,373 @@ enum Body {
     },
 }
 
-fn main() -> anyhow::Result<()> {
-    let stdin = io::stdin();
+// data for this node
+#[derive(Default)]
+struct State {
+    data: HashMap<String, Log>,
+    // by consumer group (`None` is the default group) and key
+    commited_offsets: HashMap<Option<String>, HashMap<String, u64>>,
+    // every change is written here before it is acknowledged (if `data_dir` is set)
+    wal: Option<Wal>,
+}
+
+#[derive(Clone)]
+struct Handler {
+    state: Arc<Mutex<State>>,
+    config: KafkaConfig,
+}
+
+impl Handler {
+    fn new(config: KafkaConfig) -> Self {
+        Handler {
+            state: Arc::new(Mutex::new(State::default())),
+            config,
+        }
+    }
 
-    let print_and_flush = |output: Message| -> anyhow::Result<()> {
-        let serialized_output = serde_json::to_string(&output)?;
-        eprintln!("--------------------------------------------------");
-        eprintln!("{}", serialized_output);
-        println!("{}", serialized_output);
-        io::stdout().flush()?;
+    // drop what retention allows from every key and compact what is left
+    fn retain(&self) -> anyhow::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let State {
+            data,
+            commited_offsets,
+            wal,
+        } = &mut *state;
+        let now = Instant::now();
+        for (key, log) in data.iter_mut() {
+            // only what every consumer group has processed can go,
+            // a group that never committed this key hasn't processed any of it
+            let committed = commited_offsets
+                .values()
+                .map(|offsets| offsets.get(key).copied())
+                .collect::<Option<Vec<u64>>>()
+                .and_then(|committed| committed.into_iter().min());
+            let point = log.retention_point(&self.config, committed, now);
+            if point > log.start() {
+                if let Some(wal) = wal {
+                    wal.truncate(key, point)?;
+                }
+                log.truncate(point);
+            }
+            if self.config.compact {
+                log.compact();
+            }
+        }
         Ok(())
-    };
+    }
+}
 
-    // data for this node
-    let mut data: HashMap<String, Vec<u64>> = HashMap::new();
-    let mut commited_offsets: HashMap<String, u64> = HashMap::new();
+#[async_trait]
+impl Node for Handler {
+    type Body = Body;
 
-    for line in stdin.lock().lines() {
-        eprintln!("{:#?}", data);
-        let input: Message = serde_json::from_str(&line?)?;
-        eprintln!("{:#?}", input);
-        match input.body {
-            Body::Init { msg_id, .. } => {
-                let output: Message = Message {
-                    src: input.dest,
-                    dest: input.src,
-                    body: InitOk {
-                        in_reply_to: msg_id,
-                    },
+    // rebuild the log from disk before the first request is handled
+    async fn init(&self, runtime: Runtime) -> anyhow::Result<()> {
+        if self.config.retention_enabled() {
+            let handler = self.clone();
+            runtime.every(self.config.retention_check(), move || {
+                let result = handler.retain();
+                async move { result }
+            });
+        }
+
+        let Some(data_dir) = &self.config.data_dir else {
+            return Ok(());
+        };
+        let options = wal::Options {
+            fsync: self.config.fsync,
+            segment_bytes: self.config.segment_bytes,
+        };
+        let (wal, recovered) = Wal::open(&data_dir.join(runtime.node_id()), options)?;
+        info!(
+            keys = recovered.data.len(),
+            groups = recovered.committed_offsets.len(),
+            "recovered the log"
+        );
+        let mut state = self.state.lock().unwrap();
+        state.data = recovered
+            .data
+            .into_iter()
+            .map(|(key, (start, values))| (key, Log::recovered(start, values)))
+            .collect();
+        state.commited_offsets = recovered.committed_offsets;
+        state.wal = Some(wal);
+        drop(state);
+
+        if self.config.fsync == FsyncPolicy::Interval {
+            let state = Arc::clone(&self.state);
+            runtime.every(self.config.fsync_interval(), move || {
+                let result = match &mut state.lock().unwrap().wal {
+                    Some(wal) => wal.sync(),
+                    None => Ok(()),
                 };
-                print_and_flush(output)?;
-            }
+                async move { result }
+            });
+        }
+        Ok(())
+    }
+
+    async fn shutdown(&self, _runtime: Runtime) -> anyhow::Result<()> {
+        match &mut self.state.lock().unwrap().wal {
+            Some(wal) => wal.sync(),
+            None => Ok(()),
+        }
+    }
+
+    async fn process(&self, runtime: Runtime, input: Message<Body>) -> anyhow::Result<()> {
+        // the whole request is handled under the lock, so offsets stay dense
+        let mut state = self.state.lock().unwrap();
+        let State {
+            data,
+            commited_offsets,
+            wal,
+        } = &mut *state;
+        // the runtime logs the request itself at debug
+        trace!(?data, "log before the request");
+        match input.body {
             Body::Send { msg_id, key, msg } => {
-                let idx = if data.contains_key(&key) {
-                    let x = data.entry(key).or_insert(vec![msg]);
-                    x.push(msg);
-                    x.len()
-                } else {
-                    data.insert(key, vec![msg]);
-                    1
-                };
+                let log = data.entry(key.clone()).or_default();
+                if let Some(wal) = wal {
+                    wal.append(&key, log.next_offset(), msg)?;
+                }
+                let offset = log.append(msg);
 
-                let output: Message = Message {
-                    src: input.dest,
-                    dest: input.src,
-                    body: Body::SendOk {
-                        offset: idx - 1,
+                runtime.send(
+                    &input.src,
+                    Body::SendOk {
+                        offset: offset as usize,
                         in_reply_to: msg_id,
                     },
-                };
-                print_and_flush(output)?;
+                )?;
             }
-            Body::Poll { msg_id, offsets } => {
+            Body::Poll {
+                msg_id,
+                offsets,
+                max_messages,
+                max_bytes,
+            } => {
+                let mut budget = PollBudget::new(&self.config, max_messages, max_bytes);
                 let mut msgs: HashMap<std::string::String, Vec<Vec<u64>>> = HashMap::new();
                 for (key, offset) in offsets {
-                    if let Some(values) = data.get(&key) {
-                        // let response: Vec<Vec<u64>> = values
-                        //     .iter()
-                        //     .skip((offset - 1).try_into().unwrap())
-                        //     .enumerate()
-                        //     .map(|(id, val)| vec![id as u64 + 1, *val])
-                        //     .take(3)
-                        //     .collect();
-                        let response: Vec<Vec<u64>> = values
-                            .iter()
-                            .enumerate()
-                            .filter(|(id, _)| *id >= offset)
-                            .map(|(id, val)| vec![id as u64, (*val as u64).try_into().unwrap()])
-                            .take(10)
-                            .collect();
+                    if let Some(log) = data.get(&key) {
+                        let response = match log.read(offset as u64) {
+                            Ok(entries) => budget.fill(entries),
+                            Err(truncated) => match self.config.truncated_poll {
+                                TruncatedPoll::Advance => match log.read(truncated.start) {
+                                    Ok(entries) => budget.fill(entries),
+                                    Err(_) => Vec::new(),
+                                },
+                                TruncatedPoll::Error => {
+                                    let text = format!(
+                                        "offset {} of {} was truncated, the log starts at {}",
+                                        offset, key, truncated.start
+                                    );
+                                    return Err(RpcError::new(code::OFFSET_TRUNCATED, text).into());
+                                }
+                            },
+                        };
                         msgs.insert(key, response);
                     }
                 }
-                let output: Message = Message {
-                    src: input.dest,
-                    dest: input.src,
-                    body: Body::PollOk {
+                runtime.send(
+                    &input.src,
+                    Body::PollOk {
                         msgs,
                         in_reply_to: msg_id,
                     },
-                };
-                print_and_flush(output)?;
+                )?;
             }
-            Body::CommitOffsets { msg_id, offsets } => {
+            Body::CommitOffsets {
+                msg_id,
+                offsets,
+                group,
+            } => {
+                let committed = commited_offsets.entry(group.clone()).or_default();
                 for (key, offset) in offsets {
-                    commited_offsets
-                        .entry(key)
-                        .and_modify(|curr| *curr = (*curr).max(offset))
-                        .or_insert(offset);
+                    let curr = committed.entry(key.clone()).or_insert(offset);
+                    *curr = (*curr).max(offset);
+                    if let Some(wal) = wal {
+                        wal.commit(group.as_deref(), &key, *curr)?;
+                    }
                 }
-                let output: Message = Message {
-                    src: input.dest,
-                    dest: input.src,
-                    body: Body::CommitOffsetsOk {
+                runtime.send(
+                    &input.src,
+                    Body::CommitOffsetsOk {
                         in_reply_to: msg_id,
                     },
-                };
-                print_and_flush(output)?;
+                )?;
             }
-            Body::ListCommittedOffsets { msg_id, keys } => {
+            Body::ListCommittedOffsets {
+                msg_id,
+                keys,
+                group,
+            } => {
                 let mut response = HashMap::new();
-                for key in keys {
-                    if let Some(&val) = commited_offsets.get(&key) {
-                        response.insert(key, val);
+                if let Some(committed) = commited_offsets.get(&group) {
+                    for key in keys {
+                        if let Some(&val) = committed.get(&key) {
+                            response.insert(key, val);
+                        }
                     }
                 }
-                let output: Message = Message {
-                    src: input.dest,
-                    dest: input.src,
-                    body: Body::ListCommittedOffsetsOk {
+                runtime.send(
+                    &input.src,
+                    Body::ListCommittedOffsetsOk {
                         offsets: response,
                         in_reply_to: msg_id,
                     },
-                };
-                print_and_flush(output)?;
+                )?;
+            }
+            Body::ListGroups { msg_id } => {
+                let mut groups: Vec<String> = commited_offsets.keys().flatten().cloned().collect();
+                groups.sort();
+                runtime.send(
+                    &input.src,
+                    Body::ListGroupsOk {
+                        groups,
+                        in_reply_to: msg_id,
+                    },
+                )?;
+            }
+            Body::ResetOffsets {
+                msg_id,
+                offsets,
+                group,
+            } => {
+                let committed = commited_offsets.entry(group.clone()).or_default();
+                for (key, offset) in offsets {
+                    committed.insert(key.clone(), offset);
+                    if let Some(wal) = wal {
+                        wal.commit(group.as_deref(), &key, offset)?;
+                    }
+                }
+                runtime.send(
+                    &input.src,
+                    Body::ResetOffsetsOk {
+                        in_reply_to: msg_id,
+                    },
+                )?;
             }
-            InitOk { .. }
-            | Body::SendOk { .. }
+            Body::SendOk { .. }
             | Body::PollOk { .. }
             | Body::CommitOffsetsOk { .. }
-            | Body::ListCommittedOffsetsOk { .. } => {
-                eprintln!("Impossible input");
+            | Body::ListCommittedOffsetsOk { .. }
+            | Body::ListGroupsOk { .. }
+            | Body::ResetOffsetsOk { .. } => {
+                warn!("impossible input");
             }
             Body::Error { text, .. } => {
-                eprintln!("{}", text);
+                warn!("{}", text);
             }
         }
+        Ok(())
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let mut defaults = Config::default();
+    defaults.kafka.poll_limit = 10;
+    let config = Config::load(defaults)?;
+    shared::log::init(&config.log);
+    if config.sim.enabled() {
+        return simulate(config.sim, move |_| Handler::new(config.kafka.clone())).await;
+    }
+
+    let runtime = Runtime::new()
+        .with_trace(config.trace.dir.clone())
+        .with_metrics(config.metrics.clone());
+    runtime.run(Handler::new(config.kafka)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wal::tests::TempDir;
+
+    async fn request(handler: &Handler, body: Body) {
+        let msg = Message {
+            src: "c1".to_string(),
+            dest: "n0".to_string(),
+            body,
+        };
+        handler.process(Runtime::new(), msg).await.unwrap();
+    }
+
+    async fn commit(handler: &Handler, group: Option<&str>, key: &str, offset: u64) {
+        let body = Body::CommitOffsets {
+            msg_id: 1,
+            offsets: HashMap::from([(key.to_string(), offset)]),
+            group: group.map(str::to_string),
+        };
+        request(handler, body).await;
+    }
+
+    fn committed(handler: &Handler, group: Option<&str>, key: &str) -> Option<u64> {
+        let state = handler.state.lock().unwrap();
+        let offsets = state.commited_offsets.get(&group.map(str::to_string))?;
+        offsets.get(key).copied()
+    }
+
+    #[tokio::test]
+    async fn groups_commit_independently() {
+        let handler = Handler::new(KafkaConfig::default());
+        commit(&handler, None, "a", 5).await;
+        commit(&handler, None, "a", 3).await;
+        commit(&handler, Some("g"), "a", 1).await;
+        assert_eq!(committed(&handler, None, "a"), Some(5));
+        assert_eq!(committed(&handler, Some("g"), "a"), Some(1));
+        assert_eq!(committed(&handler, Some("h"), "a"), None);
+
+        // unlike a commit a reset may go backwards
+        let reset = Body::ResetOffsets {
+            msg_id: 2,
+            offsets: HashMap::from([("a".to_string(), 2)]),
+            group: None,
+        };
+        request(&handler, reset).await;
+        assert_eq!(committed(&handler, None, "a"), Some(2));
+    }
+
+    #[tokio::test]
+    async fn retention_keeps_what_a_group_still_needs() {
+        let config = KafkaConfig {
+            retention_committed: true,
+            ..KafkaConfig::default()
+        };
+        let handler = Handler::new(config);
+        for msg in 0..6 {
+            let body = Body::Send {
+                msg_id: msg,
+                key: "a".to_string(),
+                msg,
+            };
+            request(&handler, body).await;
+        }
+        commit(&handler, None, "a", 4).await;
+        commit(&handler, Some("g"), "a", 1).await;
+        handler.retain().unwrap();
+        assert_eq!(handler.state.lock().unwrap().data["a"].start(), 2);
+    }
+
+    #[tokio::test]
+    async fn committed_offsets_survive_a_restart() {
+        let dir = TempDir::new("groups");
+        let config = KafkaConfig {
+            data_dir: Some(dir.0.clone()),
+            fsync: FsyncPolicy::Never,
+            ..KafkaConfig::default()
+        };
+        let handler = Handler::new(config.clone());
+        handler.init(Runtime::new()).await.unwrap();
+        commit(&handler, None, "a", 5).await;
+        commit(&handler, Some("g/1"), "a", 2).await;
+        commit(&handler, Some("g/1"), "a", 1).await;
+        drop(handler);
+
+        let handler = Handler::new(config);
+        handler.init(Runtime::new()).await.unwrap();
+        assert_eq!(committed(&handler, None, "a"), Some(5));
+        assert_eq!(committed(&handler, Some("g/1"), "a"), Some(2));
     }
-    Ok(())
 }
@@ -0,0 +1,523 @@
+use anyhow::{anyhow, Context};
+use shared::config::FsyncPolicy;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+// append-only write-ahead log for the single-node kafka-style log
+//
+// layout below the data directory, one directory per key (hex encoded, keys can be anything):
+//
+//   <hex(key)>/00000000000000000000.log   segment starting at offset 0
+//   <hex(key)>/00000000000000001024.log   next segment, started once the previous one was full
+//   <hex(key)>/committed                  latest committed offset (replaced atomically)
+//   <hex(key)>/committed.<hex(group)>     the same for a consumer group
+//   <hex(key)>/start                      first offset kept by retention (replaced atomically)
+//
+// every record in a segment is 16 bytes: offset and message, both u64 little endian
+// the offset is redundant, but lets the recovery notice a torn or misplaced write
+//
+// retention deletes whole segments once all of their messages are below `start`,
+// compaction is not written back to the segments, it is applied again after recovery
+
+const RECORD_SIZE: u64 = 16;
+const COMMITTED_FILE: &str = "committed";
+const START_FILE: &str = "start";
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub fsync: FsyncPolicy,
+    pub segment_bytes: u64,
+}
+
+// what was on disk when the log was opened
+#[derive(Debug, Default)]
+pub struct Recovered {
+    // start offset and the messages from there on
+    pub data: HashMap<String, (u64, Vec<u64>)>,
+    // by consumer group (`None` is the default group) and key
+    pub committed_offsets: HashMap<Option<String>, HashMap<String, u64>>,
+}
+
+pub struct Wal {
+    dir: PathBuf,
+    options: Options,
+    logs: HashMap<String, KeyLog>,
+    // keys with records which were not synced yet
+    unsynced: HashSet<String>,
+}
+
+struct KeyLog {
+    dir: PathBuf,
+    // base offsets of all segments, the last one is `active`
+    segments: Vec<u64>,
+    // the segment new records are appended to
+    active: File,
+    active_bytes: u64,
+    next_offset: u64,
+}
+
+impl Wal {
+    // open (or create) the log in `dir` and read back everything that is in it
+    pub fn open(dir: &Path, options: Options) -> anyhow::Result<(Wal, Recovered)> {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create log directory {}", dir.display()))?;
+        let mut wal = Wal {
+            dir: dir.to_path_buf(),
+            options,
+            logs: HashMap::new(),
+            unsynced: HashSet::new(),
+        };
+        let mut recovered = Recovered::default();
+
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if !path.is_dir() {
+                continue;
+            }
+            let key = match path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .map(decode_key)
+            {
+                Some(Some(key)) => key,
+                _ => {
+                    warn!("skipping unknown directory {}", path.display());
+                    continue;
+                }
+            };
+            let (log, first, mut values) = KeyLog::recover(&path)?;
+            for (group, committed) in read_committed(&path)? {
+                recovered
+                    .committed_offsets
+                    .entry(group)
+                    .or_default()
+                    .insert(key.clone(), committed);
+            }
+            // the segment holding `start` may still contain a few older messages.
+            // without fsync on every append the start file can also be ahead of the
+            // records that made it to disk, then the log continues where they end
+            let end = first + values.len() as u64;
+            let start = read_u64(&path, START_FILE)?
+                .unwrap_or(first)
+                .clamp(first, end);
+            values.drain(..(start - first) as usize);
+            recovered.data.insert(key.clone(), (start, values));
+            if let Some(log) = log {
+                wal.logs.insert(key, log);
+            }
+        }
+        Ok((wal, recovered))
+    }
+
+    // `offset` has to be the next offset of `key`
+    pub fn append(&mut self, key: &str, offset: u64, msg: u64) -> anyhow::Result<()> {
+        if !self.logs.contains_key(key) {
+            let dir = self.dir.join(encode_key(key));
+            fs::create_dir_all(&dir)?;
+            let log = KeyLog::create(&dir, Vec::new(), offset)?;
+            if self.options.fsync == FsyncPolicy::Always {
+                sync_dir(&self.dir)?;
+            }
+            self.logs.insert(key.to_string(), log);
+        }
+        let log = self.logs.get_mut(key).unwrap();
+        if offset != log.next_offset {
+            return Err(anyhow!(
+                "append to {} at offset {}, expected {}",
+                key,
+                offset,
+                log.next_offset
+            ));
+        }
+
+        if log.active_bytes + RECORD_SIZE > self.options.segment_bytes.max(RECORD_SIZE) {
+            log.roll(&self.options)?;
+        }
+        let mut record = [0u8; RECORD_SIZE as usize];
+        record[..8].copy_from_slice(&offset.to_le_bytes());
+        record[8..].copy_from_slice(&msg.to_le_bytes());
+        log.active.write_all(&record)?;
+        log.active_bytes += RECORD_SIZE;
+        log.next_offset += 1;
+
+        match self.options.fsync {
+            FsyncPolicy::Always => log.active.sync_data()?,
+            FsyncPolicy::Interval | FsyncPolicy::Never => {
+                self.unsynced.insert(key.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn commit(&mut self, group: Option<&str>, key: &str, offset: u64) -> anyhow::Result<()> {
+        let dir = self.dir.join(encode_key(key));
+        fs::create_dir_all(&dir)?;
+        let name = match group {
+            Some(group) => format!("{}.{}", COMMITTED_FILE, encode_key(group)),
+            None => COMMITTED_FILE.to_string(),
+        };
+        write_u64(&dir, &name, offset, self.options.fsync)
+    }
+
+    // forget everything below `start`, segments without any message from `start` on are deleted
+    pub fn truncate(&mut self, key: &str, start: u64) -> anyhow::Result<()> {
+        let dir = self.dir.join(encode_key(key));
+        fs::create_dir_all(&dir)?;
+        // record the new start first, a crash while deleting must not bring old messages back
+        write_u64(&dir, START_FILE, start, self.options.fsync)?;
+
+        let Some(log) = self.logs.get_mut(key) else {
+            return Ok(());
+        };
+        // a segment only holds messages below the base of the next one,
+        // the active segment is never deleted
+        let deletable = log
+            .segments
+            .windows(2)
+            .take_while(|pair| pair[1] <= start)
+            .count();
+        for base in log.segments.drain(..deletable) {
+            fs::remove_file(segment_path(&log.dir, base))?;
+        }
+        if deletable > 0 && self.options.fsync == FsyncPolicy::Always {
+            sync_dir(&log.dir)?;
+        }
+        Ok(())
+    }
+
+    // force everything appended so far to disk
+    pub fn sync(&mut self) -> anyhow::Result<()> {
+        for key in self.unsynced.drain() {
+            if let Some(log) = self.logs.get(&key) {
+                log.active.sync_data()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl KeyLog {
+    fn create(dir: &Path, mut segments: Vec<u64>, base_offset: u64) -> anyhow::Result<KeyLog> {
+        let active = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(segment_path(dir, base_offset))?;
+        segments.push(base_offset);
+        Ok(KeyLog {
+            dir: dir.to_path_buf(),
+            segments,
+            active,
+            active_bytes: 0,
+            next_offset: base_offset,
+        })
+    }
+
+    // start a new segment at the next offset
+    fn roll(&mut self, options: &Options) -> anyhow::Result<()> {
+        if options.fsync != FsyncPolicy::Never {
+            // the old segment is never written again
+            self.active.sync_data()?;
+        }
+        let segments = std::mem::take(&mut self.segments);
+        let next = KeyLog::create(&self.dir, segments, self.next_offset)?;
+        if options.fsync == FsyncPolicy::Always {
+            sync_dir(&self.dir)?;
+        }
+        *self = next;
+        Ok(())
+    }
+
+    // read all segments of a key, a torn record at the end of the last segment
+    // (crash in the middle of an append) is cut off
+    // returns the log, the offset of the first message read and the messages
+    fn recover(dir: &Path) -> anyhow::Result<(Option<KeyLog>, u64, Vec<u64>)> {
+        let mut segments = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let base = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_suffix(".log"))
+                .and_then(|base| base.parse::<u64>().ok());
+            if let Some(base) = base {
+                segments.push((base, path));
+            }
+        }
+        segments.sort();
+
+        // older segments may have been deleted by retention
+        let first = segments.first().map_or(0, |(base, _)| *base);
+        let mut values = Vec::new();
+        let mut active = None;
+        for (index, (base, path)) in segments.iter().enumerate() {
+            let last = index + 1 == segments.len();
+            let expected = first + values.len() as u64;
+            if *base != expected {
+                return Err(anyhow!(
+                    "segment {} starts at {}, expected {}",
+                    path.display(),
+                    base,
+                    expected
+                ));
+            }
+            let bytes = fs::read(path)?;
+            let mut valid = 0;
+            for record in bytes.chunks(RECORD_SIZE as usize) {
+                if record.len() < RECORD_SIZE as usize {
+                    break;
+                }
+                let offset = u64::from_le_bytes(record[..8].try_into().unwrap());
+                if offset != first + values.len() as u64 {
+                    break;
+                }
+                values.push(u64::from_le_bytes(record[8..].try_into().unwrap()));
+                valid += RECORD_SIZE;
+            }
+
+            if valid != bytes.len() as u64 {
+                if !last {
+                    return Err(anyhow!("segment {} is corrupted", path.display()));
+                }
+                warn!(
+                    "truncating {} from {} to {} bytes",
+                    path.display(),
+                    bytes.len(),
+                    valid
+                );
+                OpenOptions::new().write(true).open(path)?.set_len(valid)?;
+            }
+            if last {
+                let file = OpenOptions::new().append(true).open(path)?;
+                active = Some(KeyLog {
+                    dir: dir.to_path_buf(),
+                    segments: segments.iter().map(|(base, _)| *base).collect(),
+                    active: file,
+                    active_bytes: valid,
+                    next_offset: first + values.len() as u64,
+                });
+            }
+        }
+        Ok((active, first, values))
+    }
+}
+
+fn segment_path(dir: &Path, base_offset: u64) -> PathBuf {
+    dir.join(format!("{:020}.log", base_offset))
+}
+
+// committed offsets of all consumer groups of a key
+fn read_committed(dir: &Path) -> anyhow::Result<Vec<(Option<String>, u64)>> {
+    let mut committed = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+        let group = match name.strip_prefix(COMMITTED_FILE) {
+            Some("") => None,
+            Some(suffix) => match suffix.strip_prefix('.').and_then(decode_key) {
+                Some(group) => Some(group),
+                // `committed.tmp` left behind by a crash
+                None => continue,
+            },
+            None => continue,
+        };
+        if let Some(offset) = read_u64(dir, name)? {
+            committed.push((group, offset));
+        }
+    }
+    Ok(committed)
+}
+
+fn read_u64(dir: &Path, name: &str) -> anyhow::Result<Option<u64>> {
+    let bytes = match fs::read(dir.join(name)) {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let bytes: [u8; 8] = bytes
+        .try_into()
+        .map_err(|_| anyhow!("bad {} offset in {}", name, dir.display()))?;
+    Ok(Some(u64::from_le_bytes(bytes)))
+}
+
+// write the new value next to the old one and swap them,
+// so a crash leaves either the old or the new value behind
+fn write_u64(dir: &Path, name: &str, value: u64, fsync: FsyncPolicy) -> anyhow::Result<()> {
+    let tmp = dir.join(format!("{}.tmp", name));
+    let mut file = File::create(&tmp)?;
+    file.write_all(&value.to_le_bytes())?;
+    if fsync == FsyncPolicy::Always {
+        file.sync_data()?;
+    }
+    fs::rename(&tmp, dir.join(name))?;
+    if fsync == FsyncPolicy::Always {
+        sync_dir(dir)?;
+    }
+    Ok(())
+}
+
+// makes created / renamed / deleted files in `dir` durable
+fn sync_dir(dir: &Path) -> anyhow::Result<()> {
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+fn encode_key(key: &str) -> String {
+    key.bytes().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// `None` for anything `encode_key` didn't write, other files may end up in the directory
+fn decode_key(name: &str) -> Option<String> {
+    if !name.len().is_multiple_of(2) {
+        return None;
+    }
+    // pairs of bytes, a pair of a multi-byte character isn't valid hex
+    let bytes = name
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair).ok()?;
+            u8::from_str_radix(pair, 16).ok()
+        })
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // removed again when the test is done
+    pub(crate) struct TempDir(pub(crate) PathBuf);
+
+    impl TempDir {
+        pub(crate) fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("wal-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            TempDir(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    // three records per segment
+    fn open(dir: &TempDir) -> (Wal, Recovered) {
+        let options = Options {
+            fsync: FsyncPolicy::Never,
+            segment_bytes: 3 * RECORD_SIZE,
+        };
+        Wal::open(&dir.0, options).unwrap()
+    }
+
+    fn append(wal: &mut Wal, key: &str, offsets: std::ops::Range<u64>) {
+        for offset in offsets {
+            wal.append(key, offset, offset * 10).unwrap();
+        }
+    }
+
+    fn segments(dir: &TempDir, key: &str) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir.0.join(encode_key(key)))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.ends_with(".log"))
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn recovers_appends_across_segments() {
+        let dir = TempDir::new("recover");
+        let (mut wal, recovered) = open(&dir);
+        assert!(recovered.data.is_empty());
+        append(&mut wal, "a/b", 0..7);
+        append(&mut wal, "c", 0..1);
+        assert!(wal.append("c", 5, 0).is_err());
+        drop(wal);
+        assert_eq!(segments(&dir, "a/b").len(), 3);
+
+        let (mut wal, recovered) = open(&dir);
+        let values: Vec<u64> = (0..7).map(|offset| offset * 10).collect();
+        assert_eq!(recovered.data["a/b"], (0, values));
+        assert_eq!(recovered.data["c"], (0, vec![0]));
+        // and the log goes on where it stopped
+        append(&mut wal, "a/b", 7..8);
+        drop(wal);
+        assert_eq!(open(&dir).1.data["a/b"].1.len(), 8);
+    }
+
+    #[test]
+    fn cuts_off_a_torn_last_record() {
+        let dir = TempDir::new("torn");
+        let (mut wal, _) = open(&dir);
+        append(&mut wal, "a", 0..5);
+        drop(wal);
+        let last = dir
+            .0
+            .join(encode_key("a"))
+            .join(segment_path(Path::new(""), 3));
+        let mut file = OpenOptions::new().append(true).open(&last).unwrap();
+        file.write_all(&[1, 2, 3, 4, 5]).unwrap();
+        drop(file);
+
+        let (mut wal, recovered) = open(&dir);
+        assert_eq!(recovered.data["a"], (0, vec![0, 10, 20, 30, 40]));
+        assert_eq!(fs::metadata(&last).unwrap().len(), 2 * RECORD_SIZE);
+        append(&mut wal, "a", 5..6);
+        drop(wal);
+        assert_eq!(open(&dir).1.data["a"].1.len(), 6);
+    }
+
+    #[test]
+    fn truncate_deletes_whole_segments_only() {
+        let dir = TempDir::new("truncate");
+        let (mut wal, _) = open(&dir);
+        append(&mut wal, "a", 0..7);
+        wal.truncate("a", 4).unwrap();
+        // segment 3 still holds offsets 4 and 5, the active one is never deleted
+        let kept = [
+            segment_path(Path::new(""), 3),
+            segment_path(Path::new(""), 6),
+        ];
+        let kept: Vec<String> = kept.iter().map(|path| path.display().to_string()).collect();
+        assert_eq!(segments(&dir, "a"), kept);
+        drop(wal);
+
+        let (_, recovered) = open(&dir);
+        assert_eq!(recovered.data["a"], (4, vec![40, 50, 60]));
+    }
+
+    #[test]
+    fn start_is_clamped_to_the_records_on_disk() {
+        let dir = TempDir::new("clamp");
+        let (mut wal, _) = open(&dir);
+        append(&mut wal, "a", 0..3);
+        // the start made it to disk, the records after the first three didn't
+        wal.truncate("a", 10).unwrap();
+        drop(wal);
+
+        let (mut wal, recovered) = open(&dir);
+        assert_eq!(recovered.data["a"], (3, vec![]));
+        append(&mut wal, "a", 3..4);
+    }
+
+    #[test]
+    fn skips_directories_it_did_not_write() {
+        let dir = TempDir::new("foreign");
+        for name in ["lost+found", "0é0", "0", "ff"] {
+            fs::create_dir_all(dir.0.join(name)).unwrap();
+        }
+        let (_, recovered) = open(&dir);
+        assert!(recovered.data.is_empty());
+        assert_eq!(decode_key(&encode_key("é/ß")).as_deref(), Some("é/ß"));
+        assert_eq!(decode_key("0é0"), None);
+    }
+}
@@ -0,0 +1,147 @@
+use anyhow::{anyhow, Context};
+use serde_json::Value;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, ExitCode, Stdio};
+use std::time::Duration;
+
+// feeds a trace recorded with `--trace-dir` back into a node, to reproduce offline
+// what it did during a maelstrom run
+//
+// usage: trace-replay [--fast] [--grace-ms <ms>] <trace> <binary> [args...]
+//
+// the binary is started with `args` and gets every `in` message of the trace, with
+// the recorded gaps between them (right away with --fast). afterwards it has
+// `--grace-ms` (1000 by default) to finish before its input is closed. what it wrote
+// is compared with the recorded `out` messages, in any order: `+` lines are messages
+// the recording doesn't have, `-` lines recorded messages the replay didn't write.
+// replies to the node's own rpcs only match if it numbers its messages the same way,
+// so the first difference is usually close to the bug (or the nondeterminism)
+
+const USAGE: &str = "usage: trace-replay [--fast] [--grace-ms <ms>] <trace> <binary> [args...]";
+
+struct Record {
+    // unix milliseconds
+    time: f64,
+    inbound: bool,
+    msg: Value,
+}
+
+fn parse(contents: &str) -> anyhow::Result<Vec<Record>> {
+    let mut records = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: Value = serde_json::from_str(line)
+            .with_context(|| format!("line {} is not json", number + 1))?;
+        let inbound = match record["dir"].as_str() {
+            Some("in") => true,
+            Some("out") => false,
+            _ => return Err(anyhow!("line {} has no direction", number + 1)),
+        };
+        records.push(Record {
+            time: record["time"].as_f64().unwrap_or_default(),
+            inbound,
+            msg: record["msg"].clone(),
+        });
+    }
+    Ok(records)
+}
+
+fn main() -> anyhow::Result<ExitCode> {
+    let mut fast = false;
+    let mut grace = Duration::from_millis(1000);
+    let mut args = std::env::args().skip(1);
+    let trace = loop {
+        let arg = args.next().ok_or_else(|| anyhow!(USAGE))?;
+        match arg.as_str() {
+            "--fast" => fast = true,
+            "--grace-ms" => {
+                let ms = args.next().ok_or_else(|| anyhow!(USAGE))?;
+                grace = Duration::from_millis(ms.parse().context("--grace-ms")?);
+            }
+            _ if !arg.starts_with("--") => break arg,
+            _ => return Err(anyhow!(USAGE)),
+        }
+    };
+    let binary = args.next().ok_or_else(|| anyhow!(USAGE))?;
+    let contents =
+        std::fs::read_to_string(&trace).with_context(|| format!("failed to read {}", trace))?;
+    let records = parse(&contents)?;
+
+    let mut child = Command::new(&binary)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("failed to start {}", binary))?;
+    let mut stdin = child.stdin.take().ok_or_else(|| anyhow!("no stdin"))?;
+    let stdout = child.stdout.take().ok_or_else(|| anyhow!("no stdout"))?;
+    let reader = std::thread::spawn(move || {
+        BufReader::new(stdout)
+            .lines()
+            .map_while(Result::ok)
+            .collect::<Vec<String>>()
+    });
+
+    let mut previous: Option<f64> = None;
+    let mut fed = 0;
+    for record in records.iter().filter(|record| record.inbound) {
+        if let (false, Some(previous)) = (fast, previous) {
+            let gap = (record.time - previous).max(0.0);
+            std::thread::sleep(Duration::from_secs_f64(gap / 1000.0));
+        }
+        previous = Some(record.time);
+        // the node may have stopped already, the comparison below shows that
+        if writeln!(stdin, "{}", record.msg).is_err() {
+            break;
+        }
+        fed += 1;
+    }
+    std::thread::sleep(grace);
+    drop(stdin);
+    let status = child.wait()?;
+    let written = reader
+        .join()
+        .map_err(|_| anyhow!("reading the output failed"))?;
+
+    let mut recorded: Vec<Option<&Value>> = records
+        .iter()
+        .filter(|record| !record.inbound)
+        .map(|record| Some(&record.msg))
+        .collect();
+    let total = recorded.len();
+    let mut unexpected = 0;
+    for line in &written {
+        let msg: Value = serde_json::from_str(line).unwrap_or(Value::String(line.clone()));
+        match recorded
+            .iter_mut()
+            .find(|other| other.is_some_and(|other| *other == msg))
+        {
+            Some(slot) => *slot = None,
+            None => {
+                unexpected += 1;
+                println!("+ {}", line);
+            }
+        }
+    }
+    let missing: Vec<&Value> = recorded.into_iter().flatten().collect();
+    for msg in &missing {
+        println!("- {}", msg);
+    }
+
+    println!(
+        "fed {} messages, {} exited with {}: {} of {} recorded messages reproduced, {} unexpected",
+        fed,
+        binary,
+        status,
+        total - missing.len(),
+        total,
+        unexpected
+    );
+    Ok(if missing.is_empty() && unexpected == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
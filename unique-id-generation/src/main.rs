@@ -1,31 +1,16 @@
-use crate::Body::{GenerateOk, InitOk};
+use crate::Body::GenerateOk;
+use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
-use std::io;
-use std::io::{BufRead, Write};
+use shared::config::Config;
+use shared::log;
+use shared::sim::simulate;
+use shared::{Message, Node, Runtime};
+use tracing::warn;
 use uuid::Uuid;
 
-// generic type for json received for all problems
-// use this as a template
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Message {
-    src: String,
-    dest: String,
-    body: Body,
-}
-
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "type")]
 pub enum Body {
-    // this type = "init" is received at the start
-    #[serde(rename = "init")]
-    Init {
-        msg_id: u64,
-        node_id: String,
-        node_ids: Vec<String>,
-    },
-    // response for the type = "init"
-    #[serde(rename = "init_ok")]
-    InitOk { in_reply_to: u64 },
     // type = "generate" for this problem
     #[serde(rename = "generate")]
     Generate {
@@ -48,55 +33,46 @@ pub enum Body {
     },
 }
 
-fn main() -> anyhow::Result<()> {
-    let stdin = io::stdin();
-    let mut stdout = io::stdout();
+// the init message is answered by the runtime
+struct Handler;
 
-    let mut print_and_flush = |output: Message| -> anyhow::Result<()> {
-        let serialized_output = serde_json::to_string(&output)?;
-        writeln!(stdout, "{}", serialized_output)?;
-        stdout.flush()?;
-        Ok(())
-    };
+#[async_trait]
+impl Node for Handler {
+    type Body = Body;
 
-    for line in stdin.lock().lines() {
-        let input: Message = serde_json::from_str(&line?)?;
+    async fn process(&self, runtime: Runtime, input: Message<Body>) -> anyhow::Result<()> {
         match input.body {
-            Body::Init { msg_id, .. } => {
-                let output: Message = Message {
-                    src: input.dest,
-                    dest: input.src,
-                    body: InitOk {
-                        in_reply_to: msg_id,
-                    },
-                };
-                print_and_flush(output)?;
-            }
             Body::Generate { msg_id, .. } => {
-                let output: Message = Message {
-                    src: input.dest,
-                    dest: input.src,
-                    body: GenerateOk {
+                runtime.send(
+                    &input.src,
+                    GenerateOk {
                         msg_id,
                         in_reply_to: msg_id,
                         id: Uuid::new_v4().to_string(),
                     },
-                };
-                print_and_flush(output)?;
-            }
-            InitOk { .. } => {
-                eprintln!("{}", "Impossible input");
+                )?;
             }
             GenerateOk { .. } => {
-                eprintln!("{}", "Impossible input");
+                warn!("impossible input");
             }
             Body::Error { text, .. } => {
-                eprintln!("{}", text);
+                warn!("{}", text);
             }
         }
-        // let serialized_input = serde_json::to_string(&input)?;
-        // writeln!(stdout, "{}", serialized_input)?;
-        // stdout.flush()?;
+        Ok(())
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let config = Config::load(Config::default())?;
+    log::init(&config.log);
+    if config.sim.enabled() {
+        return simulate(config.sim, |_| Handler).await;
     }
-    Ok(())
+    Runtime::new()
+        .with_trace(config.trace.dir)
+        .with_metrics(config.metrics)
+        .run(Handler)
+        .await
 }